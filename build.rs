@@ -0,0 +1,13 @@
+//! Generate `include/magic.h` from the C interface in `src/capi.rs`.
+
+use std::env;
+
+fn main() {
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!("cargo:rerun-if-changed=src/capi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).unwrap();
+    cbindgen::generate_with_config(&crate_dir, config)
+        .expect("cannot generate include/magic.h")
+        .write_to_file(format!("{}/include/magic.h", crate_dir));
+}
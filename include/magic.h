@@ -0,0 +1,119 @@
+/*
+ * The C interface of file-type, compatible with the one of libmagic.
+ *
+ * The strings returned by magic_file(), magic_buffer(), magic_descriptor() and
+ * magic_error() are owned by the cookie, and valid until its next call.
+ *
+ * Generated by cbindgen from src/capi.rs and cbindgen.toml, do not edit.
+ */
+
+#ifndef _MAGIC_H
+#define _MAGIC_H
+
+#include <sys/types.h>
+
+#define	MAGIC_NONE		0x0000000 /* No flags */
+#define	MAGIC_DEBUG		0x0000001 /* Turn on debugging */
+#define	MAGIC_SYMLINK		0x0000002 /* Follow symlinks */
+#define	MAGIC_COMPRESS		0x0000004 /* Check inside compressed files */
+#define	MAGIC_DEVICES		0x0000008 /* Look at the contents of devices */
+#define	MAGIC_MIME_TYPE		0x0000010 /* Return the MIME type */
+#define	MAGIC_CONTINUE		0x0000020 /* Return all matches */
+#define	MAGIC_CHECK		0x0000040 /* Print warnings to stderr */
+#define	MAGIC_PRESERVE_ATIME	0x0000080 /* Restore access time on exit */
+#define	MAGIC_RAW		0x0000100 /* Don't convert unprintable chars */
+#define	MAGIC_ERROR		0x0000200 /* Handle ENOENT etc as real errors */
+#define	MAGIC_MIME_ENCODING	0x0000400 /* Return the MIME encoding */
+#define MAGIC_MIME		(MAGIC_MIME_TYPE|MAGIC_MIME_ENCODING)
+#define	MAGIC_APPLE		0x0000800 /* Return the Apple creator/type */
+#define	MAGIC_EXTENSION		0x1000000 /* Return a /-separated list of extensions */
+#define MAGIC_COMPRESS_TRANSP	0x2000000 /* Check inside compressed files but not report compression */
+#define MAGIC_NO_COMPRESS_FORK	0x4000000 /* Don't allow decompression that needs to fork */
+#define MAGIC_NODESC		(MAGIC_EXTENSION|MAGIC_MIME|MAGIC_APPLE)
+
+#define	MAGIC_NO_CHECK_COMPRESS	0x0001000 /* Don't check for compressed files */
+#define	MAGIC_NO_CHECK_TAR	0x0002000 /* Don't check for tar files */
+#define	MAGIC_NO_CHECK_SOFT	0x0004000 /* Don't check magic entries */
+#define	MAGIC_NO_CHECK_APPTYPE	0x0008000 /* Don't check application type */
+#define	MAGIC_NO_CHECK_ELF	0x0010000 /* Don't check for elf details */
+#define	MAGIC_NO_CHECK_TEXT	0x0020000 /* Don't check for text files */
+#define	MAGIC_NO_CHECK_CDF	0x0040000 /* Don't check for cdf files */
+#define	MAGIC_NO_CHECK_CSV	0x0080000 /* Don't check for CSV files */
+#define	MAGIC_NO_CHECK_TOKENS	0x0100000 /* Don't check tokens */
+#define	MAGIC_NO_CHECK_ENCODING	0x0200000 /* Don't check text encodings */
+#define	MAGIC_NO_CHECK_JSON	0x0400000 /* Don't check for JSON files */
+#define	MAGIC_NO_CHECK_SIMH	0x0800000 /* Don't check for SIMH tape files */
+
+#define MAGIC_NO_CHECK_BUILTIN	( \
+	MAGIC_NO_CHECK_COMPRESS	| \
+	MAGIC_NO_CHECK_TAR	| \
+/*	MAGIC_NO_CHECK_SOFT	| */ \
+	MAGIC_NO_CHECK_APPTYPE	| \
+	MAGIC_NO_CHECK_ELF	| \
+	MAGIC_NO_CHECK_TEXT	| \
+	MAGIC_NO_CHECK_CSV	| \
+	MAGIC_NO_CHECK_CDF	| \
+	MAGIC_NO_CHECK_TOKENS	| \
+	MAGIC_NO_CHECK_ENCODING	| \
+	MAGIC_NO_CHECK_JSON	| \
+	MAGIC_NO_CHECK_SIMH	| \
+	0			  \
+)
+
+/* Defined for backwards compatibility (renamed) */
+#define	MAGIC_NO_CHECK_ASCII	MAGIC_NO_CHECK_TEXT
+
+#define MAGIC_PARAM_INDIR_MAX		0
+#define MAGIC_PARAM_NAME_MAX		1
+#define MAGIC_PARAM_ELF_PHNUM_MAX	2
+#define MAGIC_PARAM_ELF_SHNUM_MAX	3
+#define MAGIC_PARAM_ELF_NOTES_MAX	4
+#define MAGIC_PARAM_REGEX_MAX		5
+#define	MAGIC_PARAM_BYTES_MAX		6
+#define	MAGIC_PARAM_ENCODING_MAX	7
+
+typedef struct magic_set *magic_t;
+
+#define MAGIC_VERSION 545
+
+struct magic_set;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+struct magic_set *magic_open(int flags);
+
+void magic_close(struct magic_set *ms);
+
+int magic_load(struct magic_set *ms, const char *filename);
+
+int magic_compile(struct magic_set *ms, const char *_filename);
+
+int magic_check(struct magic_set *ms, const char *filename);
+
+const char *magic_file(struct magic_set *ms, const char *filename);
+
+const char *magic_buffer(struct magic_set *ms, const void *buffer, size_t length);
+
+const char *magic_descriptor(struct magic_set *ms, int fd);
+
+const char *magic_error(const struct magic_set *ms);
+
+int magic_errno(const struct magic_set *ms);
+
+int magic_getflags(const struct magic_set *ms);
+
+int magic_setflags(struct magic_set *ms, int flags);
+
+int magic_setparam(struct magic_set *ms, int param, const void *value);
+
+int magic_getparam(struct magic_set *ms, int param, void *value);
+
+int magic_version(void);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* _MAGIC_H */
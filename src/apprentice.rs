@@ -0,0 +1,478 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs::File;
+use std::env;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use regex::bytes::{Regex, RegexBuilder};
+
+use crate::encoding;
+use crate::magic_set;
+use crate::magic::{CmpType, MagicFlags, RelnOp, RelnVal, StrModifier};
+use crate::parse_magic_entry::{MagicEntry, MagicFile};
+use crate::parse_magic_line::{Mask, MagicLine};
+
+
+/// The unit of the strength of an entry
+const MULT: i64 = 10;
+/// The magic number of the files compiled by `file -C`, in the host byte order
+const MAGIC_MGC: u32 = 0xF11E041C;
+/// The magic files loaded if no one is given
+const DEFAULT_MAGIC: &str = "/usr/share/file/magic:/usr/share/misc/magic";
+
+
+/// Split a colon separated list of magic files.
+pub(crate) fn split_paths(list: &str) -> Vec<PathBuf> {
+    list.split(':').filter(|path| !path.is_empty()).map(PathBuf::from).collect()
+}
+
+/// The magic files given by `$MAGIC`, or the default ones.
+pub(crate) fn default_paths() -> Vec<PathBuf> {
+    match env::var("MAGIC") {
+        Ok(list) => split_paths(&list),
+        Err(_) => split_paths(DEFAULT_MAGIC),
+    }
+}
+
+
+/// The entries loaded from the magic files, ready for matching.
+#[derive(Debug, Default)]
+pub(crate) struct MagicList {
+    /// All the entries, sorted by their strength
+    entries: Vec<MagicEntry>,
+    /// The strength of each entry
+    strengths: Vec<i64>,
+    /// If each entry is a binary or a text test
+    test_types: Vec<MagicFlags>,
+    /// The indices of the entries tried for every buffer, that is all but the named ones
+    top: Vec<usize>,
+    /// The index of the entry of each `name`
+    names: HashMap<Vec<u8>, usize>,
+    /// The compiled `regex` values, by the index of the entry and the line
+    regexes: HashMap<(usize, usize), Regex>,
+    /// The warnings of the magic files, which are printed with `Flags::CHECK` as
+    /// `file_magwarn` of libmagic
+    warnings: Vec<String>,
+}
+
+impl MagicList {
+    /// Load the magic files at the paths, each of which is a file or a directory of files.
+    ///
+    /// As libmagic, the paths which fail to load are skipped, and it is an error only if
+    /// nothing is loaded, with the error number of the last path which fails.
+    pub(crate) fn load<P: AsRef<Path>>(paths: &[P]) -> Result<MagicList, String> {
+        let mut entries = vec![];
+        let mut warnings = vec![];
+        let mut last_error = None;
+        let mut loaded = false;
+        for path in paths {
+            let path = path.as_ref();
+            let files = match MagicFile::list(path) {
+                Ok(files) => files,
+                Err(e) => {
+                    if e.kind() != io::ErrorKind::NotFound {
+                        warnings.push(format!("{}: Warning: {}", path.display(), e));
+                    }
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            for file in files {
+                match Self::is_compiled(&file) {
+                    Ok(false) => {}
+                    Ok(true) => {
+                        warnings.push(format!("{}: Warning: compiled magic files are not supported", file.display()));
+                        continue;
+                    }
+                    Err(e) => {
+                        if e.kind() != io::ErrorKind::NotFound {
+                            warnings.push(format!("{}: Warning: {}", file.display(), e));
+                        }
+                        last_error = Some(e);
+                        continue;
+                    }
+                }
+                match MagicFile::parse(&file) {
+                    Ok(magic_file) => {
+                        warnings.extend_from_slice(magic_file.warnings());
+                        entries.extend(magic_file.into_entries());
+                        loaded = true;
+                    }
+                    Err(e) => {
+                        warnings.push(format!("{}: Warning: {}", file.display(), e));
+                        last_error = Some(e);
+                    }
+                }
+            }
+        }
+        if !loaded {
+            if let Some(e) = &last_error {
+                magic_set::set_errno(e);
+            }
+            return Err("could not find any valid magic files!".to_string());
+        }
+        let mut mlist = Self::from_entries(entries);
+        warnings.append(&mut mlist.warnings);
+        mlist.warnings = warnings;
+        Ok(mlist)
+    }
+
+    fn is_compiled(path: &Path) -> io::Result<bool> {
+        let mut buf = [0; 4];
+        let mut file = File::open(path)?;
+        if file.read(&mut buf)? < 4 {
+            return Ok(false);
+        }
+        Ok(u32::from_le_bytes(buf) == MAGIC_MGC || u32::from_be_bytes(buf) == MAGIC_MGC)
+    }
+
+    /// Sort the entries by strength, and index the names and the regexes.
+    pub(crate) fn from_entries(entries: Vec<MagicEntry>) -> MagicList {
+        // the sort is stable, so that the entries of the same strength stay in the order
+        // they are loaded
+        let mut entries: Vec<(i64, MagicEntry)> = entries.into_iter()
+            .map(|entry| (strength(&entry), entry))
+            .collect();
+        entries.sort_by_key(|(strength, _)| Reverse(*strength));
+        let (strengths, entries): (Vec<i64>, Vec<MagicEntry>) = entries.into_iter().unzip();
+
+        let mut mlist = MagicList::default();
+        for (i, entry) in entries.iter().enumerate() {
+            let first = &entry.lines()[0];
+            match (first.cmp_type(), first.reln_val()) {
+                (CmpType::Name, RelnVal::Str(name)) => {
+                    mlist.names.entry(name.clone()).or_insert(i);
+                }
+                _ => mlist.top.push(i),
+            }
+
+            for (j, line) in entry.lines().iter().enumerate() {
+                if *line.cmp_type() != CmpType::Regex {
+                    continue;
+                }
+                match compile_regex(line) {
+                    Ok(re) => {
+                        mlist.regexes.insert((i, j), re);
+                    }
+                    Err(e) => mlist.warnings.push(format!("{}: Warning: {}", line.loc(), e)),
+                }
+            }
+        }
+        mlist.test_types = entries.iter().map(test_type).collect();
+        mlist.entries = entries;
+        mlist.strengths = strengths;
+        mlist
+    }
+
+    /// Load the entries of a magic file given as a string, for the tests.
+    #[cfg(test)]
+    pub(crate) fn load_str(text: &str) -> MagicList {
+        Self::from_entries(MagicFile::from_bytes(Path::new("test.magic"), text.as_bytes()).into_entries())
+    }
+
+    pub(crate) fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    pub(crate) fn entries(&self) -> &[MagicEntry] {
+        &self.entries
+    }
+
+    pub(crate) fn strength(&self, entry: usize) -> i64 {
+        self.strengths[entry]
+    }
+
+    /// Return `MagicFlags::BINTEST` and/or `MagicFlags::TEXTTEST` for the kind of buffers
+    /// an entry is tried on.
+    pub(crate) fn test_type(&self, entry: usize) -> MagicFlags {
+        self.test_types[entry]
+    }
+
+    /// The indices of the entries to try for a buffer, in the order of strength.
+    pub(crate) fn top(&self) -> &[usize] {
+        &self.top
+    }
+
+    /// Return the index of the entry defined by `name`.
+    pub(crate) fn find_name(&self, name: &[u8]) -> Option<usize> {
+        self.names.get(name).copied()
+    }
+
+    /// Return the compiled regex of a line, or `None` if it fails to compile.
+    pub(crate) fn regex(&self, entry: usize, line: usize) -> Option<&Regex> {
+        self.regexes.get(&(entry, line))
+    }
+}
+
+
+/// Compute the strength of an entry by its first line, which decides the order of trying
+/// the entries, as `apprentice_magic_strength` of libmagic.
+pub(crate) fn strength(entry: &MagicEntry) -> i64 {
+    let line = &entry.lines()[0];
+    let typ = line.cmp_type();
+    if *typ == CmpType::Default {
+        // make sure it sorts last
+        return 0;
+    }
+
+    let vallen = match line.reln_val() {
+        RelnVal::Str(s) => s.len() as i64,
+        _ => 0,
+    };
+    let mut val = 2 * MULT;
+    match typ {
+        CmpType::String | CmpType::PString => val += vallen * MULT,
+        CmpType::BEString16 | CmpType::LEString16 => val += vallen * MULT / 2,
+        CmpType::Search if vallen != 0 => val += vallen * (MULT / vallen).max(1),
+        CmpType::Regex => {
+            let v = match line.reln_val() {
+                RelnVal::Str(s) => nonmagic(s),
+                _ => 1,
+            };
+            val += v * (MULT / v).max(1);
+        }
+        CmpType::Der => val += MULT,
+        CmpType::Guid => val += 16 * MULT,
+        t => val += t.size().unwrap_or(0) as i64 * MULT,
+    }
+
+    match line.reln_op() {
+        // matches anything or almost anything
+        RelnOp::Any | RelnOp::Not => val = 0,
+        RelnOp::Eq => val += MULT,
+        RelnOp::Less | RelnOp::Greater => val -= 2 * MULT,
+        RelnOp::And | RelnOp::Xor => val -= MULT,
+        RelnOp::Noop => {}
+    }
+
+    if let Some(factor) = entry.factor() {
+        val = factor.apply(val);
+    }
+    if val <= 0 {
+        val = 1;
+    }
+    // entries without a description depend on their continuations to print something
+    if line.desc().is_empty() {
+        val += 1;
+    }
+    val
+}
+
+/// Classify an entry as a binary or a text test by the types of all its lines, as
+/// `set_text_binary` of libmagic.
+///
+/// The `b` and `t` modifiers of the first line override what the types imply.
+pub(crate) fn test_type(entry: &MagicEntry) -> MagicFlags {
+    let lines = entry.lines();
+    let str_flags = lines[0].str_flags();
+    let mut flags = MagicFlags::empty();
+    for line in lines {
+        match line.cmp_type() {
+            CmpType::String | CmpType::PString | CmpType::BEString16 | CmpType::LEString16 => {
+                if str_flags.contains(StrModifier::TEXTTEST) {
+                    flags |= MagicFlags::TEXTTEST;
+                } else {
+                    flags |= MagicFlags::BINTEST;
+                }
+            }
+            CmpType::Regex | CmpType::Search => {
+                if str_flags.contains(StrModifier::BINTEST) {
+                    flags |= MagicFlags::BINTEST;
+                }
+                if str_flags.contains(StrModifier::TEXTTEST) {
+                    flags |= MagicFlags::TEXTTEST;
+                }
+                if flags.intersects(MagicFlags::BINTEST | MagicFlags::TEXTTEST) {
+                    continue;
+                }
+                // a pattern which is not text looks for binary data
+                let pattern = match line.reln_val() {
+                    RelnVal::Str(s) => s.as_slice(),
+                    _ => &[],
+                };
+                if encoding::looks_utf8(pattern).is_some() {
+                    flags |= MagicFlags::TEXTTEST;
+                } else {
+                    flags |= MagicFlags::BINTEST;
+                }
+            }
+            // nothing can be told from them
+            CmpType::Invalid | CmpType::Default | CmpType::Name | CmpType::Use | CmpType::Indirect |
+            CmpType::Clear | CmpType::NamesSize => {}
+            _ => flags |= MagicFlags::BINTEST,
+        }
+    }
+    flags
+}
+
+/// Count the chars of a regex which are not special, and at least 1.
+fn nonmagic(re: &[u8]) -> i64 {
+    let mut count = 0;
+    let mut i = 0;
+    while i < re.len() {
+        match re[i] {
+            b'\\' => {
+                i += 1;
+                count += 1;
+            }
+            b'?' | b'*' | b'.' | b'+' | b'^' | b'$' => {}
+            // a bracket expression counts 1 for its `]'
+            b'[' => {
+                while i < re.len() && re[i] != b']' {
+                    i += 1;
+                }
+                continue;
+            }
+            b'{' => {
+                while i < re.len() && re[i] != b'}' {
+                    i += 1;
+                }
+            }
+            _ => count += 1,
+        }
+        i += 1;
+    }
+    count.max(1)
+}
+
+
+/// Compile the POSIX extended regex of a `regex` line, where `^` and `$` match at the line
+/// breaks as `REG_NEWLINE` of libmagic.
+fn compile_regex(line: &MagicLine) -> Result<Regex, String> {
+    let pattern = match line.reln_val() {
+        RelnVal::Str(s) => s,
+        _ => return Err("regex without a pattern".to_string()),
+    };
+    let ignore_case = match line.mask() {
+        Mask::Str { flags, .. } => flags.intersects(
+            StrModifier::IGNORE_LOWERCASE | StrModifier::IGNORE_UPPERCASE
+        ),
+        Mask::Num { .. } => false,
+    };
+
+    RegexBuilder::new(&translate_regex(pattern))
+        .unicode(false)
+        .multi_line(true)
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|e| format!("invalid regex `{}': {}", String::from_utf8_lossy(pattern), e))
+}
+
+/// Translate a POSIX regex into the syntax of the regex crate, where the bytes are matched
+/// as they are.
+fn translate_regex(pattern: &[u8]) -> String {
+    let mut out = String::new();
+    let mut i = 0;
+    while i < pattern.len() {
+        let b = pattern[i];
+        if b == b'\\' && i + 1 < pattern.len() {
+            let next = pattern[i + 1];
+            i += 2;
+            match next {
+                // word boundaries
+                b'<' | b'>' => out.push_str(r"\b"),
+                b if b.is_ascii_graphic() => {
+                    out.push('\\');
+                    out.push(b as char);
+                }
+                b => write!(out, "\\x{:02x}", b).unwrap(),
+            }
+            continue;
+        }
+        if b.is_ascii_graphic() || b == b' ' {
+            out.push(b as char);
+        } else {
+            write!(out, "\\x{:02x}", b).unwrap();
+        }
+        i += 1;
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::{nonmagic, strength, translate_regex, MagicList};
+    use crate::magic::MagicFlags;
+
+    #[test]
+    fn test_strength() {
+        let mlist = MagicList::load_str("\
+0	byte	x	any byte
+0	belong	0xcafebabe	java
+0	string	\\x7fELF	ELF
+!:strength	*2
+0	search/256	abc	search
+0	regex	\\^[a-z]+\\\\.py	regex
+0	beshort	>0x100
+0	name	sub
+");
+        let strengths: Vec<i64> = mlist.entries().iter().map(strength).collect();
+        assert_eq!(mlist.strengths, strengths);
+        // string: (20 + 4 * 10 + 10) * 2; belong: 20 + 4 * 10 + 10; search: 20 + 3 * 3 + 10;
+        // regex: 20 + 4 * 2 + 10; name: 20 + 10 + 1; beshort: 20 + 2 * 10 - 20 + 1; byte: 1
+        assert_eq!(strengths, vec![140, 70, 39, 38, 31, 21, 1]);
+        assert_eq!(mlist.top(), &[0, 1, 2, 3, 5, 6]);
+        assert_eq!(mlist.find_name(b"sub"), Some(4));
+        assert!(mlist.regex(3, 0).is_some());
+    }
+
+    #[test]
+    fn test_load_warnings() {
+        let path = env::temp_dir().join(format!("test_apprentice.{}.magic", process::id()));
+        fs::write(&path, "0\tstring\tABC\tabc\n0\tbogus\tx\ty\n0\tregex\t(\tz\n").unwrap();
+        let mlist = MagicList::load(&[&path, &env::temp_dir().join("nonexistent.magic")]);
+        fs::remove_file(&path).unwrap();
+        let mlist = mlist.unwrap();
+        assert_eq!(mlist.entries().len(), 2);
+        let warnings: Vec<&str> = mlist.warnings().iter()
+            .map(|warning| warning.split(": Warning: ").nth(1).unwrap())
+            .collect();
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0], "Unknown type `bogus'");
+        assert!(mlist.warnings()[1].contains(":3: Warning: "));
+    }
+
+    #[test]
+    fn test_test_type() {
+        let mlist = MagicList::load_str("\
+0	string	ABCDEFGHIJ	string
+0	string/t	ABCDEFGH	text string
+>8	byte	1	and byte
+0	search/b	ABCDEF	binary search
+0	search	ABC	text search
+0	search	\\0\\1	binary pattern
+0	regex/t	\\0	text regex
+");
+        let test_type = |desc: &str| {
+            let i = mlist.entries().iter().position(|entry| entry.lines()[0].desc() == desc).unwrap();
+            mlist.test_type(i)
+        };
+        assert_eq!(test_type(" string"), MagicFlags::BINTEST);
+        // the byte of the continuation is a binary test
+        assert_eq!(test_type(" text string"), MagicFlags::TEXTTEST | MagicFlags::BINTEST);
+        assert_eq!(test_type(" binary search"), MagicFlags::BINTEST);
+        assert_eq!(test_type(" text search"), MagicFlags::TEXTTEST);
+        assert_eq!(test_type(" binary pattern"), MagicFlags::BINTEST);
+        assert_eq!(test_type(" text regex"), MagicFlags::TEXTTEST);
+    }
+
+    #[test]
+    fn test_nonmagic() {
+        assert_eq!(nonmagic(b"^[a-z]+\\.py$"), 4);
+        assert_eq!(nonmagic(b"a{1,3}b"), 2);
+        assert_eq!(nonmagic(b".*"), 1);
+    }
+
+    #[test]
+    fn test_translate_regex() {
+        assert_eq!(translate_regex(b"\\<foo\\>"), "\\bfoo\\b");
+        assert_eq!(translate_regex(b"a\xff\tb\\."), "a\\xff\\x09b\\.");
+    }
+}
@@ -0,0 +1,246 @@
+use crate::apprentice::MagicList;
+use crate::encoding::Encoding;
+use crate::magic::MagicFlags;
+use crate::magic_param::{MagicParam, Param};
+use crate::magic_set::Flags;
+use crate::names::TokenTable;
+use crate::softmagic::SoftMagic;
+
+
+/// The lines longer than this are reported
+const MAXLINELEN: usize = 300;
+
+
+/// The line terminators and the other control chars of a text, as counted by
+/// `file_ascmagic_with_encoding` of libmagic.
+#[derive(Debug, Default, PartialEq)]
+struct TextProps {
+    n_crlf: usize,
+    n_cr: usize,
+    n_lf: usize,
+    /// The next line chars of ISO 6429
+    n_nel: usize,
+    /// The length of the longest line which is longer than `MAXLINELEN`, or 0
+    max_line_length: usize,
+    has_escapes: bool,
+    /// If the text is overstruck with backspaces
+    has_backspace: bool,
+}
+
+impl TextProps {
+    fn scan(chars: &[u32]) -> TextProps {
+        let mut props = TextProps::default();
+        let mut seen_cr = false;
+        // the index of the last line end, which is -1 before the first line
+        let mut last_line_end: i64 = -1;
+        for (i, &c) in chars.iter().enumerate() {
+            let i = i as i64;
+            if c == u32::from(b'\n') {
+                if seen_cr {
+                    props.n_crlf += 1;
+                } else {
+                    props.n_lf += 1;
+                }
+                last_line_end = i;
+            } else if seen_cr {
+                props.n_cr += 1;
+            }
+            seen_cr = c == u32::from(b'\r');
+            if seen_cr {
+                last_line_end = i;
+            }
+            if c == 0x85 {
+                props.n_nel += 1;
+                last_line_end = i;
+            }
+            if i > last_line_end + MAXLINELEN as i64 {
+                props.max_line_length = props.max_line_length.max((i - last_line_end) as usize);
+            }
+            match c {
+                0x1b => props.has_escapes = true,
+                0x08 => props.has_backspace = true,
+                _ => {}
+            }
+        }
+        props
+    }
+
+    /// Append the properties to the description, such as `, with CRLF line terminators`.
+    fn describe(&self, out: &mut String) {
+        if self.max_line_length > 0 {
+            out.push_str(&format!(", with very long lines ({})", self.max_line_length));
+        }
+        // only report the line terminators if there is any other than LF, or none at all
+        let terminators = [
+            (self.n_crlf, "CRLF"),
+            (self.n_cr, "CR"),
+            (self.n_lf, "LF"),
+            (self.n_nel, "NEL"),
+        ];
+        let found: Vec<&str> = terminators.iter()
+            .filter(|(n, _)| *n > 0)
+            .map(|(_, name)| *name)
+            .collect();
+        if found.is_empty() {
+            out.push_str(", with no line terminators");
+        } else if found != ["LF"] {
+            out.push_str(&format!(", with {} line terminators", found.join(", ")));
+        }
+        if self.has_escapes {
+            out.push_str(", with escape sequences");
+        }
+        if self.has_backspace {
+            out.push_str(", with overstriking");
+        }
+    }
+}
+
+
+/// Detect the encoding of a buffer without its trailing NULs, and decode it, or return
+/// `None` if it is not text.
+pub(crate) fn decode_text(buf: &[u8], param: &MagicParam) -> Option<(Encoding, Vec<u32>)> {
+    // leave at least a byte to look at
+    let end = buf.iter().rposition(|&c| c != 0).map_or(1, |i| i + 1).min(buf.len());
+    let buf = &buf[..end.min(param.get(Param::Encoding) as usize)];
+    let encoding = Encoding::detect(buf);
+    if !encoding.is_text() {
+        return None;
+    }
+    Some((encoding, encoding.decode(buf)))
+}
+
+/// Encode the chars in UTF-8, for trying the text tests on.
+pub(crate) fn to_utf8(chars: &[u32]) -> Vec<u8> {
+    chars.iter()
+        .filter_map(|&c| char::from_u32(c))
+        .collect::<String>()
+        .into_bytes()
+}
+
+
+/// Describe a buffer which looks like text by the text tests and its encoding, as
+/// `file_ascmagic` of libmagic, or return `None` if it is not text.
+///
+/// If no text test matches, the kind of text is told by the tokens of its words.
+pub(crate) fn ascmagic(
+    mlist: &MagicList,
+    tokens: &TokenTable,
+    param: &MagicParam,
+    flags: Flags,
+    executable: bool,
+    buf: &[u8],
+) -> Result<Option<String>, String> {
+    if flags.contains(Flags::APPLE) {
+        return Ok(None);
+    }
+    let (encoding, chars) = match decode_text(buf, param) {
+        Some(text) => text,
+        None => return Ok(None),
+    };
+
+    let text = to_utf8(&chars);
+    let mut out = String::new();
+    if !flags.contains(Flags::NO_CHECK_SOFT) {
+        let mut soft = SoftMagic::new(mlist, param, flags, executable);
+        if soft.run(&text, MagicFlags::TEXTTEST, true)? {
+            out = soft.into_output();
+        }
+    }
+    // the tokens have no extensions to give
+    let lang = if out.is_empty() && !flags.intersects(Flags::NO_CHECK_TOKENS | Flags::EXTENSION) {
+        tokens.classify(&text)
+    } else {
+        None
+    };
+
+    if flags.intersects(Flags::MIME) {
+        if out.is_empty() && flags.contains(Flags::MIME_TYPE) {
+            out.push_str(lang.map_or("text/plain", |lang| &lang.mime));
+        }
+        return Ok(Some(out));
+    }
+    if flags.contains(Flags::EXTENSION) {
+        return Ok(Some(out));
+    }
+    if let Some(lang) = lang {
+        out.push_str(&lang.desc);
+    }
+
+    // the text tests describe the kind of text, which the encoding is joined to
+    let mut executable = false;
+    if !out.is_empty() {
+        if let Some(desc) = out.strip_suffix(" text") {
+            out.truncate(desc.len());
+        } else if let Some(desc) = out.strip_suffix(" text executable") {
+            out.truncate(desc.len());
+            executable = true;
+        }
+        out.push_str(", ");
+    }
+    out.push_str(encoding.name());
+    out.push(' ');
+    out.push_str(encoding.text_type());
+    if executable {
+        out.push_str(" executable");
+    }
+    TextProps::scan(&chars).describe(&mut out);
+    Ok(Some(out))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ascmagic, TextProps};
+    use crate::apprentice::MagicList;
+    use crate::magic_param::MagicParam;
+    use crate::magic_set::Flags;
+    use crate::names::TokenTable;
+
+    fn describe(s: &str) -> String {
+        let chars: Vec<u32> = s.chars().map(u32::from).collect();
+        let mut out = String::new();
+        TextProps::scan(&chars).describe(&mut out);
+        out
+    }
+
+    #[test]
+    fn test_props() {
+        assert_eq!(describe("a\nb\n"), "");
+        assert_eq!(describe("a\r\nb\r\n"), ", with CRLF line terminators");
+        assert_eq!(describe("a\rb\nc\r\n"), ", with CRLF, CR, LF line terminators");
+        assert_eq!(describe("a\u{85}b"), ", with NEL line terminators");
+        assert_eq!(describe("abc"), ", with no line terminators");
+        assert_eq!(describe("\x1b[0ma\n_\x08a\n"), ", with escape sequences, with overstriking");
+        assert_eq!(describe(&format!("{}\n", "a".repeat(400))), ", with very long lines (400)");
+    }
+
+    #[test]
+    fn test_ascmagic() {
+        let mlist = MagicList::load_str(
+            "0\tregex\t\\^#!.*python\tPython script text executable\n!:mime\ttext/x-python\n"
+        );
+        let tokens = TokenTable::default();
+        let param = MagicParam::default();
+        let run = |flags: Flags, buf: &[u8]| ascmagic(&mlist, &tokens, &param, flags, false, buf).unwrap();
+
+        assert_eq!(run(Flags::NONE, b"hello\r\n\0\0").unwrap(), "ASCII text, with CRLF line terminators");
+        assert_eq!(run(Flags::NONE, "h\u{e9}llo\n".as_bytes()).unwrap(), "UTF-8 Unicode text");
+        assert_eq!(run(Flags::NONE, b"\xfe\xff\0h\0\n").unwrap(), "Big-endian UTF-16 Unicode text");
+        assert_eq!(run(Flags::NONE, b"caf\xe9\n").unwrap(), "ISO-8859 text");
+        assert_eq!(
+            run(Flags::NONE, b"\xc8\x85\x93\x93\x96\x15").unwrap(),
+            "EBCDIC character data, with NEL line terminators"
+        );
+        assert_eq!(
+            run(Flags::NONE, b"#!/usr/bin/python\n").unwrap(),
+            "Python script, ASCII text executable"
+        );
+        assert_eq!(run(Flags::MIME_TYPE, b"#!/usr/bin/python\n").unwrap(), "text/x-python");
+        assert_eq!(run(Flags::MIME_TYPE, b"hello\n").unwrap(), "text/plain");
+        let c = b"#include <stdio.h>\nint main(void) { printf(\"hi\\n\"); }\n";
+        assert_eq!(run(Flags::NONE, c).unwrap(), "C source, ASCII text");
+        assert_eq!(run(Flags::MIME_TYPE, c).unwrap(), "text/x-c");
+        assert_eq!(run(Flags::NO_CHECK_TOKENS, c).unwrap(), "ASCII text");
+        assert_eq!(run(Flags::NONE, b"\x7fELF\x02\x01\x01\x00"), None);
+    }
+}
@@ -0,0 +1,5 @@
+//! `magic-tool dump`, `fmt` and `lint`, which work on the magic files apart from `magic`.
+
+fn main() {
+    file_type::cli::tool_main();
+}
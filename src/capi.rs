@@ -0,0 +1,427 @@
+//! The C interface of libmagic, declared by `include/magic.h`, which build.rs generates with cbindgen.
+
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString, OsStr};
+use std::fs::File;
+use std::io;
+use std::mem::ManuallyDrop;
+use std::os::raw::{c_char, c_int, c_void};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::FromRawFd;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+use num_traits::FromPrimitive;
+
+use crate::apprentice;
+use crate::cookie::Magic;
+use crate::magic_param::Param;
+use crate::magic_set::{self, Flags};
+
+
+/// The version of libmagic whose interface is provided
+pub const MAGIC_VERSION: c_int = 545;
+
+
+/// The handle behind `magic_t`, which keeps the last result and error for the caller.
+pub struct MagicCookie {
+    magic: Magic,
+    result: CString,
+    error: Option<CString>,
+    /// The error number of the system call which failed the last call, or 0
+    errno: c_int,
+}
+
+impl MagicCookie {
+    /// Keep the result of a call, and return it as libmagic does.
+    fn set_result(&mut self, res: Result<String, String>) -> *const c_char {
+        match res {
+            Ok(desc) => {
+                self.error = None;
+                self.result = to_cstring(desc);
+                self.result.as_ptr()
+            }
+            Err(msg) => self.fail(msg),
+        }
+    }
+
+    fn set_status(&mut self, res: Result<(), String>) -> c_int {
+        match res {
+            Ok(()) => {
+                self.error = None;
+                0
+            }
+            Err(msg) => {
+                self.fail(msg);
+                -1
+            }
+        }
+    }
+
+    fn fail(&mut self, msg: String) -> *const c_char {
+        self.error = Some(to_cstring(msg));
+        self.errno = magic_set::take_errno();
+        ptr::null()
+    }
+}
+
+/// Convert a string to C, where it ends at the first NUL if any.
+fn to_cstring(s: String) -> CString {
+    let mut bytes = s.into_bytes();
+    if let Some(i) = bytes.iter().position(|&c| c == 0) {
+        bytes.truncate(i);
+    }
+    CString::new(bytes).unwrap()
+}
+
+unsafe fn to_path<'a>(s: *const c_char) -> &'a Path {
+    Path::new(OsStr::from_bytes(CStr::from_ptr(s).to_bytes()))
+}
+
+fn to_flags(flags: c_int) -> Result<Flags, String> {
+    Flags::from_bits(flags as u32).ok_or_else(|| format!("invalid flags 0x{:x}", flags))
+}
+
+
+#[no_mangle]
+pub extern "C" fn magic_open(flags: c_int) -> *mut MagicCookie {
+    match to_flags(flags).and_then(Magic::open) {
+        Ok(magic) => {
+            let cookie = MagicCookie { magic, result: CString::default(), error: None, errno: 0 };
+            Box::into_raw(Box::new(cookie))
+        }
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// # Safety
+///
+/// `ms` must be returned by `magic_open`, and not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn magic_close(ms: *mut MagicCookie) {
+    if !ms.is_null() {
+        drop(Box::from_raw(ms));
+    }
+}
+
+/// # Safety
+///
+/// `ms` must be returned by `magic_open`, and `filename` be NULL or a C string.
+#[no_mangle]
+pub unsafe extern "C" fn magic_load(ms: *mut MagicCookie, filename: *const c_char) -> c_int {
+    let ms = match ms.as_mut() {
+        Some(ms) => ms,
+        None => return -1,
+    };
+    let paths = if filename.is_null() {
+        apprentice::default_paths()
+    } else {
+        match CStr::from_ptr(filename).to_str() {
+            Ok(list) => apprentice::split_paths(list),
+            Err(_) => vec![to_path(filename).to_path_buf()],
+        }
+    };
+    let res = ms.magic.load(&paths);
+    ms.set_status(res)
+}
+
+/// # Safety
+///
+/// `ms` must be returned by `magic_open`, and `filename` be NULL or a C string.
+#[no_mangle]
+pub unsafe extern "C" fn magic_compile(ms: *mut MagicCookie, _filename: *const c_char) -> c_int {
+    match ms.as_mut() {
+        Some(ms) => ms.set_status(Err("compiling magic files is not supported".to_string())),
+        None => -1,
+    }
+}
+
+/// # Safety
+///
+/// `ms` must be returned by `magic_open`, and `filename` be NULL or a C string.
+#[no_mangle]
+pub unsafe extern "C" fn magic_check(ms: *mut MagicCookie, filename: *const c_char) -> c_int {
+    let ms = match ms.as_mut() {
+        Some(ms) => ms,
+        None => return -1,
+    };
+    let paths = if filename.is_null() {
+        apprentice::default_paths()
+    } else {
+        vec![to_path(filename).to_path_buf()]
+    };
+    let res = Magic::default().load(&paths);
+    ms.set_status(res)
+}
+
+/// # Safety
+///
+/// `ms` must be returned by `magic_open`, and `filename` be NULL for the standard input or
+/// a C string.
+#[no_mangle]
+pub unsafe extern "C" fn magic_file(ms: *mut MagicCookie, filename: *const c_char) -> *const c_char {
+    let ms = match ms.as_mut() {
+        Some(ms) => ms,
+        None => return ptr::null(),
+    };
+    let res = if filename.is_null() {
+        ms.magic.classify_reader(io::stdin().lock())
+    } else {
+        ms.magic.classify_path(to_path(filename))
+    };
+    ms.set_result(res)
+}
+
+/// # Safety
+///
+/// `ms` must be returned by `magic_open`, and `buffer` point to `length` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn magic_buffer(ms: *mut MagicCookie, buffer: *const c_void, length: usize)
+    -> *const c_char {
+    let ms = match ms.as_mut() {
+        Some(ms) => ms,
+        None => return ptr::null(),
+    };
+    let buf = if buffer.is_null() { &[] } else { slice::from_raw_parts(buffer as *const u8, length) };
+    let res = ms.magic.classify_bytes(buf);
+    ms.set_result(res)
+}
+
+/// # Safety
+///
+/// `ms` must be returned by `magic_open`, and `fd` be open for reading, which is left open.
+#[no_mangle]
+pub unsafe extern "C" fn magic_descriptor(ms: *mut MagicCookie, fd: c_int) -> *const c_char {
+    let ms = match ms.as_mut() {
+        Some(ms) => ms,
+        None => return ptr::null(),
+    };
+    let file = ManuallyDrop::new(File::from_raw_fd(fd));
+    let res = ms.magic.classify_reader(&*file);
+    ms.set_result(res)
+}
+
+/// # Safety
+///
+/// `ms` must be returned by `magic_open`.
+#[no_mangle]
+pub unsafe extern "C" fn magic_error(ms: *const MagicCookie) -> *const c_char {
+    match ms.as_ref().and_then(|ms| ms.error.as_ref()) {
+        Some(error) => error.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// # Safety
+///
+/// `ms` must be returned by `magic_open`.
+#[no_mangle]
+pub unsafe extern "C" fn magic_errno(ms: *const MagicCookie) -> c_int {
+    match ms.as_ref() {
+        Some(ms) if ms.error.is_some() => ms.errno,
+        _ => 0,
+    }
+}
+
+/// # Safety
+///
+/// `ms` must be returned by `magic_open`.
+#[no_mangle]
+pub unsafe extern "C" fn magic_getflags(ms: *const MagicCookie) -> c_int {
+    match ms.as_ref() {
+        Some(ms) => ms.magic.flags().bits() as c_int,
+        None => -1,
+    }
+}
+
+/// # Safety
+///
+/// `ms` must be returned by `magic_open`.
+#[no_mangle]
+pub unsafe extern "C" fn magic_setflags(ms: *mut MagicCookie, flags: c_int) -> c_int {
+    let ms = match ms.as_mut() {
+        Some(ms) => ms,
+        None => return -1,
+    };
+    let res = to_flags(flags).and_then(|flags| ms.magic.set_flags(flags));
+    ms.set_status(res)
+}
+
+/// # Safety
+///
+/// `ms` must be returned by `magic_open`, and `value` point to a `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn magic_setparam(ms: *mut MagicCookie, param: c_int, value: *const c_void) -> c_int {
+    let ms = match ms.as_mut() {
+        Some(ms) => ms,
+        None => return -1,
+    };
+    let param = match Param::from_i32(param) {
+        Some(param) if !value.is_null() => param,
+        _ => return ms.set_status(Err(format!("invalid parameter {}", param))),
+    };
+    let value = *(value as *const usize);
+    let value = match i32::try_from(value) {
+        Ok(value) => value,
+        Err(_) => return ms.set_status(Err(format!("{:?} is out of range", param))),
+    };
+    let mut params = ms.magic.param().clone();
+    let res = params.set(param, value).map(|_| ms.magic.set_param(params));
+    ms.set_status(res)
+}
+
+/// # Safety
+///
+/// `ms` must be returned by `magic_open`, and `value` point to a `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn magic_getparam(ms: *mut MagicCookie, param: c_int, value: *mut c_void) -> c_int {
+    let ms = match ms.as_mut() {
+        Some(ms) => ms,
+        None => return -1,
+    };
+    let param = match Param::from_i32(param) {
+        Some(param) if !value.is_null() => param,
+        _ => return ms.set_status(Err(format!("invalid parameter {}", param))),
+    };
+    *(value as *mut usize) = ms.magic.param().get(param) as usize;
+    ms.set_status(Ok(()))
+}
+
+#[no_mangle]
+pub extern "C" fn magic_version() -> c_int {
+    MAGIC_VERSION
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::ffi::{CStr, CString};
+    use std::fs;
+    use std::os::raw::c_void;
+    use std::os::unix::io::AsRawFd;
+    use std::process;
+    use std::ptr;
+
+    use super::*;
+
+    const HEADER: &str = include_str!("../include/magic.h");
+
+    /// The generated header declares the exported functions, and the same flags and params.
+    #[test]
+    fn test_header() {
+        let source = include_str!("capi.rs");
+        let exported: Vec<&str> = source
+            .split("#[no_mangle]\n")
+            .skip(1)
+            .map(|s| {
+                let s = &s[s.find("fn ").unwrap() + 3..];
+                &s[..s.find('(').unwrap()]
+            })
+            .collect();
+        let declared: Vec<&str> = HEADER
+            .lines()
+            .filter(|l| l.ends_with(");") && !l.starts_with('#'))
+            .map(|l| {
+                let l = &l[..l.find('(').unwrap()];
+                l.rsplit([' ', '*']).next().unwrap()
+            })
+            .collect();
+        assert_eq!(declared, exported);
+        assert_eq!(exported.len(), 15);
+
+        let mut defined = Flags::empty();
+        for line in HEADER.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (name, value) = match fields.as_slice() {
+                ["#define", name, value, ..] if name.starts_with("MAGIC_") && value.starts_with("0x") => {
+                    (name.trim_start_matches("MAGIC_"), u32::from_str_radix(&value[2..], 16).unwrap())
+                }
+                _ => continue,
+            };
+            if value.count_ones() != 1 {
+                continue;
+            }
+            let flag = Flags::from_bits(value).unwrap();
+            assert_eq!(format!("{:?}", flag), name);
+            defined |= flag;
+        }
+        assert_eq!(defined, Flags::all() - Flags::NONE);
+
+        let params: Vec<Param> = HEADER
+            .lines()
+            .filter_map(|l| match l.split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["#define", name, value] if name.starts_with("MAGIC_PARAM_") => Param::from_i32(value.parse().unwrap()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(params.len(), 8);
+        assert!(HEADER.contains(&format!("#define MAGIC_VERSION {}\n", MAGIC_VERSION)));
+    }
+
+    #[test]
+    fn test_capi() {
+        let path = env::temp_dir().join(format!("test_capi.{}.magic", process::id()));
+        fs::write(&path, "0\tstring\tGIF8\tGIF image data\n!:mime\timage/gif\n").unwrap();
+        let data = env::temp_dir().join(format!("test_capi.{}.gif", process::id()));
+        fs::write(&data, "GIF89a").unwrap();
+        let path_c = CString::new(path.to_str().unwrap()).unwrap();
+        let data_c = CString::new(data.to_str().unwrap()).unwrap();
+
+        unsafe {
+            let ms = magic_open(0);
+            assert!(!ms.is_null());
+            assert_eq!(magic_load(ms, path_c.as_ptr()), 0);
+
+            let desc = magic_buffer(ms, b"GIF87a".as_ptr() as *const c_void, 6);
+            assert_eq!(CStr::from_ptr(desc).to_str().unwrap(), "GIF image data");
+            let desc = magic_file(ms, data_c.as_ptr());
+            assert_eq!(CStr::from_ptr(desc).to_str().unwrap(), "GIF image data");
+            let file = fs::File::open(&data).unwrap();
+            let desc = magic_descriptor(ms, file.as_raw_fd());
+            assert_eq!(CStr::from_ptr(desc).to_str().unwrap(), "GIF image data");
+            assert!(magic_error(ms).is_null());
+
+            assert_eq!(magic_setflags(ms, 0x10), 0);
+            assert_eq!(magic_getflags(ms), 0x10);
+            let desc = magic_file(ms, data_c.as_ptr());
+            assert_eq!(CStr::from_ptr(desc).to_str().unwrap(), "image/gif");
+
+            let value: usize = 100;
+            assert_eq!(magic_setparam(ms, 6, &value as *const usize as *const c_void), 0);
+            let mut got: usize = 0;
+            assert_eq!(magic_getparam(ms, 6, &mut got as *mut usize as *mut c_void), 0);
+            assert_eq!(got, 100);
+            let value: usize = 1 << 20;
+            assert_eq!(magic_setparam(ms, 0, &value as *const usize as *const c_void), -1);
+            assert_eq!(magic_setparam(ms, 99, &value as *const usize as *const c_void), -1);
+            assert!(!magic_error(ms).is_null());
+
+            assert_eq!(magic_setflags(ms, 0x200), 0);
+            let missing = CString::new("/nonexistent").unwrap();
+            assert!(magic_file(ms, missing.as_ptr()).is_null());
+            assert_eq!(magic_errno(ms), libc::ENOENT);
+            assert!(!magic_file(ms, data_c.as_ptr()).is_null());
+            assert_eq!(magic_errno(ms), 0);
+            assert_eq!(magic_setparam(ms, 99, &value as *const usize as *const c_void), -1);
+            assert_eq!(magic_errno(ms), 0);
+
+            assert_eq!(magic_load(ms, missing.as_ptr()), -1);
+            assert!(!magic_error(ms).is_null());
+            assert_eq!(magic_errno(ms), libc::ENOENT);
+            magic_close(ms);
+
+            // MAGIC_DEBUG | MAGIC_PRESERVE_ATIME
+            let ms = magic_open(0x81);
+            assert!(!ms.is_null());
+            assert_eq!(magic_getflags(ms), 0x81);
+            magic_close(ms);
+            assert!(magic_open(0x10000000).is_null());
+            magic_close(ptr::null_mut());
+        }
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&data).unwrap();
+    }
+}
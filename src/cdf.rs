@@ -0,0 +1,745 @@
+use std::convert::TryInto;
+
+use crate::magic_set::{Flags, Source};
+use crate::print::{self, FmtArg, TimeKind};
+
+
+/// The magic number of a compound document file
+pub(crate) const MAGIC: &[u8] = b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1";
+
+/// The description of a compound document file which nothing more is known of
+const DOCUMENT: &str = "Composite Document File V2 Document";
+
+const HEADER_SIZE: usize = 512;
+/// The number of the sectors of the FAT listed in the header
+const HEADER_DIFAT_LEN: usize = 109;
+/// The largest id of a sector, beyond which the ids end a chain or mark the special sectors
+const MAX_SECTOR: u32 = 0xffff_fffa;
+const END_OF_CHAIN: u32 = 0xffff_fffe;
+
+const DIR_ENTRY_SIZE: usize = 128;
+const DIR_ROOT: u8 = 5;
+
+const VT_I2: u32 = 0x02;
+const VT_I4: u32 = 0x03;
+const VT_R4: u32 = 0x04;
+const VT_R8: u32 = 0x05;
+const VT_UI4: u32 = 0x13;
+const VT_LPSTR: u32 = 0x1e;
+const VT_LPWSTR: u32 = 0x1f;
+const VT_FILETIME: u32 = 0x40;
+
+const PID_APPNAME: u32 = 18;
+
+/// The longest string of a property which is printed
+const MAX_STRING: usize = 1023;
+/// The times shorter than this many 100-nanoseconds are durations instead of dates
+const MAX_DURATION: u64 = 1_000_000_000_000_000;
+
+/// The class ID of the root storage of the Windows Installer files
+const MSI_CLSID: [u8; 16] = *b"\x84\x10\x0c\x00\x00\x00\x00\x00\xc0\x00\x00\x00\x00\x00\x00\x46";
+
+/// The MIME subtypes by the name of the creating application, as `app2mime` of libmagic
+const APP_MIME: [(&str, &str); 9] = [
+    ("Word", "msword"),
+    ("Excel", "vnd.ms-excel"),
+    ("Powerpoint", "vnd.ms-powerpoint"),
+    ("Crystal Reports", "x-rpt"),
+    ("Advanced Installer", "vnd.ms-msi"),
+    ("InstallShield", "vnd.ms-msi"),
+    ("Microsoft Patch Compiler", "vnd.ms-msi"),
+    ("NAnt", "vnd.ms-msi"),
+    ("Windows Installer", "vnd.ms-msi"),
+];
+
+/// The MIME subtypes by the name of a stream, as `name2mime` of libmagic
+const STREAM_MIME: [(&str, &str); 5] = [
+    ("Book", "vnd.ms-excel"),
+    ("Workbook", "vnd.ms-excel"),
+    ("WordDocument", "msword"),
+    ("PowerPoint", "vnd.ms-powerpoint"),
+    ("DigitalSignature", "vnd.ms-msi"),
+];
+
+/// The kinds of documents by their streams when there is no summary information, with
+/// their MIME subtypes, as `sectioninfo` of libmagic
+const SECTIONS: [(&str, &str, &[&str]); 5] = [
+    ("Encrypted", "encrypted", &["EncryptedPackage", "EncryptedSummary"]),
+    ("Microsoft Excel", "vnd.ms-excel", &["Book", "Workbook"]),
+    ("Microsoft Word", "msword", &["WordDocument"]),
+    ("Microsoft PowerPoint", "vnd.ms-powerpoint", &["PowerPoint"]),
+    ("Microsoft Outlook Message", "vnd.ms-outlook", &["__properties_version1.0", "__recip_version1.0_#00000000"]),
+];
+
+
+fn le16(buf: &[u8], off: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(buf.get(off..off.checked_add(2)?)?.try_into().ok()?))
+}
+
+fn le32(buf: &[u8], off: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(buf.get(off..off.checked_add(4)?)?.try_into().ok()?))
+}
+
+fn le64(buf: &[u8], off: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(buf.get(off..off.checked_add(8)?)?.try_into().ok()?))
+}
+
+fn le32s(buf: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    buf.chunks_exact(4).map(|id| u32::from_le_bytes(id.try_into().unwrap()))
+}
+
+/// Decode a UTF-16 string up to its NUL.
+fn utf16(buf: &[u8]) -> String {
+    let units: Vec<u16> = buf.chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&c| c != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+
+/// Describe a compound document file, such as an Office 97-2003 document or an MSI
+/// installer, by its summary information, as `file_trycdf` of libmagic, or return `None`
+/// if it is not one or there is nothing to tell with the MIME flags.
+///
+/// A document which cannot be read is described by what is wrong with it.
+pub(crate) fn trycdf(source: Source, flags: Flags) -> Option<String> {
+    let mut cdf = Cdf::open(source)?;
+    match cdf.describe(flags) {
+        Ok(desc) => Some(desc).filter(|desc| !desc.is_empty()),
+        Err(e) if !flags.intersects(Flags::MIME) => Some(format!("{}, {}", DOCUMENT, e)),
+        Err(_) if flags.contains(Flags::MIME_TYPE) => Some("application/CDFV2".to_string()),
+        Err(_) => None,
+    }
+}
+
+
+/// An entry of the directory, which is a storage or a stream.
+#[derive(Debug)]
+struct DirEntry {
+    name: String,
+    typ: u8,
+    clsid: [u8; 16],
+    start: u32,
+    size: u64,
+}
+
+impl DirEntry {
+    fn parse(buf: &[u8], v4: bool) -> DirEntry {
+        let name_len = usize::from(le16(buf, 64).unwrap_or(0)).min(64);
+        DirEntry {
+            name: utf16(&buf[..name_len]),
+            typ: buf[66],
+            clsid: buf[80..96].try_into().unwrap(),
+            start: le32(buf, 116).unwrap_or(END_OF_CHAIN),
+            // the high half is not used by version 3, which may be garbage
+            size: if v4 { le64(buf, 120).unwrap_or(0) } else { u64::from(le32(buf, 120).unwrap_or(0)) },
+        }
+    }
+}
+
+
+/// A compound document file, as the `cdf_header_t` and the tables read by `file_trycdf` of
+/// libmagic.
+struct Cdf<'a> {
+    source: Source<'a>,
+    header: Vec<u8>,
+    sector_shift: u32,
+    mini_shift: u32,
+    /// The streams smaller than this are in the mini stream
+    cutoff: u64,
+    fat: Vec<u32>,
+    minifat: Vec<u32>,
+    dir: Vec<DirEntry>,
+    ministream: Vec<u8>,
+}
+
+impl<'a> Cdf<'a> {
+    /// Read the header, or return `None` if it is not a compound document file, as
+    /// `cdf_read_header` of libmagic.
+    fn open(source: Source<'a>) -> Option<Cdf<'a>> {
+        let header = source.read(0, HEADER_SIZE as u64);
+        if header.len() < HEADER_SIZE || !header.starts_with(MAGIC) || le16(&header, 28)? != 0xfffe {
+            return None;
+        }
+        let sector_shift = u32::from(le16(&header, 30)?);
+        let mini_shift = u32::from(le16(&header, 32)?);
+        if !(7..=20).contains(&sector_shift) || !(2..=sector_shift).contains(&mini_shift) {
+            return None;
+        }
+        Some(Cdf {
+            source,
+            cutoff: u64::from(le32(&header, 56)?),
+            header,
+            sector_shift,
+            mini_shift,
+            fat: vec![],
+            minifat: vec![],
+            dir: vec![],
+            ministream: vec![],
+        })
+    }
+
+    fn header32(&self, off: usize) -> u32 {
+        le32(&self.header, off).unwrap()
+    }
+
+    fn sector_size(&self) -> usize {
+        1 << self.sector_shift
+    }
+
+    /// The number of the sectors in the file, which bounds the tables and the chains.
+    fn num_sectors(&self) -> usize {
+        (self.source.size().unwrap_or(0) >> self.sector_shift) as usize
+    }
+
+    fn sector(&self, id: u32) -> Option<Vec<u8>> {
+        if id > MAX_SECTOR {
+            return None;
+        }
+        let size = self.sector_size();
+        let buf = self.source.read((u64::from(id) + 1) << self.sector_shift, size as u64);
+        Some(buf).filter(|buf| buf.len() == size)
+    }
+
+    /// Read the FAT from the sectors listed in the header and the DIFAT sectors, as
+    /// `cdf_read_sat` of libmagic.
+    fn read_fat(&self) -> Option<Vec<u32>> {
+        let num_fat = self.header32(44) as usize;
+        let mut ids: Vec<u32> = le32s(&self.header[76..76 + HEADER_DIFAT_LEN * 4]).collect();
+        // each DIFAT sector ends with the id of the next one
+        let per_difat = self.sector_size() / 4 - 1;
+        let mut next = self.header32(68);
+        for _ in 0..(self.header32(72) as usize).min(self.num_sectors()) {
+            let sector = self.sector(next)?;
+            ids.extend(le32s(&sector).take(per_difat));
+            next = le32(&sector, per_difat * 4)?;
+        }
+        if num_fat > ids.len() || num_fat > self.num_sectors() {
+            return None;
+        }
+        let mut fat = vec![];
+        for &id in &ids[..num_fat] {
+            fat.extend(le32s(&self.sector(id)?));
+        }
+        Some(fat)
+    }
+
+    /// The most sectors of a chain, after `CDF_SEC_LIMIT` of libmagic.
+    fn sec_limit(&self) -> usize {
+        u32::MAX as usize / (64 * self.sector_size())
+    }
+
+    /// Follow a chain of sectors in a FAT, where a chain which is longer than the FAT loops
+    /// and one which is longer than `limit` is refused, as `cdf_count_chain` of libmagic.
+    fn chain(fat: &[u32], start: u32, limit: usize) -> Option<Vec<u32>> {
+        let mut ids = vec![];
+        let mut id = start;
+        while id != END_OF_CHAIN {
+            if id as usize >= fat.len() || ids.len() >= fat.len().min(limit) {
+                return None;
+            }
+            ids.push(id);
+            id = fat[id as usize];
+        }
+        Some(ids)
+    }
+
+    /// Read a chain of sectors up to `len` bytes, as `cdf_read_long_sector_chain` of
+    /// libmagic.
+    fn read_chain(&self, start: u32, len: u64) -> Option<Vec<u8>> {
+        let mut buf = vec![];
+        for id in Cdf::chain(&self.fat, start, self.sec_limit())? {
+            if buf.len() as u64 >= len {
+                break;
+            }
+            buf.extend(self.sector(id)?);
+        }
+        buf.truncate(len.min(buf.len() as u64) as usize);
+        Some(buf)
+    }
+
+    /// Read a chain of sectors of the mini stream up to `len` bytes, as
+    /// `cdf_read_short_sector_chain` of libmagic.
+    fn read_mini_chain(&self, start: u32, len: u64) -> Option<Vec<u8>> {
+        let size = 1 << self.mini_shift;
+        let mut buf = vec![];
+        for id in Cdf::chain(&self.minifat, start, self.sec_limit())? {
+            if buf.len() as u64 >= len {
+                break;
+            }
+            buf.extend_from_slice(self.ministream.get(id as usize * size..(id as usize + 1) * size)?);
+        }
+        buf.truncate(len.min(buf.len() as u64) as usize);
+        Some(buf)
+    }
+
+    /// Read a stream, which is in the mini stream if it is small.
+    fn read_stream(&self, entry: &DirEntry) -> Option<Vec<u8>> {
+        if entry.size < self.cutoff {
+            self.read_mini_chain(entry.start, entry.size)
+        } else {
+            self.read_chain(entry.start, entry.size)
+        }
+    }
+
+    fn read_minifat(&self) -> Option<Vec<u32>> {
+        match self.header32(60) {
+            END_OF_CHAIN => Some(vec![]),
+            start => Some(le32s(&self.read_chain(start, u64::MAX)?).collect()),
+        }
+    }
+
+    fn read_dir(&self) -> Option<Vec<DirEntry>> {
+        let v4 = le16(&self.header, 26) == Some(4);
+        let buf = self.read_chain(self.header32(48), u64::MAX)?;
+        Some(buf.chunks_exact(DIR_ENTRY_SIZE).map(|entry| DirEntry::parse(entry, v4)).collect())
+    }
+
+    /// Read the mini stream, which is the stream of the root storage.
+    fn read_ministream(&self) -> Option<Vec<u8>> {
+        match self.root() {
+            Some(root) if root.start != END_OF_CHAIN && root.size > 0 => self.read_chain(root.start, root.size),
+            _ => Some(vec![]),
+        }
+    }
+
+    fn root(&self) -> Option<&DirEntry> {
+        self.dir.iter().find(|entry| entry.typ == DIR_ROOT)
+    }
+
+    /// If the root storage is of a Windows Installer file.
+    fn is_msi(&self) -> bool {
+        self.root().is_some_and(|root| root.clsid == MSI_CLSID)
+    }
+
+    fn find(&self, name: &str) -> Option<&DirEntry> {
+        self.dir.iter().find(|entry| entry.typ != 0 && entry.name == name)
+    }
+
+    /// Read the tables and describe the document, or return what cannot be read.
+    fn describe(&mut self, flags: Flags) -> Result<String, &'static str> {
+        self.fat = self.read_fat().ok_or("Can't read SAT")?;
+        self.minifat = self.read_minifat().ok_or("Can't read SSAT")?;
+        self.dir = self.read_dir().ok_or("Can't read directory")?;
+        self.ministream = self.read_ministream().ok_or("Cannot read short stream")?;
+
+        let mime = flags.intersects(Flags::MIME);
+        let mime_type = flags.contains(Flags::MIME_TYPE);
+        let summary = match self.find("\u{5}SummaryInformation") {
+            Some(summary) => summary,
+            None => {
+                if let Some(catalog) = self.find("Catalog").and_then(|entry| self.read_stream(entry)) {
+                    return Ok(if !mime {
+                        format!("Microsoft Thumbs.db [{}]", thumbnails(&catalog).join(", "))
+                    } else if mime_type {
+                        "application/CDFV2".to_string()
+                    } else {
+                        String::new()
+                    });
+                }
+                let (desc, subtype, _) = SECTIONS.iter()
+                    .find(|(_, _, names)| names.iter().any(|name| self.find(name).is_some()))
+                    .ok_or("Cannot read summary info")?;
+                return Ok(if !mime {
+                    format!("CDFV2 {}", desc)
+                } else if mime_type {
+                    format!("application/{}", subtype)
+                } else {
+                    String::new()
+                });
+            }
+        };
+        let stream = self.read_stream(summary).ok_or("Cannot read summary info")?;
+        let info = SummaryInfo::parse(&stream).ok_or("Can't expand summary_info")?;
+        if mime_type {
+            Ok(format!("application/{}", self.mime_type(&info)))
+        } else if mime {
+            Ok(String::new())
+        } else {
+            Ok(self.describe_summary(&info))
+        }
+    }
+
+    /// The MIME subtype by the class of the root storage, the name of the creating
+    /// application or the names of the streams.
+    fn mime_type(&self, info: &SummaryInfo) -> &'static str {
+        if self.is_msi() {
+            return "x-msi";
+        }
+        let contains = |s: &str, pattern: &str| s.to_lowercase().contains(&pattern.to_lowercase());
+        let app = info.props.iter().find_map(|(id, value)| match value {
+            Value::Str(app) if *id == PID_APPNAME => Some(app),
+            _ => None,
+        });
+        if let Some(subtype) = app.and_then(|app| APP_MIME.iter().find(|(name, _)| contains(app, name))) {
+            return subtype.1;
+        }
+        self.dir.iter()
+            .find_map(|entry| STREAM_MIME.iter().find(|(name, _)| contains(&entry.name, name)))
+            .map_or("vnd.ms-office", |(_, subtype)| subtype)
+    }
+
+    /// Describe the document by its summary information, as `cdf_file_summary_info` of
+    /// libmagic.
+    fn describe_summary(&self, info: &SummaryInfo) -> String {
+        let mut out = DOCUMENT.to_string();
+        out.push_str(if info.byte_order == 0xfffe { ", Little Endian" } else { ", Big Endian" });
+        let (major, minor) = (info.os_version & 0xff, info.os_version >> 8);
+        match info.os {
+            2 => out.push_str(&format!(", Os: Windows, Version {}.{}", major, minor)),
+            1 => out.push_str(&format!(", Os: MacOS, Version {}.{}", minor, major)),
+            os => out.push_str(&format!(", Os {}, Version: {}.{}", os, major, minor)),
+        }
+        if self.is_msi() {
+            out.push_str(", MSI Installer");
+        }
+        for (id, value) in &info.props {
+            let value = match value {
+                Value::Int(n) => n.to_string(),
+                Value::UInt(n) => n.to_string(),
+                Value::Float(n) => print::sprintf("%g", &FmtArg::Float(*n)),
+                Value::Str(s) if !s.is_empty() => s.clone(),
+                Value::Time(t) if *t >= MAX_DURATION => print::fmt_time(*t, TimeKind::Windows),
+                Value::Time(t) if *t > 0 => fmt_duration(*t),
+                _ => continue,
+            };
+            out.push_str(&format!(", {}: {}", property_name(*id), value));
+        }
+        out
+    }
+}
+
+
+/// The value of a property.
+#[derive(Debug, PartialEq)]
+enum Value {
+    Int(i64),
+    UInt(u32),
+    Float(f64),
+    Str(String),
+    /// 100-nanoseconds, since 1601-01-01 or of a duration
+    Time(u64),
+}
+
+/// The summary information property set, as `cdf_summary_info_header_t` and the properties
+/// of its first section.
+#[derive(Debug)]
+struct SummaryInfo {
+    byte_order: u16,
+    os_version: u16,
+    os: u16,
+    props: Vec<(u32, Value)>,
+}
+
+impl SummaryInfo {
+    /// Parse the summary information, where the properties of the unknown types are left
+    /// out, as `cdf_unpack_summary_info` of libmagic.
+    fn parse(buf: &[u8]) -> Option<SummaryInfo> {
+        let mut info = SummaryInfo {
+            byte_order: le16(buf, 0)?,
+            os_version: le16(buf, 4)?,
+            os: le16(buf, 6)?,
+            props: vec![],
+        };
+        if le32(buf, 24)? == 0 {
+            return Some(info);
+        }
+        // the format ID of the first section is followed by its offset
+        let section = le32(buf, 44)? as usize;
+        let num = le32(buf, section.checked_add(4)?)? as usize;
+        for i in 0..num {
+            let entry = section + 8 + i * 8;
+            let id = le32(buf, entry)?;
+            let off = section.checked_add(le32(buf, entry + 4)? as usize)?;
+            if let Some(value) = SummaryInfo::value(buf, off) {
+                info.props.push((id, value));
+            }
+        }
+        Some(info)
+    }
+
+    /// Parse the type and the value of a property, as `cdf_read_property_info` of libmagic.
+    fn value(buf: &[u8], off: usize) -> Option<Value> {
+        let off_val = off.checked_add(4)?;
+        match le32(buf, off)? {
+            VT_I2 => le16(buf, off_val).map(|n| Value::Int(i64::from(n as i16))),
+            VT_I4 => le32(buf, off_val).map(|n| Value::Int(i64::from(n as i32))),
+            VT_UI4 => le32(buf, off_val).map(Value::UInt),
+            VT_R4 => le32(buf, off_val).map(|n| Value::Float(f64::from(f32::from_bits(n)))),
+            VT_R8 => le64(buf, off_val).map(|n| Value::Float(f64::from_bits(n))),
+            VT_FILETIME => le64(buf, off_val).map(Value::Time),
+            typ @ (VT_LPSTR | VT_LPWSTR) => {
+                // only the printable ASCII chars are kept, as the code page is not known
+                let len = le32(buf, off_val)? as usize;
+                let step = if typ == VT_LPWSTR { 2 } else { 1 };
+                let s = buf.get(off_val + 4..)?.iter()
+                    .step_by(step)
+                    .take(len)
+                    .take_while(|&&c| c != 0)
+                    .filter(|c| c.is_ascii_graphic() || **c == b' ')
+                    .take(MAX_STRING)
+                    .map(|&c| char::from(c))
+                    .collect();
+                Some(Value::Str(s))
+            }
+            _ => None,
+        }
+    }
+}
+
+
+/// The name of a property of the summary information, as `cdf_print_property_name` of
+/// libmagic.
+fn property_name(id: u32) -> String {
+    let name = match id {
+        1 => "Code page",
+        2 => "Title",
+        3 => "Subject",
+        4 => "Author",
+        5 => "Keywords",
+        6 => "Comments",
+        7 => "Template",
+        8 => "Last Saved By",
+        9 => "Revision Number",
+        10 => "Total Editing Time",
+        11 => "Last Printed",
+        12 => "Create Time/Date",
+        13 => "Last Saved Time/Date",
+        14 => "Number of Pages",
+        15 => "Number of Words",
+        16 => "Number of Characters",
+        17 => "Thumbnail",
+        18 => "Name of Creating Application",
+        19 => "Security",
+        0x8000_0000 => "Locale ID",
+        _ => return format!("{:#x}", id),
+    };
+    name.to_string()
+}
+
+/// Format a duration in 100-nanoseconds, such as `1d+02:03:04`, as
+/// `cdf_print_elapsed_time` of libmagic.
+fn fmt_duration(t: u64) -> String {
+    let secs = t / 10_000_000;
+    let (days, hours) = (secs / 86400, secs / 3600 % 24);
+    let mut out = String::new();
+    if days > 0 {
+        out.push_str(&format!("{}d+", days));
+    }
+    if days > 0 || hours > 0 {
+        out.push_str(&format!("{:02}:", hours));
+    }
+    out.push_str(&format!("{:02}:{:02}", secs / 60 % 60, secs % 60));
+    out
+}
+
+/// The names of the pictures of the catalog of a `Thumbs.db`, where each record starts
+/// with its length, and the first one is the header.
+fn thumbnails(catalog: &[u8]) -> Vec<String> {
+    let mut names = vec![];
+    let mut off = 0;
+    while let Some(len) = le16(catalog, off).filter(|&len| len != 0) {
+        if off > 0 {
+            names.push(utf16(catalog.get(off + 16..).unwrap_or_default()));
+        }
+        off += usize::from(len);
+    }
+    names
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{fmt_duration, trycdf, Cdf, END_OF_CHAIN, MSI_CLSID};
+    use crate::magic_set::{Flags, Source};
+
+    const FREE: u32 = 0xffff_ffff;
+
+    fn dir_entry(buf: &mut Vec<u8>, name: &str, typ: u8, clsid: [u8; 16], start: u32, size: u32) {
+        let mut name: Vec<u8> = name.encode_utf16().chain([0]).flat_map(u16::to_le_bytes).collect();
+        let name_len = name.len() as u16;
+        name.resize(64, 0);
+        buf.extend_from_slice(&name);
+        buf.extend_from_slice(&name_len.to_le_bytes());
+        buf.extend_from_slice(&[typ, 1]);
+        for _ in 0..3 {
+            buf.extend_from_slice(&FREE.to_le_bytes());
+        }
+        buf.extend_from_slice(&clsid);
+        buf.extend_from_slice(&[0; 20]);
+        buf.extend_from_slice(&start.to_le_bytes());
+        buf.extend_from_slice(&size.to_le_bytes());
+        buf.extend_from_slice(&[0; 4]);
+    }
+
+    /// Build the summary information of a document created by an application.
+    fn summary(app: &str) -> Vec<u8> {
+        let mut values: Vec<(u32, Vec<u8>)> = vec![
+            (1, [&2u32.to_le_bytes()[..], &1252u16.to_le_bytes(), &[0, 0]].concat()),
+            (2, [&0x1eu32.to_le_bytes()[..], &7u32.to_le_bytes(), b"Report\0\0"].concat()),
+            (10, [&0x40u32.to_le_bytes()[..], &(3723u64 * 10_000_000).to_le_bytes()].concat()),
+        ];
+        let mut wide: Vec<u8> = "Alice\0".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        wide.resize(12, 0);
+        values.push((4, [&0x1fu32.to_le_bytes()[..], &6u32.to_le_bytes(), &wide].concat()));
+        let mut name = app.as_bytes().to_vec();
+        name.push(0);
+        let len = name.len() as u32;
+        name.resize(name.len().div_ceil(4) * 4, 0);
+        values.push((18, [&0x1eu32.to_le_bytes()[..], &len.to_le_bytes(), &name].concat()));
+        let created = (1_700_000_000u64 + 11_644_473_600) * 10_000_000;
+        values.push((12, [&0x40u32.to_le_bytes()[..], &created.to_le_bytes()].concat()));
+
+        let mut buf = vec![0xfe, 0xff, 0, 0, 0x06, 0x01, 2, 0];
+        buf.extend_from_slice(&[0; 16]);
+        buf.extend_from_slice(&1u32.to_le_bytes());
+        buf.extend_from_slice(&[0xe0; 16]);
+        buf.extend_from_slice(&48u32.to_le_bytes());
+        let mut section = vec![];
+        let mut off = 8 + 8 * values.len();
+        for (id, value) in &values {
+            section.extend_from_slice(&id.to_le_bytes());
+            section.extend_from_slice(&(off as u32).to_le_bytes());
+            off += value.len();
+        }
+        for (_, value) in &values {
+            section.extend_from_slice(value);
+        }
+        buf.extend_from_slice(&(section.len() as u32 + 8).to_le_bytes());
+        buf.extend_from_slice(&(values.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&section);
+        buf
+    }
+
+    /// Build a document of 512-byte sectors with the FAT, the directory, the mini FAT and
+    /// the mini stream, where the streams are in the mini stream.
+    fn build_cdf(streams: &[(&str, Vec<u8>)], clsid: [u8; 16]) -> Vec<u8> {
+        let mut ministream = vec![];
+        let mut minifat = vec![];
+        let mut entries = vec![];
+        for (name, data) in streams {
+            let start = minifat.len() as u32;
+            let sectors = data.len().div_ceil(64);
+            for i in 1..sectors {
+                minifat.push(start + i as u32);
+            }
+            minifat.push(END_OF_CHAIN);
+            ministream.extend_from_slice(data);
+            ministream.resize(minifat.len() * 64, 0);
+            entries.push((name, start, data.len()));
+        }
+        let ministream_sectors = ministream.len().div_ceil(512);
+        ministream.resize(ministream_sectors * 512, 0);
+        minifat.resize(128, FREE);
+
+        // the FAT, the directory, the mini FAT and the mini stream, in this order
+        let mut fat = vec![0xffff_fffd, END_OF_CHAIN, END_OF_CHAIN];
+        for i in 1..ministream_sectors {
+            fat.push(3 + i as u32);
+        }
+        fat.push(END_OF_CHAIN);
+        fat.resize(128, FREE);
+
+        let mut buf = super::MAGIC.to_vec();
+        buf.extend_from_slice(&[0; 16]);
+        for n in [0x3eu16, 3, 0xfffe, 9, 6] {
+            buf.extend_from_slice(&n.to_le_bytes());
+        }
+        buf.extend_from_slice(&[0; 6]);
+        for n in [0, 1, 1, 0, 4096, 2, 1, END_OF_CHAIN, 0, 0] {
+            buf.extend_from_slice(&u32::to_le_bytes(n));
+        }
+        buf.resize(512, 0xff);
+        buf.extend(fat.iter().flat_map(|n| n.to_le_bytes()));
+        dir_entry(&mut buf, "Root Entry", 5, clsid, 3, ministream.len() as u32);
+        for (name, start, size) in entries {
+            dir_entry(&mut buf, name, 2, [0; 16], start, size as u32);
+        }
+        buf.resize(3 * 512, 0);
+        buf.extend(minifat.iter().flat_map(|n| n.to_le_bytes()));
+        buf.extend_from_slice(&ministream);
+        buf
+    }
+
+    #[test]
+    fn test_trycdf() {
+        let word = build_cdf(&[
+            ("\u{5}SummaryInformation", summary("Microsoft Office Word")),
+            ("WordDocument", vec![0; 10]),
+        ], [0; 16]);
+        let run = |buf: &[u8], flags: Flags| trycdf(Source::Buffer(buf), flags);
+        assert_eq!(
+            run(&word, Flags::NONE).unwrap(),
+            "Composite Document File V2 Document, Little Endian, Os: Windows, Version 6.1, \
+             Code page: 1252, Title: Report, Total Editing Time: 01:02:03, Author: Alice, \
+             Name of Creating Application: Microsoft Office Word, \
+             Create Time/Date: Tue Nov 14 22:13:20 2023"
+        );
+        assert_eq!(run(&word, Flags::MIME_TYPE).unwrap(), "application/msword");
+        assert_eq!(run(&word, Flags::MIME_ENCODING), None);
+
+        let excel = build_cdf(&[("\u{5}SummaryInformation", summary("Microsoft Excel"))], [0; 16]);
+        assert_eq!(run(&excel, Flags::MIME_TYPE).unwrap(), "application/vnd.ms-excel");
+        let msi = build_cdf(&[("\u{5}SummaryInformation", summary("Windows Installer XML"))], MSI_CLSID);
+        assert_eq!(run(&msi, Flags::MIME_TYPE).unwrap(), "application/x-msi");
+        assert!(run(&msi, Flags::NONE).unwrap().contains(", Version 6.1, MSI Installer, Code page: 1252"));
+        let other = build_cdf(&[("\u{5}SummaryInformation", summary("Other")), ("Workbook", vec![1])], [0; 16]);
+        assert_eq!(run(&other, Flags::MIME_TYPE).unwrap(), "application/vnd.ms-excel");
+
+        // without the summary information
+        let word = build_cdf(&[("WordDocument", vec![0; 10])], [0; 16]);
+        assert_eq!(run(&word, Flags::NONE).unwrap(), "CDFV2 Microsoft Word");
+        assert_eq!(run(&word, Flags::MIME_TYPE).unwrap(), "application/msword");
+        let mut catalog = [&16u16.to_le_bytes()[..], &[0; 14]].concat();
+        for name in ["a.jpg", "b.png"] {
+            let name: Vec<u8> = name.encode_utf16().chain([0, 0]).flat_map(u16::to_le_bytes).collect();
+            catalog.extend_from_slice(&(16 + name.len() as u16).to_le_bytes());
+            catalog.extend_from_slice(&[0; 14]);
+            catalog.extend_from_slice(&name);
+        }
+        let thumbs = build_cdf(&[("Catalog", catalog)], [0; 16]);
+        assert_eq!(run(&thumbs, Flags::NONE).unwrap(), "Microsoft Thumbs.db [a.jpg, b.png]");
+        let unknown = build_cdf(&[("Other", vec![0; 10])], [0; 16]);
+        assert_eq!(
+            run(&unknown, Flags::NONE).unwrap(),
+            "Composite Document File V2 Document, Cannot read summary info"
+        );
+
+        // a chain of the directory which loops
+        let mut looped = build_cdf(&[("WordDocument", vec![0; 10])], [0; 16]);
+        looped[512 + 4..512 + 8].copy_from_slice(&1u32.to_le_bytes());
+        assert_eq!(run(&looped, Flags::NONE).unwrap(), "Composite Document File V2 Document, Can't read directory");
+        assert_eq!(run(&looped, Flags::MIME_TYPE).unwrap(), "application/CDFV2");
+        // the chains of the mini stream and of a stream go on past the end of the file and
+        // of the mini stream, which are not read beyond the sizes
+        let mut long = word.clone();
+        long[512 + 3 * 4..512 + 4 * 4].copy_from_slice(&5u32.to_le_bytes());
+        long[512 + 5 * 4..512 + 6 * 4].copy_from_slice(&END_OF_CHAIN.to_le_bytes());
+        long[3 * 512..3 * 512 + 4].copy_from_slice(&100u32.to_le_bytes());
+        long[3 * 512 + 100 * 4..3 * 512 + 101 * 4].copy_from_slice(&END_OF_CHAIN.to_le_bytes());
+        assert_eq!(run(&long, Flags::NONE).unwrap(), "CDFV2 Microsoft Word");
+        assert_eq!(run(&word[..1024], Flags::NONE).unwrap(), "Composite Document File V2 Document, Can't read SSAT");
+        assert_eq!(run(&word[..600], Flags::NONE).unwrap(), "Composite Document File V2 Document, Can't read SAT");
+        assert_eq!(run(&word[..400], Flags::NONE), None);
+    }
+
+    #[test]
+    fn test_chain() {
+        let fat = [1, 2, END_OF_CHAIN, 3];
+        assert_eq!(Cdf::chain(&fat, 0, 10), Some(vec![0, 1, 2]));
+        assert_eq!(Cdf::chain(&fat, 0, 3), Some(vec![0, 1, 2]));
+        // longer than the limit
+        assert_eq!(Cdf::chain(&fat, 0, 2), None);
+        // a loop and a sector out of the FAT
+        assert_eq!(Cdf::chain(&fat, 3, 10), None);
+        assert_eq!(Cdf::chain(&[4], 0, 10), None);
+    }
+
+    #[test]
+    fn test_fmt_duration() {
+        assert_eq!(fmt_duration(59 * 10_000_000), "00:59");
+        assert_eq!(fmt_duration(3723 * 10_000_000), "01:02:03");
+        assert_eq!(fmt_duration(90000 * 10_000_000), "1d+01:00:00");
+    }
+}
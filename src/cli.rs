@@ -0,0 +1,542 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+use std::process;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use crate::apprentice;
+use crate::fmt_magic_file;
+use crate::lint_magic_file::{self, Severity};
+use crate::magic_param::{MagicParam, Param};
+use crate::magic_set::{Flags, MagicSet};
+use crate::parse_magic_entry::{MagicFile, SourceLine};
+use crate::print;
+use crate::softmagic::TraceLine;
+
+
+/// Print the parsed rules of the magic files, so that they can be inspected by other tools.
+///
+/// This is `magic-tool dump` rather than `magic dump`, as the arguments of `magic` are the
+/// files to classify, as of `file(1)`.
+fn dump(matches: &ArgMatches) -> Result<(), String> {
+    let mut magic_files = vec![];
+    for path in matches.values_of("MAGIC").unwrap() {
+        let path = Path::new(path);
+        let files = MagicFile::list(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        for file in files {
+            let magic_file = MagicFile::parse(&file)
+                .map_err(|e| format!("{}: {}", file.display(), e))?;
+            for warning in magic_file.warnings() {
+                eprintln!("{}", warning);
+            }
+            magic_files.push(magic_file);
+        }
+    }
+
+    let mut out = io::stdout().lock();
+    if matches.is_present("json") {
+        let json = serde_json::to_string_pretty(&magic_files).map_err(|e| e.to_string())?;
+        check_write(writeln!(out, "{}", json))?;
+    } else {
+        for magic_file in magic_files {
+            check_write(write!(out, "{}", magic_file))?;
+        }
+    }
+    check_write(out.flush())
+}
+
+
+/// Format the magic files in place, or only check if they are formatted with `--check`, as
+/// `magic-tool fmt`.
+///
+/// Return false if any file is not formatted in the check mode.
+fn format(matches: &ArgMatches) -> Result<bool, String> {
+    let check = matches.is_present("check");
+    let mut formatted = true;
+    let mut stdout = io::stdout().lock();
+    for path in matches.values_of("MAGIC").unwrap() {
+        let path = Path::new(path);
+        let files = MagicFile::list(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        for file in files {
+            let raw = fs::read(&file)
+                .map_err(|e| format!("{}: {}", file.display(), e))?;
+            if std::str::from_utf8(&raw).is_err() {
+                eprintln!("{}: Warning: not valid utf-8, skipped", file.display());
+                continue;
+            }
+
+            let lines = MagicFile::lines_from_bytes(&file, &raw);
+            for (loc, line) in &lines {
+                if let SourceLine::Invalid { msg, .. } = line {
+                    eprintln!("{}: Warning: {}", loc, msg);
+                }
+            }
+
+            let out = fmt_magic_file::format_lines(&lines);
+            if out.as_bytes() == raw.as_slice() {
+                continue;
+            }
+            if check {
+                check_write(writeln!(stdout, "{}", file.display()))?;
+                formatted = false;
+            } else {
+                fs::write(&file, out)
+                    .map_err(|e| format!("{}: {}", file.display(), e))?;
+            }
+        }
+    }
+    check_write(stdout.flush())?;
+    Ok(formatted)
+}
+
+
+/// Check the magic files for mistakes which libmagic accepts silently or only warns about, as
+/// `magic-tool lint`.
+///
+/// Return false if any error is found.
+fn lint(matches: &ArgMatches) -> Result<bool, String> {
+    let mut files = vec![];
+    for path in matches.values_of("MAGIC").unwrap() {
+        let path = Path::new(path);
+        for file in MagicFile::list(path).map_err(|e| format!("{}: {}", path.display(), e))? {
+            let lines = MagicFile::parse_lines(&file)
+                .map_err(|e| format!("{}: {}", file.display(), e))?;
+            files.push(lines);
+        }
+    }
+
+    let findings = lint_magic_file::lint_files(&files);
+    let mut out = io::stdout().lock();
+    for finding in &findings {
+        check_write(writeln!(out, "{}", finding))?;
+    }
+    check_write(out.flush())?;
+    Ok(findings.iter().all(|finding| finding.severity() != Severity::Error))
+}
+
+
+/// How the result of each file is printed, as `file(1)`.
+struct Output<'a> {
+    brief: bool,
+    raw: bool,
+    /// The times of `-0`: once puts a NUL after the file name, twice also ends the
+    /// result with a NUL
+    nulsep: u64,
+    separator: &'a str,
+    no_pad: bool,
+    /// If the magic lines tried on the contents are printed to the standard error
+    trace: bool,
+}
+
+impl Output<'_> {
+    /// Print the result of a file to `out`, where `width` is of the longest file name for
+    /// padding.
+    ///
+    /// Return false if the file fails to classify.
+    fn process(&self, out: &mut impl Write, ms: &MagicSet, name: &str, width: usize) -> io::Result<bool> {
+        let stdin = name == "-";
+        let shown = if stdin { "/dev/stdin" } else { name };
+        let shown = if self.raw { shown.to_string() } else { print::octalify(shown) };
+        if !self.brief {
+            write!(out, "{}", shown)?;
+            if self.nulsep > 0 {
+                write!(out, "\0")?;
+            }
+            if self.nulsep < 2 {
+                let pad = if self.no_pad { 0 } else { width.saturating_sub(name.chars().count()) };
+                write!(out, "{}{:pad$} ", self.separator, "", pad = pad)?;
+            }
+        }
+
+        // the contents are read once more for the trace, except the standard input
+        let mut trace = None;
+        let res = if stdin && self.trace {
+            let mut buf = vec![];
+            match io::stdin().lock().read_to_end(&mut buf) {
+                Ok(_) => {
+                    trace = Some(ms.buffer_trace(&buf));
+                    ms.buffer(&buf)
+                }
+                Err(e) => Err(format!("cannot read ({})", e)),
+            }
+        } else if stdin {
+            ms.reader(io::stdin().lock())
+        } else {
+            if self.trace && Path::new(name).is_file() {
+                let bytes = ms.param().get(Param::Bytes) as u64;
+                let mut buf = vec![];
+                if fs::File::open(name).and_then(|file| file.take(bytes).read_to_end(&mut buf)).is_ok() {
+                    trace = Some(ms.buffer_trace(&buf));
+                }
+            }
+            ms.file(Path::new(name))
+        };
+        let end = if self.nulsep > 1 { '\0' } else { '\n' };
+        let ok = match res {
+            Ok(desc) => {
+                write!(out, "{}{}", desc, end)?;
+                true
+            }
+            Err(msg) => {
+                write!(out, "ERROR: {}{}", msg, end)?;
+                false
+            }
+        };
+        match trace {
+            Some(Ok(trace)) => {
+                out.flush()?;
+                print_trace(&trace);
+            }
+            Some(Err(msg)) => eprintln!("trace: {}", msg),
+            None => {}
+        }
+        Ok(ok)
+    }
+
+    /// Return the width of the longest name, unless padding is disabled.
+    fn width(&self, names: &[String]) -> usize {
+        if self.no_pad {
+            return 0;
+        }
+        names.iter().map(|name| name.chars().count()).max().unwrap_or(0)
+    }
+}
+
+
+/// Exit quietly if the reader of the standard output has gone, as `file(1)` is killed by
+/// SIGPIPE, or fail with the error otherwise.
+fn check_write<T>(res: io::Result<T>) -> Result<T, String> {
+    match res {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => process::exit(0),
+        res => res.map_err(|e| format!("cannot write to the standard output ({})", e)),
+    }
+}
+
+
+/// Print the magic lines tried on a file as a tree by their depth, each with the value it
+/// reads and if it matches.
+fn print_trace(trace: &[TraceLine]) {
+    for line in trace {
+        let indent = "  ".repeat(line.depth);
+        eprintln!("{}{}:{}: {}", indent, line.path.display(), line.line_no, line.rule);
+        let read = match (line.offset, &line.value) {
+            (None, _) => "out of the buffer".to_string(),
+            (Some(offset), None) => format!("at {}", offset),
+            (Some(offset), Some(value)) => {
+                let raw: Vec<String> = line.raw.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("at {} [{}] value {}", offset, raw.join(" "), value)
+            }
+        };
+        eprintln!("{}  {}: {}", indent, read, if line.matched { "match" } else { "no match" });
+    }
+}
+
+
+/// Read the file names listed line by line in a file, or the standard input for `-`.
+fn read_names(namefile: &str) -> Result<Vec<String>, String> {
+    let read = |reader: &mut dyn BufRead| -> io::Result<Vec<String>> {
+        reader.lines().collect()
+    };
+    let names = if namefile == "-" {
+        read(&mut io::stdin().lock())
+    } else {
+        fs::File::open(namefile).and_then(|file| read(&mut io::BufReader::new(file)))
+    };
+    names.map_err(|e| format!("Cannot open `{}' ({})", namefile, e))
+}
+
+
+/// Build the magic set from the `file(1)` options.
+fn magic_set(matches: &ArgMatches) -> Result<MagicSet, String> {
+    let mut flags = Flags::NONE;
+    let switches = [
+        ("mime", Flags::MIME),
+        ("mime-type", Flags::MIME_TYPE),
+        ("mime-encoding", Flags::MIME_ENCODING),
+        ("extension", Flags::EXTENSION),
+        ("apple", Flags::APPLE),
+        ("checking-printout", Flags::CHECK),
+        ("keep-going", Flags::CONTINUE),
+        ("raw", Flags::RAW),
+        ("special-files", Flags::DEVICES),
+        ("uncompress", Flags::COMPRESS),
+        ("uncompress-noreport", Flags::COMPRESS | Flags::COMPRESS_TRANSP),
+    ];
+    for (name, flag) in switches.iter() {
+        if matches.is_present(name) {
+            flags |= *flag;
+        }
+    }
+
+    // the last one of `-L' and `-h' wins, and symlinks are followed by default only for POSIX
+    let follow = match (matches.index_of("dereference"), matches.index_of("no-dereference")) {
+        (Some(l), Some(h)) => l > h,
+        (Some(_), None) => true,
+        (None, Some(_)) => false,
+        (None, None) => env::var_os("POSIXLY_CORRECT").is_some(),
+    };
+    if follow {
+        flags |= Flags::SYMLINK;
+    }
+
+    for name in matches.values_of("exclude").into_iter().flatten() {
+        flags |= Flags::from_check_name(name)
+            .ok_or_else(|| format!("invalid test `{}' for -e", name))?;
+    }
+
+    let mut ms = MagicSet::new(flags)?;
+    if let Some(params) = matches.values_of("parameter") {
+        ms.set_param(MagicParam::from_cmd_line(&params.collect::<Vec<_>>().join(";"))?);
+    }
+
+    let paths = match matches.value_of("magic-file") {
+        Some(magic) => apprentice::split_paths(magic),
+        None => apprentice::default_paths(),
+    };
+    ms.load(&paths)?;
+    for path in matches.values_of("tokens").into_iter().flatten() {
+        ms.load_tokens(Path::new(path))?;
+    }
+    Ok(ms)
+}
+
+
+/// Classify the files as `file(1)`.
+///
+/// Return false if any file fails to classify.
+fn classify(matches: &ArgMatches) -> Result<bool, String> {
+    let ms = magic_set(matches)?;
+    let output = Output {
+        brief: matches.is_present("brief"),
+        raw: matches.is_present("raw"),
+        nulsep: matches.occurrences_of("print0"),
+        separator: matches.value_of("separator").unwrap_or(":"),
+        no_pad: matches.is_present("no-pad"),
+        trace: matches.is_present("trace"),
+    };
+
+    let mut ok = true;
+    let mut out = io::stdout().lock();
+    if let Some(namefile) = matches.value_of("files-from") {
+        let names = read_names(namefile)?;
+        let width = output.width(&names);
+        for name in &names {
+            ok &= check_write(output.process(&mut out, &ms, name, width))?;
+        }
+    }
+    if let Some(files) = matches.values_of("FILE") {
+        let names: Vec<String> = files.map(String::from).collect();
+        let width = output.width(&names);
+        for name in &names {
+            ok &= check_write(output.process(&mut out, &ms, name, width))?;
+        }
+    }
+    check_write(out.flush())?;
+    Ok(ok)
+}
+
+
+/// Run the `magic` command with the arguments of the process.
+pub fn main() {
+    let matches = App::new("magic")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Determine file type by magic rules")
+        .after_help("The magic files are dumped, formatted and checked by `magic-tool dump`, \
+            `magic-tool fmt` and `magic-tool lint`.")
+        .arg(Arg::with_name("brief")
+            .short("b")
+            .long("brief")
+            .help("Do not prepend filenames to output lines"))
+        .arg(Arg::with_name("mime")
+            .short("i")
+            .long("mime")
+            .help("Output MIME type strings"))
+        .arg(Arg::with_name("mime-type")
+            .long("mime-type")
+            .help("Output the MIME type"))
+        .arg(Arg::with_name("mime-encoding")
+            .long("mime-encoding")
+            .help("Output the MIME encoding"))
+        .arg(Arg::with_name("apple")
+            .long("apple")
+            .help("Output the Apple creator and type codes"))
+        .arg(Arg::with_name("extension")
+            .long("extension")
+            .help("Output a slash-separated list of extensions"))
+        .arg(Arg::with_name("checking-printout")
+            .short("c")
+            .long("checking-printout")
+            .help("Print the warnings of the magic files"))
+        .arg(Arg::with_name("magic-file")
+            .short("m")
+            .long("magic-file")
+            .takes_value(true)
+            .value_name("LIST")
+            .help("Use the colon separated list of magic files"))
+        .arg(Arg::with_name("tokens")
+            .long("tokens")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("FILE")
+            .help("Add the tokens of FILE to the ones which tell the kinds of text"))
+        .arg(Arg::with_name("keep-going")
+            .short("k")
+            .long("keep-going")
+            .help("Do not stop at the first match"))
+        .arg(Arg::with_name("raw")
+            .short("r")
+            .long("raw")
+            .help("Do not translate unprintable chars to \\ooo"))
+        .arg(Arg::with_name("print0")
+            .short("0")
+            .long("print0")
+            .multiple(true)
+            .help("Terminate filenames with ASCII NUL, and the output too if given twice"))
+        .arg(Arg::with_name("separator")
+            .short("F")
+            .long("separator")
+            .takes_value(true)
+            .value_name("STRING")
+            .help("Use STRING as the separator instead of `:'"))
+        .arg(Arg::with_name("files-from")
+            .short("f")
+            .long("files-from")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Read the filenames to be examined from FILE"))
+        .arg(Arg::with_name("dereference")
+            .short("L")
+            .long("dereference")
+            .help("Follow symlinks"))
+        .arg(Arg::with_name("no-dereference")
+            .short("h")
+            .long("no-dereference")
+            .help("Do not follow symlinks"))
+        .arg(Arg::with_name("special-files")
+            .short("s")
+            .long("special-files")
+            .help("Treat special (block/char devices) files as ordinary ones"))
+        .arg(Arg::with_name("uncompress")
+            .short("z")
+            .long("uncompress")
+            .help("Try to look inside compressed files"))
+        .arg(Arg::with_name("uncompress-noreport")
+            .short("Z")
+            .long("uncompress-noreport")
+            .help("Only print the contents of compressed files"))
+        .arg(Arg::with_name("exclude")
+            .short("e")
+            .long("exclude")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("TEST")
+            .help("Exclude TEST from the list of tests, which is one of apptype, ascii, cdf, \
+                compress, csv, elf, encoding, json, simh, soft, tar, text and tokens"))
+        .arg(Arg::with_name("parameter")
+            .short("P")
+            .long("parameter")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("NAME=VALUE")
+            .help("Set a parameter of the limits"))
+        .arg(Arg::with_name("trace")
+            .long("trace")
+            .help("Print the magic lines tried on each file and why they match or not to the \
+                standard error"))
+        .arg(Arg::with_name("no-pad")
+            .short("N")
+            .long("no-pad")
+            .help("Do not pad the filenames so that they align"))
+        .arg(Arg::with_name("FILE")
+            .help("Files to classify, or `-' for the standard input")
+            .multiple(true))
+        .get_matches();
+
+    let res = if matches.is_present("FILE") || matches.is_present("files-from") {
+        match classify(&matches) {
+            Ok(true) => Ok(()),
+            Ok(false) => process::exit(1),
+            Err(msg) => Err(msg),
+        }
+    } else {
+        eprintln!("{}", matches.usage());
+        process::exit(2);
+    };
+
+    if let Err(msg) = res {
+        eprintln!("magic: {}", msg);
+        process::exit(1);
+    }
+}
+
+
+/// Run the `magic-tool` command, which works on the magic files, with the arguments of the
+/// process.
+///
+/// Its subcommands `dump`, `fmt` and `lint` are not of `magic`, where they would be taken for
+/// the names of files to classify.
+pub fn tool_main() {
+    let matches = App::new("magic-tool")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Inspect, format and check magic files, which `magic` leaves to this command as \
+            its arguments are the files to classify")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("dump")
+                .about("Dump the parsed rules of magic files")
+                .arg(Arg::with_name("json")
+                    .long("json")
+                    .help("Dump as json instead of the canonical magic(5) syntax"))
+                .arg(Arg::with_name("MAGIC")
+                    .help("Magic files, or directories of magic files")
+                    .required(true)
+                    .multiple(true))
+        )
+        .subcommand(
+            SubCommand::with_name("fmt")
+                .about("Format magic files in place")
+                .arg(Arg::with_name("check")
+                    .long("check")
+                    .help("Only list the files which are not formatted, and exit with 1 if any"))
+                .arg(Arg::with_name("MAGIC")
+                    .help("Magic files, or directories of magic files")
+                    .required(true)
+                    .multiple(true))
+        )
+        .subcommand(
+            SubCommand::with_name("lint")
+                .about("Check magic files for mistakes")
+                .arg(Arg::with_name("MAGIC")
+                    .help("Magic files, or directories of magic files")
+                    .required(true)
+                    .multiple(true))
+        )
+        .get_matches();
+
+    let res = match matches.subcommand() {
+        ("dump", Some(sub_matches)) => dump(sub_matches),
+        ("fmt", Some(sub_matches)) => match format(sub_matches) {
+            Ok(true) => Ok(()),
+            Ok(false) => process::exit(1),
+            Err(msg) => Err(msg),
+        },
+        ("lint", Some(sub_matches)) => match lint(sub_matches) {
+            Ok(true) => Ok(()),
+            Ok(false) => process::exit(1),
+            Err(msg) => Err(msg),
+        },
+        _ => unreachable!(),
+    };
+
+    if let Err(msg) = res {
+        eprintln!("magic-tool: {}", msg);
+        process::exit(1);
+    }
+}
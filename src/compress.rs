@@ -0,0 +1,160 @@
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+
+use lzma_rs::decompress::UnpackedSize;
+
+
+/// The most a stream is taken to expand by, beyond which the rest is not decompressed, so
+/// that a small decompression bomb cannot cost the whole size limit.
+const MAX_RATIO: usize = 1024;
+
+
+/// The compressed formats which are looked into, as the `compr` table of libmagic.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Method {
+    Gzip,
+    Bzip2,
+    Xz,
+    Lzma,
+    Zstd,
+    Lz4,
+}
+
+impl Method {
+    /// Detect the format by the magic number at the start of a buffer.
+    pub(crate) fn detect(buf: &[u8]) -> Option<Method> {
+        let magics: [(&[u8], Method); 6] = [
+            (b"\x1f\x8b", Method::Gzip),
+            (b"BZh", Method::Bzip2),
+            (b"\xfd7zXZ\0", Method::Xz),
+            // the default properties and dictionary of 8 MiB, as libmagic, since `]\0\0` alone
+            // is too common
+            (b"]\0\0\x80\0", Method::Lzma),
+            (b"\x28\xb5\x2f\xfd", Method::Zstd),
+            (b"\x04\x22\x4d\x18", Method::Lz4),
+        ];
+        magics.iter()
+            .find(|(magic, _)| buf.starts_with(magic))
+            .map(|(_, method)| *method)
+    }
+}
+
+
+/// Decompress the start of a compressed buffer up to `max` bytes, as `uncompressbuf` of
+/// libmagic, or return `None` if it is not compressed or nothing can be decompressed.
+///
+/// A buffer which is cut off or corrupted is decompressed as far as it goes.
+pub(crate) fn decompress(buf: &[u8], max: usize) -> Option<Vec<u8>> {
+    let method = Method::detect(buf)?;
+    let max = max.min(buf.len().saturating_mul(MAX_RATIO)) as u64;
+    let mut out = vec![];
+    // the error is ignored to keep what is decompressed before it
+    let _ = match method {
+        Method::Gzip => flate2::read::MultiGzDecoder::new(buf).take(max).read_to_end(&mut out),
+        Method::Bzip2 => bzip2_rs::DecoderReader::new(buf).take(max).read_to_end(&mut out),
+        Method::Zstd => match ruzstd::decoding::StreamingDecoder::new(buf) {
+            Ok(decoder) => decoder.take(max).read_to_end(&mut out),
+            Err(_) => return None,
+        },
+        Method::Lz4 => lz4_flex::frame::FrameDecoder::new(buf).take(max).read_to_end(&mut out),
+        Method::Xz => {
+            let mut bounded = Bounded { out: &mut out, max: max as usize };
+            lzma_rs::xz_decompress(&mut &buf[..], &mut bounded).map(|_| 0).map_err(lzma_error)
+        }
+        Method::Lzma => {
+            // the data is kept in memory up to the size of the dictionary before it is
+            // written, so stop at the limit instead of growing the dictionary
+            let size = buf.get(5..13).map_or(u64::MAX, |size| u64::from_le_bytes(size.try_into().unwrap()));
+            let options = if size == u64::MAX {
+                // ended by a marker
+                lzma_rs::decompress::Options { memlimit: Some(max as usize), ..Default::default() }
+            } else {
+                let size = UnpackedSize::ReadHeaderButUseProvided(Some(size.min(max)));
+                lzma_rs::decompress::Options { unpacked_size: size, ..Default::default() }
+            };
+            let mut bounded = Bounded { out: &mut out, max: max as usize };
+            lzma_rs::lzma_decompress_with_options(&mut &buf[..], &mut bounded, &options)
+                .map(|_| 0)
+                .map_err(lzma_error)
+        }
+    };
+    if out.is_empty() {
+        None
+    } else {
+        Some(out)
+    }
+}
+
+fn lzma_error(e: lzma_rs::error::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e))
+}
+
+
+/// A writer which takes up to `max` bytes, and fails after that to stop the decoder.
+struct Bounded<'a> {
+    out: &'a mut Vec<u8>,
+    max: usize,
+}
+
+impl Write for Bounded<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let room = self.max - self.out.len();
+        if room == 0 && !buf.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "size limit reached"));
+        }
+        let len = buf.len().min(room);
+        self.out.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{decompress, Method};
+
+    #[test]
+    fn test_decompress() {
+        let mut gz = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gz.write_all(b"hello world\n").unwrap();
+        let gz = gz.finish().unwrap();
+        assert_eq!(Method::detect(&gz), Some(Method::Gzip));
+        assert_eq!(decompress(&gz, 1024).unwrap(), b"hello world\n");
+        assert_eq!(decompress(&gz, 5).unwrap(), b"hello");
+        // cut off in the middle
+        assert_eq!(decompress(&gz[..gz.len() - 8], 1024).unwrap(), b"hello world\n");
+
+        let mut xz = vec![];
+        lzma_rs::xz_compress(&mut &b"hello xz\n"[..], &mut xz).unwrap();
+        assert_eq!(Method::detect(&xz), Some(Method::Xz));
+        assert_eq!(decompress(&xz, 1024).unwrap(), b"hello xz\n");
+        assert_eq!(decompress(&xz, 5).unwrap(), b"hello");
+
+        let mut lzma = vec![];
+        lzma_rs::lzma_compress(&mut &b"hello lzma\n"[..], &mut lzma).unwrap();
+        assert_eq!(Method::detect(&lzma), Some(Method::Lzma));
+        assert_eq!(decompress(&lzma, 1024).unwrap(), b"hello lzma\n");
+        assert_eq!(Method::detect(b"]\0\0\0\x01\0\0\0"), None);
+
+        let lz4 = {
+            let mut enc = lz4_flex::frame::FrameEncoder::new(vec![]);
+            enc.write_all(b"hello lz4\n").unwrap();
+            enc.finish().unwrap()
+        };
+        assert_eq!(decompress(&lz4, 1024).unwrap(), b"hello lz4\n");
+
+        // the ratio limits a bomb
+        let bomb = ruzstd::encoding::compress_to_vec(&vec![0; 1 << 20][..], ruzstd::encoding::CompressionLevel::Fastest);
+        assert_eq!(Method::detect(&bomb), Some(Method::Zstd));
+        assert_eq!(decompress(&bomb, 1 << 20).unwrap().len(), bomb.len() * 1024);
+
+        assert_eq!(decompress(b"\x1f\x8bnot gzip", 1024), None);
+        assert_eq!(decompress(b"plain", 1024), None);
+    }
+}
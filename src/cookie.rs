@@ -0,0 +1,121 @@
+use std::io::Read;
+use std::path::Path;
+
+use crate::apprentice;
+use crate::magic_param::MagicParam;
+use crate::magic_set::{Flags, MagicSet};
+use crate::softmagic::{Match, TraceLine};
+
+
+/// A handle of the loaded magic and the options to classify files with, as `magic_t` of
+/// libmagic.
+#[derive(Debug, Default)]
+pub struct Magic {
+    set: MagicSet,
+}
+
+impl Magic {
+    /// Create a handle with the flags as `magic_open`, where nothing is loaded yet.
+    ///
+    /// It is an error if any of the flags is not supported.
+    pub fn open(flags: Flags) -> Result<Magic, String> {
+        Ok(Magic { set: MagicSet::new(flags)? })
+    }
+
+    pub fn flags(&self) -> Flags {
+        self.set.flags()
+    }
+
+    pub fn set_flags(&mut self, flags: Flags) -> Result<(), String> {
+        self.set.set_flags(flags)
+    }
+
+    pub fn param(&self) -> &MagicParam {
+        self.set.param()
+    }
+
+    pub fn set_param(&mut self, param: MagicParam) {
+        self.set.set_param(param);
+    }
+
+    /// Load the magic files at the paths, each of which is a file or a directory of files,
+    /// replacing the loaded ones.
+    pub fn load<P: AsRef<Path>>(&mut self, paths: &[P]) -> Result<(), String> {
+        self.set.load(paths)
+    }
+
+    /// Load the magic files listed by `$MAGIC`, or the ones installed by `file(1)`.
+    pub fn load_default(&mut self) -> Result<(), String> {
+        self.set.load(&apprentice::default_paths())
+    }
+
+    /// Extend the tokens which tell the kinds of text when no magic matches by a token file,
+    /// where every line is a description, a MIME type and the tokens separated by spaces,
+    /// with the three separated by tabs.
+    pub fn load_tokens<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
+        self.set.load_tokens(path.as_ref())
+    }
+
+    /// Classify the contents of a buffer as `magic_buffer`.
+    pub fn classify_bytes(&self, buf: &[u8]) -> Result<String, String> {
+        self.set.buffer(buf)
+    }
+
+    /// Classify the contents of a file as `magic_file`.
+    pub fn classify_path<P: AsRef<Path>>(&self, path: P) -> Result<String, String> {
+        self.set.file(path.as_ref())
+    }
+
+    /// Classify the contents read from a reader as `magic_descriptor`.
+    pub fn classify_reader<R: Read>(&self, reader: R) -> Result<String, String> {
+        self.set.reader(reader)
+    }
+
+    /// Return all the entries which match a buffer, in the order of strength.
+    pub fn matches_bytes(&self, buf: &[u8]) -> Result<Vec<Match>, String> {
+        self.set.buffer_matches(buf)
+    }
+
+    /// Return the magic lines tried on a buffer in order, with the values they read and if
+    /// they match, to see where the matching goes.
+    pub fn trace_bytes(&self, buf: &[u8]) -> Result<Vec<TraceLine>, String> {
+        self.set.buffer_trace(buf)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::Magic;
+    use crate::magic_set::Flags;
+
+    #[test]
+    fn test_magic() {
+        let dir = env::temp_dir();
+        let magic = dir.join(format!("test_cookie.{}.magic", process::id()));
+        fs::write(&magic, "0\tstring\tGIF8\tGIF image data\n!:mime\timage/gif\n").unwrap();
+        let gif = dir.join(format!("test_cookie.{}.gif", process::id()));
+        fs::write(&gif, "GIF89a").unwrap();
+
+        let mut cookie = Magic::open(Flags::NONE).unwrap();
+        cookie.load(&[&magic]).unwrap();
+        assert_eq!(cookie.classify_bytes(b"GIF87a").unwrap(), "GIF image data");
+        assert_eq!(cookie.classify_path(&gif).unwrap(), "GIF image data");
+        assert_eq!(cookie.classify_reader(&b"GIF89a"[..]).unwrap(), "GIF image data");
+        assert_eq!(cookie.matches_bytes(b"GIF89a").unwrap()[0].mime.as_deref(), Some("image/gif"));
+        let trace = cookie.trace_bytes(b"GIF89a").unwrap();
+        assert_eq!(trace.len(), 1);
+        assert_eq!((trace[0].line_no, trace[0].rule.as_str(), trace[0].matched), (1, "0 string GIF8 GIF image data", true));
+
+        cookie.set_flags(Flags::MIME_TYPE).unwrap();
+        assert_eq!(cookie.flags(), Flags::MIME_TYPE);
+        assert_eq!(cookie.classify_path(&gif).unwrap(), "image/gif");
+
+        fs::remove_file(&magic).unwrap();
+        fs::remove_file(&gif).unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+/// The class of a byte in text, as `text_chars` of libmagic.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CharClass {
+    /// Never appears in text
+    F,
+    /// Appears in plain ASCII text
+    T,
+    /// Appears in ISO-8859 text
+    I,
+    /// Appears in non-ISO extended ASCII, such as Mac and IBM PC
+    X,
+}
+
+fn char_class(c: u8) -> CharClass {
+    match c {
+        // BEL, BS, HT, LF, VT, FF, CR and ESC
+        0x07..=0x0d | 0x1b => CharClass::T,
+        0x00..=0x1f | 0x7f => CharClass::F,
+        0x20..=0x7e => CharClass::T,
+        // NEL
+        0x85 => CharClass::T,
+        0x80..=0x9f => CharClass::X,
+        0xa0..=0xff => CharClass::I,
+    }
+}
+
+
+/// The conversion from EBCDIC to ISO-8859-1, as `ebcdic_to_ascii` of libmagic
+const EBCDIC_TO_ASCII: [u8; 256] = [
+    0, 1, 2, 3, 156, 9, 134, 127, 151, 141, 142, 11, 12, 13, 14, 15,
+    16, 17, 18, 19, 157, 133, 8, 135, 24, 25, 146, 143, 28, 29, 30, 31,
+    128, 129, 130, 131, 132, 10, 23, 27, 136, 137, 138, 139, 140, 5, 6, 7,
+    144, 145, 22, 147, 148, 149, 150, 4, 152, 153, 154, 155, 20, 21, 158, 26,
+    b' ', 160, 161, 162, 163, 164, 165, 166, 167, 168, 213, b'.', b'<', b'(', b'+', b'|',
+    b'&', 169, 170, 171, 172, 173, 174, 175, 176, 177, b'!', b'$', b'*', b')', b';', b'~',
+    b'-', b'/', 178, 179, 180, 181, 182, 183, 184, 185, 203, b',', b'%', b'_', b'>', b'?',
+    186, 187, 188, 189, 190, 191, 192, 193, 194, b'`', b':', b'#', b'@', b'\'', b'=', b'"',
+    195, b'a', b'b', b'c', b'd', b'e', b'f', b'g', b'h', b'i', 196, 197, 198, 199, 200, 201,
+    202, b'j', b'k', b'l', b'm', b'n', b'o', b'p', b'q', b'r', b'^', 204, 205, 206, 207, 208,
+    209, 229, b's', b't', b'u', b'v', b'w', b'x', b'y', b'z', 210, 211, 212, b'[', 214, 215,
+    216, 217, 218, 219, 220, 221, 222, 223, 224, 225, 226, 227, 228, b']', 230, 231,
+    b'{', b'A', b'B', b'C', b'D', b'E', b'F', b'G', b'H', b'I', 232, 233, 234, 235, 236, 237,
+    b'}', b'J', b'K', b'L', b'M', b'N', b'O', b'P', b'Q', b'R', 238, 239, 240, 241, 242, 243,
+    b'\\', 159, b'S', b'T', b'U', b'V', b'W', b'X', b'Y', b'Z', 244, 245, 246, 247, 248, 249,
+    b'0', b'1', b'2', b'3', b'4', b'5', b'6', b'7', b'8', b'9', 250, 251, 252, 253, 254, 255,
+];
+
+
+/// The character encoding of a buffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Encoding {
+    Ascii,
+    Utf7,
+    Utf8Bom,
+    Utf8,
+    Utf32Le,
+    Utf32Be,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+    /// Non-ISO extended ASCII
+    Extended,
+    Ebcdic,
+    /// EBCDIC with the international characters
+    IntlEbcdic,
+    /// Not text at all
+    Binary,
+}
+
+impl Encoding {
+    /// Detect the encoding of a buffer, as `file_encoding` of libmagic.
+    pub(crate) fn detect(buf: &[u8]) -> Encoding {
+        if looks_ascii(buf) {
+            if looks_utf7(buf) {
+                Encoding::Utf7
+            } else {
+                Encoding::Ascii
+            }
+        } else if buf.starts_with(b"\xef\xbb\xbf") && looks_utf8(&buf[3..]).is_some() {
+            Encoding::Utf8Bom
+        } else if looks_utf8(buf) == Some(true) {
+            Encoding::Utf8
+        } else if let Some(big) = looks_ucs32(buf) {
+            if big { Encoding::Utf32Be } else { Encoding::Utf32Le }
+        } else if let Some(big) = looks_ucs16(buf) {
+            if big { Encoding::Utf16Be } else { Encoding::Utf16Le }
+        } else if looks_latin1(buf) {
+            Encoding::Latin1
+        } else if looks_extended(buf) {
+            Encoding::Extended
+        } else {
+            let converted: Vec<u8> = buf.iter().map(|&c| EBCDIC_TO_ASCII[c as usize]).collect();
+            if looks_ascii(&converted) {
+                Encoding::Ebcdic
+            } else if looks_latin1(&converted) {
+                Encoding::IntlEbcdic
+            } else {
+                Encoding::Binary
+            }
+        }
+    }
+
+    pub(crate) fn is_text(self) -> bool {
+        self != Encoding::Binary
+    }
+
+    /// The name of the encoding in the description of a text file.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Encoding::Ascii => "ASCII",
+            Encoding::Utf7 => "UTF-7 Unicode",
+            Encoding::Utf8Bom => "UTF-8 Unicode (with BOM)",
+            Encoding::Utf8 => "UTF-8 Unicode",
+            Encoding::Utf32Le => "Little-endian UTF-32 Unicode",
+            Encoding::Utf32Be => "Big-endian UTF-32 Unicode",
+            Encoding::Utf16Le => "Little-endian UTF-16 Unicode",
+            Encoding::Utf16Be => "Big-endian UTF-16 Unicode",
+            Encoding::Latin1 => "ISO-8859",
+            Encoding::Extended => "Non-ISO extended-ASCII",
+            Encoding::Ebcdic => "EBCDIC",
+            Encoding::IntlEbcdic => "International EBCDIC",
+            Encoding::Binary => "",
+        }
+    }
+
+    /// What the text is called after the name of the encoding.
+    pub(crate) fn text_type(self) -> &'static str {
+        match self {
+            Encoding::Ebcdic | Encoding::IntlEbcdic => "character data",
+            Encoding::Binary => "binary",
+            _ => "text",
+        }
+    }
+
+    /// Decode a buffer of this encoding into the Unicode chars, as the `ubuf` filled by
+    /// `file_encoding` of libmagic, where the single byte encodings are taken as ISO-8859-1.
+    pub(crate) fn decode(self, buf: &[u8]) -> Vec<u32> {
+        match self {
+            Encoding::Utf8Bom | Encoding::Utf8 => {
+                let buf = buf.strip_prefix(b"\xef\xbb\xbf").unwrap_or(buf);
+                let valid = match std::str::from_utf8(buf) {
+                    Ok(s) => s,
+                    Err(e) => std::str::from_utf8(&buf[..e.valid_up_to()]).unwrap(),
+                };
+                valid.chars().map(u32::from).collect()
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => buf[2..].chunks_exact(2)
+                .map(|pair| {
+                    let pair = [pair[0], pair[1]];
+                    let uc = if self == Encoding::Utf16Be { u16::from_be_bytes(pair) } else { u16::from_le_bytes(pair) };
+                    uc as u32
+                })
+                .collect(),
+            Encoding::Utf32Le | Encoding::Utf32Be => buf[4..].chunks_exact(4)
+                .map(|quad| {
+                    let quad = [quad[0], quad[1], quad[2], quad[3]];
+                    if self == Encoding::Utf32Be { u32::from_be_bytes(quad) } else { u32::from_le_bytes(quad) }
+                })
+                .collect(),
+            Encoding::Ebcdic | Encoding::IntlEbcdic => {
+                buf.iter().map(|&c| EBCDIC_TO_ASCII[c as usize] as u32).collect()
+            }
+            Encoding::Binary => vec![],
+            _ => buf.iter().map(|&c| c as u32).collect(),
+        }
+    }
+
+    /// The charset reported by the MIME encoding.
+    pub(crate) fn mime(self) -> &'static str {
+        match self {
+            Encoding::Ascii => "us-ascii",
+            Encoding::Utf7 => "utf-7",
+            Encoding::Utf8Bom | Encoding::Utf8 => "utf-8",
+            Encoding::Utf32Le => "utf-32le",
+            Encoding::Utf32Be => "utf-32be",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1 => "iso-8859-1",
+            Encoding::Extended => "unknown-8bit",
+            Encoding::Ebcdic | Encoding::IntlEbcdic => "ebcdic",
+            Encoding::Binary => "binary",
+        }
+    }
+}
+
+
+fn looks_ascii(buf: &[u8]) -> bool {
+    buf.iter().all(|&c| char_class(c) == CharClass::T)
+}
+
+fn looks_latin1(buf: &[u8]) -> bool {
+    buf.iter().all(|&c| matches!(char_class(c), CharClass::T | CharClass::I))
+}
+
+fn looks_extended(buf: &[u8]) -> bool {
+    buf.iter().all(|&c| char_class(c) != CharClass::F)
+}
+
+/// Return true if the text starts with the signature of UTF-7.
+fn looks_utf7(buf: &[u8]) -> bool {
+    buf.len() > 4 && buf.starts_with(b"+/v") && matches!(buf[3], b'8' | b'9' | b'+' | b'/')
+}
+
+/// Check if the buffer is UTF-8 text, where the last char may be cut off, and return if
+/// it has any non-ASCII char, as `file_looks_utf8` of libmagic.
+pub(crate) fn looks_utf8(buf: &[u8]) -> Option<bool> {
+    let valid = match std::str::from_utf8(buf) {
+        Ok(s) => s,
+        // the end of the buffer is in the middle of a char
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&buf[..e.valid_up_to()]).unwrap(),
+        Err(_) => return None,
+    };
+    let mut non_ascii = false;
+    for c in valid.bytes() {
+        if c.is_ascii() {
+            if char_class(c) != CharClass::T {
+                return None;
+            }
+        } else {
+            non_ascii = true;
+        }
+    }
+    Some(non_ascii)
+}
+
+/// Check if the buffer is UTF-16 text led by a BOM, and return if it is big endian.
+fn looks_ucs16(buf: &[u8]) -> Option<bool> {
+    let big = match buf {
+        [0xff, 0xfe, ..] => false,
+        [0xfe, 0xff, ..] => true,
+        _ => return None,
+    };
+    for pair in buf[2..].chunks_exact(2) {
+        let pair = [pair[0], pair[1]];
+        let uc = if big { u16::from_be_bytes(pair) } else { u16::from_le_bytes(pair) };
+        match uc {
+            0xfffe | 0xffff | 0xfdd0..=0xfdef => return None,
+            uc if uc < 128 && char_class(uc as u8) != CharClass::T => return None,
+            _ => {}
+        }
+    }
+    Some(big)
+}
+
+/// Check if the buffer is UTF-32 text led by a BOM, and return if it is big endian.
+fn looks_ucs32(buf: &[u8]) -> Option<bool> {
+    let big = match buf {
+        [0xff, 0xfe, 0, 0, ..] => false,
+        [0, 0, 0xfe, 0xff, ..] => true,
+        _ => return None,
+    };
+    for quad in buf[4..].chunks_exact(4) {
+        let quad = [quad[0], quad[1], quad[2], quad[3]];
+        let uc = if big { u32::from_be_bytes(quad) } else { u32::from_le_bytes(quad) };
+        match uc {
+            0xfffe => return None,
+            uc if uc < 128 && char_class(uc as u8) != CharClass::T => return None,
+            _ => {}
+        }
+    }
+    Some(big)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::Encoding;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Encoding::detect(b"hello\r\n\tworld\x1b[0m\n"), Encoding::Ascii);
+        assert_eq!(Encoding::detect(b"+/v8 utf-7"), Encoding::Utf7);
+        assert_eq!(Encoding::detect("h\u{e9}llo".as_bytes()), Encoding::Utf8);
+        assert_eq!(Encoding::detect(b"\xef\xbb\xbfhello"), Encoding::Utf8Bom);
+        // cut off in the middle of a char
+        assert_eq!(Encoding::detect(b"h\xc3\xa9 \xe2\x82"), Encoding::Utf8);
+        assert_eq!(Encoding::detect(b"\xff\xfeh\0i\0"), Encoding::Utf16Le);
+        assert_eq!(Encoding::detect(b"\xfe\xff\0h\0i"), Encoding::Utf16Be);
+        assert_eq!(Encoding::detect(b"\xff\xfe\0\0h\0\0\0"), Encoding::Utf32Le);
+        assert_eq!(Encoding::detect(b"caf\xe9"), Encoding::Latin1);
+        assert_eq!(Encoding::detect(b"caf\x82"), Encoding::Extended);
+        assert_eq!(Encoding::detect(b"\xc8\x85\x93\x93\x96\x15"), Encoding::Ebcdic);
+        assert_eq!(Encoding::detect(b"\x7fELF\x02\x01\x01\x00"), Encoding::Binary);
+
+        assert_eq!(Encoding::Utf16Le.decode(b"\xff\xfeh\0i\0"), vec![0x68, 0x69]);
+        assert_eq!(Encoding::Utf8Bom.decode("\u{feff}h\u{e9}".as_bytes()), vec![0x68, 0xe9]);
+        assert_eq!(Encoding::Ebcdic.decode(b"\xc8\x85\x15"), vec![0x48, 0x65, 0x85]);
+        assert_eq!(Encoding::Ebcdic.text_type(), "character data");
+
+        assert!(!Encoding::Binary.is_text());
+        assert_eq!(Encoding::Utf8Bom.mime(), "utf-8");
+        assert_eq!(Encoding::Binary.mime(), "binary");
+    }
+}
@@ -0,0 +1,164 @@
+use crate::parse_magic_entry::SourceLine;
+use crate::source_loc::SourceLoc;
+
+const TAB_WIDTH: usize = 8;
+
+
+/// Format the lines of a magic file in the canonical style, for `magic-tool fmt`:
+///
+/// - the fields are separated by tabs, and aligned to the same tab stops within an entry,
+/// - the strings are written with the canonical escapes, while the offsets, the types and the
+///   numbers are kept as they are written,
+/// - the aux lines directly follow the line they are attached to,
+/// - the comments, the blank lines and the invalid lines are kept as they are.
+pub(crate) fn format_lines(lines: &[(SourceLoc, SourceLine)]) -> String {
+    let lines: Vec<&SourceLine> = lines.iter().map(|(_, line)| line).collect();
+    let lines = place_aux_lines(&lines);
+
+    let mut out = String::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let end = lines[start + 1..].iter()
+            .position(|line| line.is_entry_line())
+            .map_or(lines.len(), |i| start + 1 + i);
+        format_entry(&lines[start..end], &mut out);
+        start = end;
+    }
+    out
+}
+
+/// Move each aux line up to follow the last magic line, before any comments or blank lines.
+fn place_aux_lines<'a>(lines: &[&'a SourceLine]) -> Vec<&'a SourceLine> {
+    let mut placed = Vec::with_capacity(lines.len());
+    // where the next aux line goes, which is after the last magic line and its aux lines
+    let mut aux_pos = None;
+    for &line in lines {
+        match line {
+            SourceLine::Blank | SourceLine::Comment(_) => placed.push(line),
+            _ if line.is_aux_line() => match aux_pos {
+                Some(pos) => {
+                    placed.insert(pos, line);
+                    aux_pos = Some(pos + 1);
+                }
+                None => placed.push(line),
+            },
+            _ => {
+                placed.push(line);
+                aux_pos = Some(placed.len());
+            }
+        }
+    }
+    placed
+}
+
+/// Format the lines of an entry, where the fields of magic lines are aligned.
+fn format_entry(lines: &[&SourceLine], out: &mut String) {
+    let fields: Vec<Option<Vec<String>>> = lines.iter()
+        .map(|line| match line {
+            SourceLine::Magic(magic_line) => Some(magic_line.fields()),
+            _ => None,
+        })
+        .collect();
+
+    // the width of a column is decided by the fields followed by another field
+    let mut widths: Vec<usize> = vec![];
+    for fields in fields.iter().flatten() {
+        for (i, field) in fields.iter().enumerate().take(fields.len() - 1) {
+            let width = (field.chars().count() / TAB_WIDTH + 1) * TAB_WIDTH;
+            if i < widths.len() {
+                widths[i] = widths[i].max(width);
+            } else {
+                widths.push(width);
+            }
+        }
+    }
+
+    for (line, fields) in lines.iter().zip(fields) {
+        match line {
+            SourceLine::Blank => {}
+            SourceLine::Comment(text) => out.push_str(text),
+            SourceLine::Magic(_) => {
+                let fields = fields.unwrap();
+                let last = fields.len() - 1;
+                for (i, field) in fields.iter().enumerate() {
+                    out.push_str(field);
+                    if i != last {
+                        let tabs = (widths[i] - field.chars().count()).div_ceil(TAB_WIDTH);
+                        out.push_str(&"\t".repeat(tabs));
+                    }
+                }
+            }
+            SourceLine::Aux(aux) => out.push_str(&aux.to_string()),
+            SourceLine::Invalid { text, .. } => out.push_str(text),
+        }
+        out.push('\n');
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::format_lines;
+    use crate::parse_magic_entry::MagicFile;
+
+    fn format_str(s: &str) -> String {
+        format_lines(&MagicFile::lines_from_bytes(Path::new("test_fmt.magic"), s.as_bytes()))
+    }
+
+    #[test]
+    fn test_format_lines() {
+        let raw = "\
+#  gzip
+0 string \\037\\213 gzip compressed data
+
+!:mime   application/gzip
+>3   byte &0x08   \\b, was \"%s\"
+>>0x100000 ulelong x
+# broken
+0 nosuchtype  x  broken  \t
+!:ext  gz/tgz
+0 name foo
+";
+        let expect = "\
+#  gzip
+0\t\tstring\t\\x1f\\x8b\tgzip compressed data
+!:mime\tapplication/gzip
+
+>3\t\tbyte\t&0x08\t\t\\b, was \"%s\"
+>>0x100000\tulelong\tx
+# broken
+0 nosuchtype  x  broken
+!:ext\tgz/tgz
+0\tname\tfoo
+";
+        let formatted = format_str(raw);
+        assert_eq!(formatted, expect);
+        assert_eq!(format_str(&formatted), formatted);
+    }
+
+    #[test]
+    fn test_format_numbers() {
+        // the numbers keep their bases, widths, suffixes and the `=` before them
+        let raw = "\
+0 short 1000 decimal
+>(0x3C.l+0x0010) byte&0x08 =0x10 hex
+>>&-04 ulelong > 077L octal
+>>0 dC x byte
+0 search/0x100/c/W abc
+>&0 lefloat >1.5e3 float
+";
+        let expect = "\
+0\t\t\tshort\t\t1000\tdecimal
+>(0x3C.l+0x0010)\tbyte&0x08\t=0x10\thex
+>>&-04\t\t\tulelong\t\t>077L\toctal
+>>0\t\t\tdC\t\tx\tbyte
+0\tsearch/0x100/c/W\tabc
+>&0\tlefloat\t\t\t>1.5e3\tfloat
+";
+        let formatted = format_str(raw);
+        assert_eq!(formatted, expect);
+        assert_eq!(format_str(&formatted), formatted);
+    }
+}
@@ -0,0 +1,200 @@
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::Path;
+
+use crate::magic_set::{os_error, strerror, Flags};
+
+
+/// The result of looking at a file by its metadata.
+#[derive(Debug, PartialEq)]
+pub(crate) enum FsMagic {
+    /// The file is described without reading it
+    Done(String),
+    /// The contents are to be classified, and described after the prefix
+    Contents(String),
+}
+
+
+/// Describe a file which is not a regular one by its metadata, as `file_fsmagic` of
+/// libmagic, where symlinks are followed with `Flags::SYMLINK`, and the devices are read
+/// as regular files with `Flags::DEVICES`.
+pub(crate) fn fsmagic(path: &Path, flags: Flags) -> Result<FsMagic, String> {
+    let meta = if flags.contains(Flags::SYMLINK) {
+        fs::metadata(path)
+    } else {
+        fs::symlink_metadata(path)
+    };
+    let meta = match meta {
+        Ok(meta) => meta,
+        Err(e) if flags.contains(Flags::ERROR) => {
+            return Err(format!("cannot stat `{}' ({})", path.display(), os_error(&e)));
+        }
+        Err(e) => return Ok(FsMagic::Done(format!("cannot open `{}' ({})", path.display(), strerror(&e)))),
+    };
+
+    let mime = flags.intersects(Flags::MIME);
+    // the extensions and the Apple codes only come from the contents
+    let silent = flags.intersects(Flags::APPLE | Flags::EXTENSION);
+    let mut out = String::new();
+    if !mime && !silent {
+        let bits = [(0o4000, "setuid"), (0o2000, "setgid"), (0o1000, "sticky")];
+        let set: Vec<&str> = bits.iter()
+            .filter(|(bit, _)| meta.mode() & bit != 0)
+            .map(|(_, name)| *name)
+            .collect();
+        out = set.join(", ");
+    }
+
+    let typ = meta.file_type();
+    let (mime_type, desc) = if typ.is_dir() {
+        ("directory", "directory".to_string())
+    } else if typ.is_char_device() || typ.is_block_device() {
+        if flags.contains(Flags::DEVICES) {
+            return Ok(FsMagic::Contents(contents_prefix(out)));
+        }
+        let (major, minor) = (libc::major(meta.rdev()), libc::minor(meta.rdev()));
+        if typ.is_char_device() {
+            ("chardevice", format!("character special ({}/{})", major, minor))
+        } else {
+            ("blockdevice", format!("block special ({}/{})", major, minor))
+        }
+    } else if typ.is_fifo() {
+        ("fifo", "fifo (named pipe)".to_string())
+    } else if typ.is_socket() {
+        ("socket", "socket".to_string())
+    } else if typ.is_symlink() {
+        match symlink(path, flags)? {
+            Ok(desc) => ("symlink", desc),
+            // a broken link is described without the other properties
+            Err(desc) => {
+                let desc = if flags.contains(Flags::MIME_TYPE) {
+                    "inode/symlink".to_string()
+                } else if mime {
+                    String::new()
+                } else {
+                    desc
+                };
+                return Ok(FsMagic::Done(desc));
+            }
+        }
+    } else if meta.len() == 0 && !flags.contains(Flags::DEVICES) {
+        // no need to read an empty file, unless it may be a device whose size is not known
+        ("x-empty", "empty".to_string())
+    } else {
+        return Ok(FsMagic::Contents(contents_prefix(out)));
+    };
+
+    if silent {
+        // nothing is known but the file is not to be read
+        let default = if flags.contains(Flags::APPLE) { "UNKNUNKN" } else { "???" };
+        return Ok(FsMagic::Done(default.to_string()));
+    }
+    if mime {
+        return Ok(FsMagic::Done(handle_mime(flags, mime_type)));
+    }
+    if !out.is_empty() {
+        out.push_str(", ");
+    }
+    out.push_str(&desc);
+    Ok(FsMagic::Done(out))
+}
+
+/// The prefix of the contents, such as `setuid `.
+fn contents_prefix(mut out: String) -> String {
+    if !out.is_empty() {
+        out.push(' ');
+    }
+    out
+}
+
+/// Describe a symlink by its target, or return the description of a broken one as the
+/// inner error.
+fn symlink(path: &Path, flags: Flags) -> Result<Result<String, String>, String> {
+    let target = match fs::read_link(path) {
+        Ok(target) => target,
+        Err(e) if flags.contains(Flags::ERROR) => {
+            return Err(format!("unreadable symlink `{}' ({})", path.display(), os_error(&e)));
+        }
+        Err(e) => return Ok(Ok(format!("unreadable symlink `{}' ({})", path.display(), strerror(&e)))),
+    };
+    // a relative target is relative to the directory of the link
+    let resolved = match path.parent() {
+        Some(dir) => dir.join(&target),
+        None => target.clone(),
+    };
+    if let Err(e) = fs::metadata(&resolved) {
+        if flags.contains(Flags::ERROR) && !flags.intersects(Flags::MIME) {
+            return Err(format!("broken symbolic link to {} ({})", target.display(), os_error(&e)));
+        }
+        return Ok(Err(format!("broken symbolic link to {}", target.display())));
+    }
+    Ok(Ok(format!("symbolic link to {}", target.display())))
+}
+
+/// The MIME type and encoding of a file which is not read, as `handle_mime` of libmagic.
+fn handle_mime(flags: Flags, typ: &str) -> String {
+    let mut out = String::new();
+    if flags.contains(Flags::MIME_TYPE) {
+        out.push_str("inode/");
+        out.push_str(typ);
+        if flags.contains(Flags::MIME_ENCODING) {
+            out.push_str("; charset=");
+        }
+    }
+    if flags.contains(Flags::MIME_ENCODING) {
+        out.push_str("binary");
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::os::unix::net::UnixListener;
+    use std::process;
+
+    use super::{fsmagic, FsMagic};
+    use crate::magic_set::Flags;
+
+    #[test]
+    fn test_fsmagic() {
+        let dir = env::temp_dir().join(format!("test_fsmagic.{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("file"), "data").unwrap();
+        fs::write(dir.join("empty"), "").unwrap();
+        symlink("file", dir.join("link")).unwrap();
+        symlink("nowhere", dir.join("broken")).unwrap();
+        let _socket = UnixListener::bind(dir.join("socket")).unwrap();
+
+        let done = |path: &str, flags: Flags| match fsmagic(&dir.join(path), flags).unwrap() {
+            FsMagic::Done(desc) => desc,
+            FsMagic::Contents(prefix) => panic!("contents of {} after `{}'", path, prefix),
+        };
+        assert_eq!(done("", Flags::NONE), "directory");
+        assert_eq!(done("", Flags::MIME), "inode/directory; charset=binary");
+        assert_eq!(done("empty", Flags::NONE), "empty");
+        assert_eq!(done("empty", Flags::MIME_TYPE), "inode/x-empty");
+        assert_eq!(done("link", Flags::NONE), "symbolic link to file");
+        assert_eq!(done("link", Flags::MIME_TYPE), "inode/symlink");
+        assert_eq!(done("broken", Flags::NONE), "broken symbolic link to nowhere");
+        assert!(fsmagic(&dir.join("broken"), Flags::ERROR).is_err());
+        assert_eq!(done("socket", Flags::NONE), "socket");
+        assert_eq!(done("none", Flags::NONE).split(" (").next().unwrap(),
+                   format!("cannot open `{}'", dir.join("none").display()));
+        assert_eq!(
+            fsmagic("/dev/null".as_ref(), Flags::NONE).unwrap(),
+            FsMagic::Done("character special (1/3)".to_string())
+        );
+        assert_eq!(done("", Flags::EXTENSION), "???");
+
+        assert_eq!(fsmagic(&dir.join("file"), Flags::NONE).unwrap(), FsMagic::Contents(String::new()));
+        assert_eq!(fsmagic(&dir.join("link"), Flags::SYMLINK).unwrap(), FsMagic::Contents(String::new()));
+        assert_eq!(fsmagic("/dev/null".as_ref(), Flags::DEVICES).unwrap(), FsMagic::Contents(String::new()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
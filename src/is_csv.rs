@@ -0,0 +1,107 @@
+use crate::magic_set::Flags;
+
+
+/// The number of lines whose fields are counted, beyond which the rest is not looked at
+const CSV_LINES: usize = 10;
+
+
+/// Skip a quoted field after its opening quote, where a doubled quote is an escaped one,
+/// and return the index after the closing quote.
+fn eat_quote(buf: &[u8], mut i: usize) -> usize {
+    let mut quote = false;
+    while i < buf.len() {
+        if buf[i] != b'"' {
+            if quote {
+                return i;
+            }
+        } else {
+            // the second of a doubled quote is escaped
+            quote = !quote;
+        }
+        i += 1;
+    }
+    buf.len()
+}
+
+/// If the lines of a buffer have the same number of fields separated by commas, which is
+/// at least 2, as `csv_parse` of libmagic.
+///
+/// Only the first `CSV_LINES` lines are looked at, and there must be at least 3 lines.
+fn looks_csv(buf: &[u8]) -> bool {
+    // the number of commas of the current line and of the first line
+    let mut n_fields = 0;
+    let mut first_fields = 0;
+    let mut n_lines = 0;
+    let mut i = 0;
+    while i < buf.len() {
+        let c = buf[i];
+        i += 1;
+        match c {
+            b'"' => i = eat_quote(buf, i),
+            b',' => n_fields += 1,
+            b'\n' => {
+                n_lines += 1;
+                if n_lines == CSV_LINES {
+                    return first_fields != 0 && first_fields == n_fields;
+                }
+                if first_fields == 0 {
+                    if n_fields == 0 {
+                        return false;
+                    }
+                    first_fields = n_fields;
+                } else if first_fields != n_fields {
+                    return false;
+                }
+                n_fields = 0;
+            }
+            _ => {}
+        }
+    }
+    first_fields != 0 && n_lines > 2
+}
+
+/// Describe a text buffer which is comma-separated values, as `file_is_csv` of libmagic,
+/// or return `None` if it is not.
+pub(crate) fn is_csv(buf: &[u8], flags: Flags) -> Option<String> {
+    if flags.intersects(Flags::APPLE | Flags::EXTENSION) || !looks_csv(buf) {
+        return None;
+    }
+    let desc = if flags.contains(Flags::MIME_TYPE) {
+        "text/csv"
+    } else if flags.intersects(Flags::MIME) {
+        ""
+    } else {
+        "CSV text"
+    };
+    Some(desc.to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{is_csv, looks_csv};
+    use crate::magic_set::Flags;
+
+    #[test]
+    fn test_looks_csv() {
+        assert!(looks_csv(b"a,b,c\n1,2,3\n4,5,6\n"));
+        assert!(looks_csv(b"name,note\n\"Doe, J\",\"said \"\"hi\"\"\"\nRoe,\"multi\nline\"\n"));
+        // a line with another number of fields after the lines which are counted
+        let mut long = "a,b\n".repeat(10);
+        long.push_str("a\n");
+        assert!(looks_csv(long.as_bytes()));
+
+        assert!(!looks_csv(b"a,b,c\n1,2\n4,5,6\n"));
+        assert!(!looks_csv(b"a,b\n1,2\n"));
+        assert!(!looks_csv(b"a\nb\nc\nd\n"));
+        assert!(!looks_csv(b"hello, world\n"));
+    }
+
+    #[test]
+    fn test_is_csv() {
+        let csv = b"a,b\n1,2\n3,4\n";
+        assert_eq!(is_csv(csv, Flags::NONE).unwrap(), "CSV text");
+        assert_eq!(is_csv(csv, Flags::MIME_TYPE).unwrap(), "text/csv");
+        assert_eq!(is_csv(csv, Flags::APPLE), None);
+    }
+}
@@ -0,0 +1,238 @@
+use crate::magic_set::Flags;
+
+
+/// The deepest nesting of arrays and objects which is parsed
+const MAX_DEPTH: usize = 500;
+
+
+/// A parser which only checks if a buffer is valid JSON, as `json_parse` of libmagic.
+struct Parser<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    /// If there is an object or an array which is not empty, as a lone string or number
+    /// is not taken as JSON data
+    structured: bool,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.buf.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn skip_space(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> bool {
+        if depth > MAX_DEPTH {
+            return false;
+        }
+        self.skip_space();
+        match self.peek() {
+            Some(b'"') => self.string(),
+            Some(b'[') => self.array(depth + 1),
+            Some(b'{') => self.object(depth + 1),
+            Some(b't') => self.literal(b"true"),
+            Some(b'f') => self.literal(b"false"),
+            Some(b'n') => self.literal(b"null"),
+            Some(_) => self.number(),
+            None => false,
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> bool {
+        self.pos += 1;
+        self.skip_space();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return true;
+        }
+        loop {
+            if !self.value(depth) {
+                return false;
+            }
+            self.skip_space();
+            match self.next() {
+                Some(b',') => {}
+                Some(b']') => {
+                    self.structured = true;
+                    return true;
+                }
+                _ => return false,
+            }
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> bool {
+        self.pos += 1;
+        self.skip_space();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            self.structured = true;
+            return true;
+        }
+        loop {
+            self.skip_space();
+            if self.peek() != Some(b'"') || !self.string() {
+                return false;
+            }
+            self.skip_space();
+            if self.next() != Some(b':') || !self.value(depth) {
+                return false;
+            }
+            self.skip_space();
+            match self.next() {
+                Some(b',') => {}
+                Some(b'}') => {
+                    self.structured = true;
+                    return true;
+                }
+                _ => return false,
+            }
+        }
+    }
+
+    fn string(&mut self) -> bool {
+        self.pos += 1;
+        loop {
+            match self.next() {
+                Some(b'"') => return true,
+                Some(b'\\') => match self.next() {
+                    Some(b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't') => {}
+                    Some(b'u') => {
+                        for _ in 0..4 {
+                            if !self.next().is_some_and(|c| c.is_ascii_hexdigit()) {
+                                return false;
+                            }
+                        }
+                    }
+                    _ => return false,
+                },
+                Some(c) if c >= 0x20 => {}
+                _ => return false,
+            }
+        }
+    }
+
+    fn literal(&mut self, word: &[u8]) -> bool {
+        if self.buf[self.pos..].starts_with(word) {
+            self.pos += word.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Skip the digits, and return if there is any.
+    fn digits(&mut self) -> bool {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.pos > start
+    }
+
+    fn number(&mut self) -> bool {
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        if self.peek() == Some(b'0') {
+            self.pos += 1;
+        } else if !self.digits() {
+            return false;
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if !self.digits() {
+                return false;
+            }
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if !self.digits() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+
+/// If a buffer is a JSON object, or an array which is not empty, where the nesting is
+/// limited to `MAX_DEPTH`.
+fn looks_json(buf: &[u8]) -> bool {
+    let mut parser = Parser { buf, pos: 0, structured: false };
+    let valid = parser.value(0);
+    parser.skip_space();
+    valid && parser.pos == buf.len() && parser.structured
+}
+
+/// Describe a text buffer which is JSON data, as `file_is_json` of libmagic, or return
+/// `None` if it is not.
+pub(crate) fn is_json(buf: &[u8], flags: Flags) -> Option<String> {
+    if flags.intersects(Flags::APPLE | Flags::EXTENSION) || !looks_json(buf) {
+        return None;
+    }
+    let desc = if flags.contains(Flags::MIME_TYPE) {
+        "application/json"
+    } else if flags.intersects(Flags::MIME) {
+        ""
+    } else {
+        "JSON data"
+    };
+    Some(desc.to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{is_json, looks_json};
+    use crate::magic_set::Flags;
+
+    #[test]
+    fn test_looks_json() {
+        assert!(looks_json(b"{}"));
+        assert!(looks_json(b" {\"a\": [1, -2.5e+3, true, null], \"b\\u00e9\\n\": {\"c\": \"d\"}}\n"));
+        assert!(looks_json(b"[[], {}]"));
+        // a lone value or an empty array is not enough
+        assert!(!looks_json(b"[]"));
+        assert!(!looks_json(b"\"string\""));
+        assert!(!looks_json(b"12"));
+
+        assert!(!looks_json(b"{\"a\": 1,}"));
+        assert!(!looks_json(b"{\"a\" 1}"));
+        assert!(!looks_json(b"{a: 1}"));
+        assert!(!looks_json(b"[01]"));
+        assert!(!looks_json(b"[1.]"));
+        assert!(!looks_json(b"[\"a\tb\"]"));
+        assert!(!looks_json(b"[\"\\x\"]"));
+        assert!(!looks_json(b"[1, 2"));
+        assert!(!looks_json(b"{} {}"));
+        assert!(!looks_json(b"[tru]"));
+
+        let deep = format!("{}1{}", "[".repeat(500), "]".repeat(500));
+        assert!(looks_json(deep.as_bytes()));
+        let deeper = format!("{}1{}", "[".repeat(501), "]".repeat(501));
+        assert!(!looks_json(deeper.as_bytes()));
+    }
+
+    #[test]
+    fn test_is_json() {
+        assert_eq!(is_json(b"{\"a\": 1}", Flags::NONE).unwrap(), "JSON data");
+        assert_eq!(is_json(b"{\"a\": 1}", Flags::MIME_TYPE).unwrap(), "application/json");
+        assert_eq!(is_json(b"{\"a\": 1}", Flags::MIME_ENCODING).unwrap(), "");
+        assert_eq!(is_json(b"{\"a\": 1}", Flags::EXTENSION), None);
+        assert_eq!(is_json(b"a, b", Flags::NONE), None);
+    }
+}
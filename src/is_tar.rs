@@ -0,0 +1,141 @@
+use crate::magic_set::Flags;
+
+
+/// The size of a tar header record
+const RECORD_SIZE: usize = 512;
+/// The offset and the size of the checksum of a header
+const CHKSUM: (usize, usize) = (148, 8);
+/// The offset of the magic of a header, which is 6 bytes
+const MAGIC: usize = 257;
+
+/// The name of a Gentoo binary package, which is a tar archive left to the rules
+const GPKG_MATCH: &[u8] = b"/gpkg-1";
+
+
+/// The kinds of tar archive, as told by `is_tar` of libmagic.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TarType {
+    /// The old fashioned one of V7
+    V7,
+    Posix,
+    Gnu,
+}
+
+impl TarType {
+    fn desc(self) -> &'static str {
+        match self {
+            TarType::V7 => "tar archive",
+            TarType::Posix => "POSIX tar archive",
+            TarType::Gnu => "POSIX tar archive (GNU)",
+        }
+    }
+}
+
+
+/// Parse an octal field, after the leading spaces and up to a space or a NUL, as `from_oct`
+/// of libmagic, or return `None` if it is blank or ends with something else.
+fn from_oct(field: &[u8]) -> Option<u32> {
+    let start = field.iter().position(|c| !c.is_ascii_whitespace())?;
+    let field = &field[start..];
+    let end = field.iter().position(|c| !(b'0'..=b'7').contains(c)).unwrap_or(field.len());
+    if field.get(end).is_some_and(|&c| c != 0 && !c.is_ascii_whitespace()) {
+        return None;
+    }
+    Some(field[..end].iter().fold(0, |value, &c| (value << 3) | u32::from(c - b'0')))
+}
+
+/// Tell the kind of tar archive by the checksum and the magic of its first header.
+fn tar_type(buf: &[u8]) -> Option<TarType> {
+    let header = buf.get(..RECORD_SIZE)?;
+    let name = &header[..100];
+    if let Some(nul) = name.iter().position(|&c| c == 0) {
+        if name[..nul].ends_with(GPKG_MATCH) {
+            return None;
+        }
+    }
+
+    let (off, len) = CHKSUM;
+    let recsum = from_oct(&header[off..off + len])?;
+    // the checksum is summed as if it is blanks
+    let sum: u32 = header.iter().map(|&c| u32::from(c)).sum::<u32>()
+        - header[off..off + len].iter().map(|&c| u32::from(c)).sum::<u32>()
+        + u32::from(b' ') * len as u32;
+    if sum != recsum {
+        return None;
+    }
+    Some(match &header[MAGIC..MAGIC + 6] {
+        b"ustar " => TarType::Gnu,
+        b"ustar\0" => TarType::Posix,
+        _ => TarType::V7,
+    })
+}
+
+/// Describe a buffer which is a tar archive, as `file_is_tar` of libmagic, or return
+/// `None` if it is not.
+pub(crate) fn is_tar(buf: &[u8], flags: Flags) -> Option<String> {
+    if flags.intersects(Flags::APPLE | Flags::EXTENSION) {
+        return None;
+    }
+    let tar = tar_type(buf)?;
+    let desc = if flags.contains(Flags::MIME_TYPE) {
+        "application/x-tar"
+    } else if flags.intersects(Flags::MIME) {
+        ""
+    } else {
+        tar.desc()
+    };
+    Some(desc.to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{from_oct, is_tar, tar_type, TarType};
+    use crate::magic_set::Flags;
+
+    /// Build a header with a name and a magic, and its checksum.
+    fn header(name: &[u8], magic: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0; 1024];
+        buf[..name.len()].copy_from_slice(name);
+        buf[100..108].copy_from_slice(b"0000644\0");
+        buf[124..136].copy_from_slice(b"00000000012\0");
+        buf[156] = b'0';
+        buf[257..257 + magic.len()].copy_from_slice(magic);
+        buf[148..156].copy_from_slice(b"        ");
+        let sum: u32 = buf[..512].iter().map(|&c| u32::from(c)).sum();
+        buf[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+        buf
+    }
+
+    #[test]
+    fn test_from_oct() {
+        assert_eq!(from_oct(b"0001234\0"), Some(0o1234));
+        assert_eq!(from_oct(b"  755 \0\0"), Some(0o755));
+        assert_eq!(from_oct(b"\0\0\0"), Some(0));
+        assert_eq!(from_oct(b"        "), None);
+        assert_eq!(from_oct(b"12x"), None);
+    }
+
+    #[test]
+    fn test_tar_type() {
+        assert_eq!(tar_type(&header(b"a.txt", b"")), Some(TarType::V7));
+        assert_eq!(tar_type(&header(b"a.txt", b"ustar\x0000")), Some(TarType::Posix));
+        assert_eq!(tar_type(&header(b"a.txt", b"ustar  \0")), Some(TarType::Gnu));
+        assert_eq!(tar_type(&header(b"x/gpkg-1", b"ustar\x0000")), None);
+
+        let mut bad = header(b"a.txt", b"ustar\x0000");
+        bad[0] = b'b';
+        assert_eq!(tar_type(&bad), None);
+        assert_eq!(tar_type(&[0; 1024]), None);
+        assert_eq!(tar_type(&header(b"a.txt", b"")[..511]), None);
+    }
+
+    #[test]
+    fn test_is_tar() {
+        let gnu = header(b"a.txt", b"ustar  \0");
+        assert_eq!(is_tar(&gnu, Flags::NONE).unwrap(), "POSIX tar archive (GNU)");
+        assert_eq!(is_tar(&gnu, Flags::MIME_TYPE).unwrap(), "application/x-tar");
+        assert_eq!(is_tar(&gnu, Flags::MIME_ENCODING).unwrap(), "");
+        assert_eq!(is_tar(&gnu, Flags::EXTENSION), None);
+    }
+}
@@ -0,0 +1,47 @@
+//! Determine the types of files by the magic rules of libmagic.
+//!
+//! ```no_run
+//! use file_type::{Flags, Magic};
+//!
+//! let mut magic = Magic::open(Flags::MIME_TYPE).unwrap();
+//! magic.load_default().unwrap();
+//! println!("{}", magic.classify_path("/bin/sh").unwrap());
+//! ```
+
+#[macro_use]
+extern crate bitflags;
+extern crate regex;
+extern crate num_derive;
+
+mod str_utils;
+mod magic;
+mod magic_param;
+mod parse_magic_line;
+mod parse_magic_aux_line;
+mod parse_magic_entry;
+mod source_loc;
+mod fmt_magic_file;
+mod lint_magic_file;
+mod print;
+mod apprentice;
+mod softmagic;
+mod magic_set;
+mod encoding;
+mod ascmagic;
+mod names;
+mod fsmagic;
+mod compress;
+mod readelf;
+mod cdf;
+mod is_json;
+mod is_csv;
+mod is_tar;
+mod cookie;
+mod capi;
+#[doc(hidden)]
+pub mod cli;
+
+pub use crate::cookie::Magic;
+pub use crate::magic_param::{MagicParam, Param};
+pub use crate::magic_set::Flags;
+pub use crate::softmagic::{Match, TraceLine};
@@ -0,0 +1,336 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::magic::{CmpType, RelnOp, RelnVal, StrModifier};
+use crate::parse_magic_entry::SourceLine;
+use crate::parse_magic_line::{Mask, MagicLine};
+use crate::source_loc::SourceLoc;
+use crate::str_utils;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Finding {
+    loc: SourceLoc,
+    severity: Severity,
+    msg: String,
+}
+
+impl Finding {
+    pub(crate) fn severity(&self) -> Severity {
+        self.severity
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.loc, self.severity, self.msg)
+    }
+}
+
+
+/// Check the lines of the magic files for `magic-tool lint`, which are loaded together so that a
+/// `use` can refer to a `name` in another file.
+pub(crate) fn lint_files(files: &[Vec<(SourceLoc, SourceLine)>]) -> Vec<Finding> {
+    let names: HashSet<&[u8]> = files.iter()
+        .flatten()
+        .filter_map(|(_, line)| match line {
+            SourceLine::Magic(magic_line) if *magic_line.cmp_type() == CmpType::Name => {
+                match magic_line.reln_val() {
+                    RelnVal::Str(name) => Some(name.as_slice()),
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .collect();
+
+    let mut findings = vec![];
+    for lines in files {
+        Linter::new(&names, &mut findings).lint(lines);
+    }
+    findings
+}
+
+
+/// The state of checking a magic file line by line.
+struct Linter<'a> {
+    names: &'a HashSet<&'a [u8]>,
+    findings: &'a mut Vec<Finding>,
+    /// Where the line being checked is
+    loc: SourceLoc,
+    /// The level of the last magic line, or `None` if not in a valid entry
+    cont_lvl: Option<usize>,
+    /// The always-true `x` line seen at each level of the entry since the last `clear`
+    always_true: Vec<Option<SourceLoc>>,
+    /// The kinds of aux lines attached to the last magic line
+    aux_kinds: Vec<&'static str>,
+    has_strength: bool,
+}
+
+impl<'a> Linter<'a> {
+    fn new(names: &'a HashSet<&'a [u8]>, findings: &'a mut Vec<Finding>) -> Self {
+        Linter {
+            names,
+            findings,
+            loc: SourceLoc::default(),
+            cont_lvl: None,
+            always_true: vec![],
+            aux_kinds: vec![],
+            has_strength: false,
+        }
+    }
+
+    fn report(&mut self, severity: Severity, msg: String) {
+        self.findings.push(Finding {
+            loc: self.loc.clone(),
+            severity,
+            msg,
+        });
+    }
+
+    fn lint(&mut self, lines: &[(SourceLoc, SourceLine)]) {
+        for (loc, line) in lines {
+            self.loc = loc.clone();
+            match line {
+                SourceLine::Blank | SourceLine::Comment(_) => {}
+                SourceLine::Magic(magic_line) => self.lint_magic_line(magic_line),
+                SourceLine::Aux(aux) => self.lint_aux_line(aux.kind()),
+                SourceLine::Invalid { msg, .. } => {
+                    if line.is_entry_line() {
+                        self.cont_lvl = None;
+                    }
+                    self.report(Severity::Error, msg.clone());
+                }
+            }
+        }
+    }
+
+    fn lint_magic_line(&mut self, line: &MagicLine) {
+        let lvl = line.cont_lvl();
+        if lvl == 0 {
+            self.has_strength = false;
+            self.always_true.clear();
+        } else {
+            match self.cont_lvl {
+                // the continuations of an invalid entry have been reported
+                None => return,
+                Some(prev) if lvl > prev + 1 => {
+                    self.report(Severity::Error, format!(
+                        "continuation level jumps from {} to {}, the line is never reached",
+                        prev, lvl
+                    ));
+                }
+                _ => {}
+            }
+        }
+        self.cont_lvl = Some(lvl);
+        self.aux_kinds.clear();
+
+        self.lint_reachable(line);
+        self.lint_use(line);
+        self.lint_format(line);
+        self.lint_str_modifier(line);
+    }
+
+    /// Check if a `default` line follows an always-true sibling, so that it never matches.
+    fn lint_reachable(&mut self, line: &MagicLine) {
+        let lvl = line.cont_lvl();
+        self.always_true.resize(lvl + 1, None);
+        match line.cmp_type() {
+            CmpType::Default => {
+                if let Some(loc) = self.always_true[lvl].clone() {
+                    self.report(Severity::Warning, format!(
+                        "`default` is never reached after the always-true `x` at {}", loc
+                    ));
+                }
+            }
+            CmpType::Clear => self.always_true[lvl] = None,
+            CmpType::Name | CmpType::Use | CmpType::Indirect => {}
+            _ if *line.reln_op() == RelnOp::Any => self.always_true[lvl] = Some(line.loc().clone()),
+            _ => {}
+        }
+    }
+
+    fn lint_use(&mut self, line: &MagicLine) {
+        if *line.cmp_type() != CmpType::Use {
+            return;
+        }
+        if let RelnVal::Str(name) = line.reln_val() {
+            // `^' before the name flips the endianness
+            let name = name.strip_prefix(b"^").unwrap_or(name);
+            if !self.names.contains(name) {
+                self.report(Severity::Error, format!(
+                    "`use {}` without a matching `name`", String::from_utf8_lossy(name)
+                ));
+            }
+        }
+    }
+
+    fn lint_format(&mut self, line: &MagicLine) {
+        let typ = line.cmp_type();
+        let specs = str_utils::format_specs(line.desc());
+        if specs.len() > 1 {
+            self.report(Severity::Error, format!("too many format specs `{}`", specs.join("")));
+        }
+
+        let fmt_type = typ.fmt_type();
+        for spec in specs {
+            if !spec.chars().last().is_some_and(|conv| fmt_type.accepts(conv)) {
+                self.report(Severity::Error, format!(
+                    "format spec `{}` is incompatible with type `{}`",
+                    spec, typ.to_string().to_lowercase()
+                ));
+            }
+        }
+    }
+
+    /// Check if the string modifiers apply to the type.
+    fn lint_str_modifier(&mut self, line: &MagicLine) {
+        let (flags, range) = match line.mask() {
+            Mask::Str { flags, range } => (*flags, *range),
+            Mask::Num { .. } => return,
+        };
+        let typ = line.cmp_type();
+        let typ_name = typ.to_string().to_lowercase();
+
+        if range != 0 && !matches!(typ, CmpType::Search | CmpType::Regex) {
+            self.report(Severity::Warning, format!("range does not apply to `{}`", typ_name));
+        }
+
+        let applicable = match typ {
+            CmpType::String | CmpType::BEString16 | CmpType::LEString16 => Self::compare_modifiers(),
+            CmpType::Search => Self::compare_modifiers() | StrModifier::REGEX_OFFSET_START,
+            CmpType::Regex => Self::compare_modifiers() | StrModifier::REGEX_OFFSET_START |
+                // `l' counts the range in lines
+                StrModifier::PSTRING_4_LE,
+            CmpType::PString => Self::compare_modifiers() | StrModifier::PSTRING_LEN |
+                StrModifier::PSTRING_LENGTH_INCLUDES_ITSELF,
+            CmpType::Indirect => StrModifier::INDIRECT_RRELATIVE,
+            _ => StrModifier::NONE,
+        };
+        let extra = flags - applicable;
+        if !extra.is_empty() {
+            self.report(Severity::Warning, format!(
+                "string modifier `{}` does not apply to `{}`", extra.to_chars(), typ_name
+            ));
+        }
+    }
+
+    /// The modifiers which apply to the comparison of all the string types.
+    fn compare_modifiers() -> StrModifier {
+        StrModifier::COMPACT_WHITESPACE |
+            StrModifier::COMPACT_OPTIONAL_WHITESPACE |
+            StrModifier::IGNORE_LOWERCASE |
+            StrModifier::IGNORE_UPPERCASE |
+            StrModifier::BINTEST |
+            StrModifier::TEXTTEST |
+            StrModifier::TRIM |
+            StrModifier::FULL_WORD
+    }
+
+    fn lint_aux_line(&mut self, kind: &'static str) {
+        if self.cont_lvl.is_none() {
+            return;
+        }
+        if kind == "strength" {
+            if self.has_strength {
+                self.report(Severity::Error, "duplicated `!:strength` in the entry".to_string());
+            }
+            self.has_strength = true;
+        } else {
+            if self.aux_kinds.contains(&kind) {
+                self.report(Severity::Error, format!("duplicated `!:{}` on the line", kind));
+            }
+            self.aux_kinds.push(kind);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{lint_files, Severity, SourceLine, SourceLoc};
+    use crate::parse_magic_entry::MagicFile;
+
+    fn lint_str(name: &str, s: &str) -> Vec<(SourceLoc, SourceLine)> {
+        MagicFile::lines_from_bytes(Path::new(&format!("test_lint.{}.magic", name)), s.as_bytes())
+    }
+
+    #[test]
+    fn test_lint_files() {
+        let files = vec![
+            lint_str("a", "\
+0	name	elf-le
+>16	leshort	2	executable
+0	string	\\177ELF	ELF
+!:strength	+10
+>5	byte	1	LSB
+>>0	use	\\^elf-le
+>>0	use	elf-be
+>>>>4	byte	1	jumped
+!:mime	application/x-executable
+!:mime	application/x-sharedlib
+!:strength	*2
+"),
+            lint_str("b", "\
+0	belong	0xcafebabe	Java %s
+>4	byte	x	version %d.%d
+>4	default	x	unknown
+>8	string/256/r	x	\\b, %s
+!:mime	bad mime!
+"),
+        ];
+
+        let findings: Vec<(usize, Severity)> = lint_files(&files).iter()
+            .map(|f| (f.loc.line_no, f.severity))
+            .collect();
+        assert_eq!(findings, vec![
+            (7, Severity::Error),    // use without name
+            (8, Severity::Error),    // level jump
+            (10, Severity::Error),   // duplicated mime
+            (11, Severity::Error),   // duplicated strength
+            (1, Severity::Error),    // `%s` for belong
+            (2, Severity::Error),    // too many format specs
+            (3, Severity::Warning),  // unreachable default
+            (4, Severity::Warning),  // range for string
+            (4, Severity::Warning),  // `r` for string
+            (5, Severity::Error),    // invalid mime
+        ]);
+    }
+
+    #[test]
+    fn test_lint_reachable() {
+        let files = vec![lint_str("reachable", "\
+0	byte	x	any
+0	default	x	other
+0	string	AB	AB
+>2	byte	x	byte
+>2	default	x	unreachable
+>2	clear	x
+>2	default	x	reachable
+")];
+
+        let findings: Vec<(usize, Severity)> = lint_files(&files).iter()
+            .map(|f| (f.loc.line_no, f.severity))
+            .collect();
+        assert_eq!(findings, vec![(5, Severity::Warning)]);
+    }
+}
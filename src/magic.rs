@@ -1,9 +1,10 @@
 use num_traits::FromPrimitive;
 use num_derive::FromPrimitive;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 
 bitflags! {
-    struct MagicFlags: u8 {
+    pub(crate) struct MagicFlags: u8 {
         const INDIR        = 0b00000001;  /* if '(...)' appears */
         const OFFADD       = 0b00000010;  /* if '>&' or '>...(&' appears */
         const INDIROFFADD  = 0b00000100;  /* if '>&(' appears */
@@ -14,20 +15,16 @@ bitflags! {
     }
 }
 
-impl MagicFlags {
-    pub fn clear(&mut self) {
-        self.bits = 0;
-    }
-}
-
 impl From<u8> for MagicFlags {
     fn from(bits: u8) -> Self {
-        return MagicFlags::from_bits_truncate(bits);
+        MagicFlags::from_bits_truncate(bits)
     }
 }
 
 
 bitflags! {
+    #[derive(Serialize, Deserialize)]
+    #[serde(transparent)]
     pub(crate) struct StrModifier: u16 {
         const NONE                            = 0x0000;
         const COMPACT_WHITESPACE              = 0x0001;
@@ -45,31 +42,40 @@ bitflags! {
         const PSTRING_4_LE                    = 0x1000;
         const PSTRING_LENGTH_INCLUDES_ITSELF  = 0x2000;
         const INDIRECT_RRELATIVE              = 0x4000;
+        const FULL_WORD                       = 0x8000;
         const PSTRING_LEN = Self::PSTRING_1_LE.bits | Self::PSTRING_2_BE.bits |
             Self::PSTRING_2_LE.bits | Self::PSTRING_4_BE.bits | Self::PSTRING_4_LE.bits;
     }
 }
 
 impl StrModifier {
-    fn all_in_chars() -> &'static str {
-        const ALL_IN_CHARS: &str = "WwcCsbtTBHhLlJr";
-        return ALL_IN_CHARS;
+    pub(crate) fn all_in_chars() -> &'static str {
+        const ALL_IN_CHARS: &str = "WwcCsbtTBHhLlJrf";
+        ALL_IN_CHARS
     }
 
     pub(crate) fn is_pstring(&self) -> bool {
-        return self.contains(
+        self.intersects(
             StrModifier::PSTRING_1_LE |
                 StrModifier::PSTRING_2_BE |
                 StrModifier::PSTRING_2_LE |
                 StrModifier::PSTRING_4_BE |
                 StrModifier::PSTRING_4_LE
-        );
+        )
+    }
+
+    /// Return the modifier chars of the set flags, in the order of `all_in_chars`.
+    pub(crate) fn to_chars(self) -> String {
+        StrModifier::all_in_chars().chars().enumerate()
+            .filter(|(i, _)| self.bits & (1 << i) != 0)
+            .map(|(_, c)| c)
+            .collect()
     }
 }
 
 impl From<u16> for StrModifier {
     fn from(bits: u16) -> Self {
-        return StrModifier::from_bits_truncate(bits);
+        StrModifier::from_bits_truncate(bits)
     }
 }
 
@@ -99,8 +105,9 @@ impl From<char> for StrModifier {
 
 
 #[repr(u8)]
-#[derive(FromPrimitive, Debug, PartialEq)]
+#[derive(FromPrimitive, Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub(crate) enum CmpType {
+    #[default]
     Invalid = 0,
     Byte,
     Short,
@@ -150,16 +157,24 @@ pub(crate) enum CmpType {
     Use,
     Clear,
     Der,
+    Guid,
+    Offset,
+    BEVarInt,
+    LEVarInt,
+    MSDOSDate,
+    LEMSDOSDate,
+    BEMSDOSDate,
+    MSDOSTime,
+    LEMSDOSTime,
+    BEMSDOSTime,
+    Octal,
     NamesSize,
 }
 
-impl Default for CmpType {
-    fn default() -> Self { CmpType::Invalid }
-}
-
 impl CmpType {
     pub(crate) fn is_string(&self) -> bool {
-        match self {
+        matches!(
+            self,
             CmpType::String |
             CmpType::PString |
             CmpType::Regex |
@@ -168,11 +183,189 @@ impl CmpType {
             CmpType::Search |
             CmpType::Name |
             CmpType::Use |
-            CmpType::Indirect => true,
-            _ => false
+            CmpType::Indirect
+        )
+    }
+
+    /// Return true if the value of this type is a floating point number.
+    pub(crate) fn is_float(&self) -> bool {
+        matches!(
+            self,
+            CmpType::Float |
+            CmpType::BEFloat |
+            CmpType::LEFloat |
+            CmpType::Double |
+            CmpType::BEDouble |
+            CmpType::LEDouble
+        )
+    }
+
+    /// Return true if the relation value of this type is written as a string.
+    ///
+    /// Besides the string types, this covers the types whose value is a text
+    /// that only makes sense to the type itself, such as `der` and `guid`.
+    pub(crate) fn has_str_value(&self) -> bool {
+        self.is_string() || matches!(self, CmpType::Der | CmpType::Guid)
+    }
+
+    /// Return the kind of value printed by the format spec in the description.
+    pub(crate) fn fmt_type(&self) -> FmtType {
+        match self {
+            CmpType::Invalid |
+            CmpType::Default |
+            CmpType::Clear |
+            CmpType::Name |
+            CmpType::Use |
+            CmpType::Indirect |
+            CmpType::NamesSize => FmtType::None,
+            CmpType::String |
+            CmpType::PString |
+            CmpType::BEString16 |
+            CmpType::LEString16 |
+            CmpType::Search |
+            CmpType::Regex |
+            CmpType::Der |
+            CmpType::Guid |
+            CmpType::Octal => FmtType::Str,
+            t if t.is_date() => FmtType::Str,
+            t if t.is_float() => FmtType::Float,
+            _ => FmtType::Num,
+        }
+    }
+
+    /// Return true if the value of this type is printed as a date or a time.
+    pub(crate) fn is_date(&self) -> bool {
+        matches!(
+            self,
+            CmpType::Date |
+            CmpType::BEDate |
+            CmpType::LEDate |
+            CmpType::MEDate |
+            CmpType::LDate |
+            CmpType::BELDate |
+            CmpType::LELDate |
+            CmpType::MELDate |
+            CmpType::QDate |
+            CmpType::LEQDate |
+            CmpType::BEQDate |
+            CmpType::QLDate |
+            CmpType::LEQLDate |
+            CmpType::BEQLDate |
+            CmpType::QWDate |
+            CmpType::LEQWDate |
+            CmpType::BEQWDate |
+            CmpType::MSDOSDate |
+            CmpType::LEMSDOSDate |
+            CmpType::BEMSDOSDate |
+            CmpType::MSDOSTime |
+            CmpType::LEMSDOSTime |
+            CmpType::BEMSDOSTime
+        )
+    }
+
+    /// Return the size in bytes of the numeric types, or `None` for the others.
+    pub(crate) fn size(&self) -> Option<usize> {
+        match self {
+            CmpType::Byte => Some(1),
+            CmpType::Short |
+            CmpType::BEShort |
+            CmpType::LEShort |
+            CmpType::MSDOSDate |
+            CmpType::LEMSDOSDate |
+            CmpType::BEMSDOSDate |
+            CmpType::MSDOSTime |
+            CmpType::LEMSDOSTime |
+            CmpType::BEMSDOSTime => Some(2),
+            CmpType::Long |
+            CmpType::BELong |
+            CmpType::LELong |
+            CmpType::MELong |
+            CmpType::Date |
+            CmpType::BEDate |
+            CmpType::LEDate |
+            CmpType::MEDate |
+            CmpType::LDate |
+            CmpType::BELDate |
+            CmpType::LELDate |
+            CmpType::MELDate |
+            CmpType::Float |
+            CmpType::BEFloat |
+            CmpType::LEFloat |
+            CmpType::BEID3 |
+            CmpType::LEID3 => Some(4),
+            CmpType::Quad |
+            CmpType::BEQuad |
+            CmpType::LEQuad |
+            CmpType::QDate |
+            CmpType::LEQDate |
+            CmpType::BEQDate |
+            CmpType::QLDate |
+            CmpType::LEQLDate |
+            CmpType::BEQLDate |
+            CmpType::QWDate |
+            CmpType::LEQWDate |
+            CmpType::BEQWDate |
+            CmpType::Double |
+            CmpType::BEDouble |
+            CmpType::LEDouble |
+            CmpType::Offset => Some(8),
+            _ => None,
         }
     }
 
+    /// Swap the big and little endian types, for a `use` of `^name`.
+    pub(crate) fn flip(&self) -> CmpType {
+        match self {
+            CmpType::BEShort => CmpType::LEShort,
+            CmpType::LEShort => CmpType::BEShort,
+            CmpType::BELong => CmpType::LELong,
+            CmpType::LELong => CmpType::BELong,
+            CmpType::BEDate => CmpType::LEDate,
+            CmpType::LEDate => CmpType::BEDate,
+            CmpType::BELDate => CmpType::LELDate,
+            CmpType::LELDate => CmpType::BELDate,
+            CmpType::BEQuad => CmpType::LEQuad,
+            CmpType::LEQuad => CmpType::BEQuad,
+            CmpType::BEQDate => CmpType::LEQDate,
+            CmpType::LEQDate => CmpType::BEQDate,
+            CmpType::BEQLDate => CmpType::LEQLDate,
+            CmpType::LEQLDate => CmpType::BEQLDate,
+            CmpType::BEQWDate => CmpType::LEQWDate,
+            CmpType::LEQWDate => CmpType::BEQWDate,
+            CmpType::BEFloat => CmpType::LEFloat,
+            CmpType::LEFloat => CmpType::BEFloat,
+            CmpType::BEDouble => CmpType::LEDouble,
+            CmpType::LEDouble => CmpType::BEDouble,
+            CmpType::BEID3 => CmpType::LEID3,
+            CmpType::LEID3 => CmpType::BEID3,
+            CmpType::BEString16 => CmpType::LEString16,
+            CmpType::LEString16 => CmpType::BEString16,
+            CmpType::BEMSDOSDate => CmpType::LEMSDOSDate,
+            CmpType::LEMSDOSDate => CmpType::BEMSDOSDate,
+            CmpType::BEMSDOSTime => CmpType::LEMSDOSTime,
+            CmpType::LEMSDOSTime => CmpType::BEMSDOSTime,
+            t => *t,
+        }
+    }
+
+    /// Parse the SUS (`od(1)` style) type spelling, such as `dC`, `u2` or `dL`.
+    pub(crate) fn from_sus(s: &str) -> Option<(CmpType, bool)> {
+        let mut chars = s.chars();
+        let unsigned = match chars.next()? {
+            'd' => false,
+            'u' => true,
+            _ => return None,
+        };
+        let typ = match chars.as_str() {
+            "" | "I" | "4" | "L" => CmpType::Long,
+            "C" | "1" => CmpType::Byte,
+            "S" | "2" => CmpType::Short,
+            "Q" | "8" => CmpType::Quad,
+            _ => return None,
+        };
+        Some((typ, unsigned))
+    }
+
     fn all() -> Vec<CmpType> {
         let mut all: Vec<CmpType> = vec![];
         let mut i = 0;
@@ -180,7 +373,7 @@ impl CmpType {
             all.push(t);
             i += 1;
         }
-        return all;
+        all
     }
 }
 
@@ -211,8 +404,35 @@ impl fmt::Display for CmpType {
 }
 
 
+/// The kind of value printed by a format spec such as `%s` or `%d`.
 #[derive(Debug, PartialEq)]
+pub(crate) enum FmtType {
+    /// The type prints nothing, and no format spec is allowed
+    None,
+    /// `%d`, `%u`, `%x`, `%o`, `%c` and so on
+    Num,
+    /// `%e`, `%f` or `%g`
+    Float,
+    /// `%s`
+    Str,
+}
+
+impl FmtType {
+    /// Return true if the conversion char of a format spec prints this kind of value.
+    pub(crate) fn accepts(&self, conv: char) -> bool {
+        match self {
+            FmtType::None => false,
+            FmtType::Num => "diuxXoc".contains(conv),
+            FmtType::Float => "eEfFgG".contains(conv),
+            FmtType::Str => conv == 's',
+        }
+    }
+}
+
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
 pub(crate) enum MaskOp {
+    #[default]
     Noop,
     And,
     Or,
@@ -224,10 +444,6 @@ pub(crate) enum MaskOp {
     Modulo,
 }
 
-impl Default for MaskOp {
-    fn default() -> Self { MaskOp::Noop }
-}
-
 impl From<&str> for MaskOp {
     fn from(s: &str) -> Self {
         match s {
@@ -244,9 +460,27 @@ impl From<&str> for MaskOp {
     }
 }
 
+impl fmt::Display for MaskOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            MaskOp::Noop => "",
+            MaskOp::And => "&",
+            MaskOp::Or => "|",
+            MaskOp::Xor => "^",
+            MaskOp::Add => "+",
+            MaskOp::Minus => "-",
+            MaskOp::Multiply => "*",
+            MaskOp::Divide => "/",
+            MaskOp::Modulo => "%",
+        };
+        write!(f, "{}", op)
+    }
+}
 
-#[derive(Debug, PartialEq)]
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
 pub(crate) enum RelnOp {
+    #[default]
     Noop,
     And,
     Xor,
@@ -254,10 +488,8 @@ pub(crate) enum RelnOp {
     Less,
     Greater,
     Not,
-}
-
-impl Default for RelnOp {
-    fn default() -> Self { RelnOp::Noop }
+    /// `x`, matches any value
+    Any,
 }
 
 impl From<&str> for RelnOp {
@@ -269,21 +501,41 @@ impl From<&str> for RelnOp {
             "<" => RelnOp::Less,
             ">" => RelnOp::Greater,
             "!" => RelnOp::Not,
+            "x" => RelnOp::Any,
             _ => RelnOp::Noop,
         }
     }
 }
 
-enum RelnVal {
-
+impl fmt::Display for RelnOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            RelnOp::Noop => "",
+            RelnOp::And => "&",
+            RelnOp::Xor => "^",
+            RelnOp::Eq => "=",
+            RelnOp::Less => "<",
+            RelnOp::Greater => ">",
+            RelnOp::Not => "!",
+            RelnOp::Any => "x",
+        };
+        write!(f, "{}", op)
+    }
 }
 
 
-enum CondType {
-    None = 0,
-    If = 1,
-    ElIf = 2,
-    Else = 3,
+/// The value at the right side of a relation.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum RelnVal {
+    /// Integers are kept in their two's complement form
+    Num(u64),
+    Float(f64),
+    /// Unescaped string, which is not necessarily valid utf-8
+    Str(Vec<u8>),
+}
+
+impl Default for RelnVal {
+    fn default() -> Self { RelnVal::Num(0) }
 }
 
 
@@ -304,7 +556,7 @@ mod tests {
     fn test_from_i32_to_cmp_typ() {
         let x = CmpType::all();
         for i in x {
-            println!("{}", i.to_string())
+            println!("{}", i)
         }
     }
 
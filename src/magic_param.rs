@@ -1,10 +1,65 @@
+use std::convert::TryFrom;
+
+use num_derive::FromPrimitive;
 use serde::{Deserialize, Serialize};
-use serde_json;
-use serde_json::json;
 
 
-#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
-pub(crate) struct MagicParam {
+/// A field of `MagicParam`, numbered as the `MAGIC_PARAM_*` of libmagic.
+#[derive(FromPrimitive, Debug, Clone, Copy, PartialEq)]
+pub enum Param {
+    Indir = 0,
+    Name = 1,
+    ElfPhnum = 2,
+    ElfShnum = 3,
+    ElfNotes = 4,
+    Regex = 5,
+    Bytes = 6,
+    Encoding = 7,
+}
+
+impl Param {
+    const ALL: [Param; 8] = [
+        Param::Indir,
+        Param::Name,
+        Param::ElfPhnum,
+        Param::ElfShnum,
+        Param::ElfNotes,
+        Param::Regex,
+        Param::Bytes,
+        Param::Encoding,
+    ];
+
+    /// The name given to `file -P`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Param::Indir => "indir",
+            Param::Name => "name",
+            Param::ElfPhnum => "elf_phnum",
+            Param::ElfShnum => "elf_shnum",
+            Param::ElfNotes => "elf_notes",
+            Param::Regex => "regex",
+            Param::Bytes => "bytes",
+            Param::Encoding => "encoding",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Param> {
+        Self::ALL.iter().copied().find(|param| param.name() == name)
+    }
+
+    /// The maximum value of the parameter.
+    fn max(self) -> i32 {
+        match self {
+            Param::Bytes | Param::Encoding => i32::MAX,
+            _ => u16::MAX as i32,
+        }
+    }
+}
+
+
+/// The limits of matching a file, so that a hostile file cannot make it take forever.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MagicParam {
     indir: i32,
     name: i32,
     elf_phnum: i32,
@@ -12,53 +67,129 @@ pub(crate) struct MagicParam {
     elf_notes: i32,
     regex: i32,
     bytes: i32,
+    encoding: i32,
+}
+
+impl Default for MagicParam {
+    /// The same limits as libmagic.
+    fn default() -> Self {
+        MagicParam {
+            indir: 50,
+            name: 50,
+            elf_phnum: 2048,
+            elf_shnum: 32768,
+            elf_notes: 256,
+            regex: 8192,
+            bytes: 7 * 1024 * 1024,
+            encoding: 64 * 1024,
+        }
+    }
 }
 
 
 impl MagicParam {
-    pub(crate) fn from_cmd_line(line: &str) -> MagicParam {
-        let default = MagicParam::default();
-
-        let mut mp: serde_json::Value = serde_json::to_value(&default).unwrap();
-        for field_assign in line.split(";") {
-            let field_assign: Vec<&str> = field_assign.split("=").collect();
-            if field_assign.len() != 2 {
-                panic!("Each assignment should consist of exactly two parts separated by `='")
-            }
-
-            let field = field_assign[0];
-            if mp[field] == json!(null) {
-                panic!("Unknown field `{:?}' for :class MagicParam!", field);
-            }
-
-            match field_assign[1].parse::<i32>() {
-                Ok(assign) => {
-                    mp[field] = json!(assign);
-                },
-                Err(_) => {
-                    panic!("The assigned value to the field should be a number!");
-                },
-            }
+    /// Parse the `;` separated assignments such as `name=100;bytes=1M`, where the other
+    /// parameters are left as default.
+    pub fn from_cmd_line(line: &str) -> Result<MagicParam, String> {
+        let mut mp = MagicParam::default();
+        for assign in line.split(';').filter(|assign| !assign.is_empty()) {
+            let (name, value) = assign.split_once('=')
+                .ok_or_else(|| format!("invalid parameter `{}', which should be name=value", assign))?;
+            let param = Param::from_name(name.trim()).ok_or_else(|| {
+                let names: Vec<&str> = Param::ALL.iter().map(|param| param.name()).collect();
+                format!("unknown parameter `{}', valid ones are {}", name.trim(), names.join(", "))
+            })?;
+            mp.set(param, parse_size(value.trim())?)?;
+        }
+        Ok(mp)
+    }
+
+    pub fn get(&self, param: Param) -> i32 {
+        match param {
+            Param::Indir => self.indir,
+            Param::Name => self.name,
+            Param::ElfPhnum => self.elf_phnum,
+            Param::ElfShnum => self.elf_shnum,
+            Param::ElfNotes => self.elf_notes,
+            Param::Regex => self.regex,
+            Param::Bytes => self.bytes,
+            Param::Encoding => self.encoding,
         }
-        serde_json::from_value(mp).unwrap()
     }
+
+    /// Set a parameter, which is an error if the value is out of its range.
+    pub fn set(&mut self, param: Param, value: i32) -> Result<(), String> {
+        if !(0..=param.max()).contains(&value) {
+            return Err(format!("`{}' must be between 0 and {}, not {}", param.name(), param.max(), value));
+        }
+        let field = match param {
+            Param::Indir => &mut self.indir,
+            Param::Name => &mut self.name,
+            Param::ElfPhnum => &mut self.elf_phnum,
+            Param::ElfShnum => &mut self.elf_shnum,
+            Param::ElfNotes => &mut self.elf_notes,
+            Param::Regex => &mut self.regex,
+            Param::Bytes => &mut self.bytes,
+            Param::Encoding => &mut self.encoding,
+        };
+        *field = value;
+        Ok(())
+    }
+}
+
+
+/// Parse a number with an optional suffix of `k`, `m` or `g` in the units of 1024.
+fn parse_size(s: &str) -> Result<i32, String> {
+    let (digits, unit) = match s.chars().last().map(|c| c.to_ascii_lowercase()) {
+        Some('k') => (&s[..s.len() - 1], 1 << 10),
+        Some('m') => (&s[..s.len() - 1], 1 << 20),
+        Some('g') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+    let invalid = || format!("invalid parameter value `{}'", s);
+    let num = digits.parse::<i64>().map_err(|_| invalid())?;
+    num.checked_mul(unit)
+        .and_then(|num| i32::try_from(num).ok())
+        .ok_or_else(invalid)
 }
 
 
 #[cfg(test)]
 mod tests {
-    use crate::magic_param::MagicParam;
+    use crate::magic_param::{MagicParam, Param};
 
     #[test]
     fn test_from_cmd_line() {
         let input = "name=10;regex=12";
 
-        let get = MagicParam::from_cmd_line(input);
+        let get = MagicParam::from_cmd_line(input).unwrap();
         let expect = MagicParam {
             name: 10,
             regex: 12,
             ..MagicParam::default()
         };
         assert_eq!(get, expect);
+        assert_eq!(get.get(Param::Regex), 12);
+
+        let get = MagicParam::from_cmd_line("bytes=1M; encoding = 64k").unwrap();
+        assert_eq!(get.get(Param::Bytes), 1 << 20);
+        assert_eq!(get.get(Param::Encoding), 1 << 16);
+        assert_eq!(get.get(Param::Indir), 50);
+
+        let err = MagicParam::from_cmd_line("depth=3").unwrap_err();
+        assert!(err.contains("indir, name, elf_phnum, elf_shnum, elf_notes, regex, bytes, encoding"), "{}", err);
+        assert!(MagicParam::from_cmd_line("name").is_err());
+        assert!(MagicParam::from_cmd_line("name=ten").is_err());
+        assert!(MagicParam::from_cmd_line("bytes=4g").is_err());
+        assert!(MagicParam::from_cmd_line("indir=100000").is_err());
+
+        let mut param = MagicParam::default();
+        assert_eq!(param.get(Param::Indir), 50);
+        assert_eq!(param.get(Param::Bytes), 7 * 1024 * 1024);
+        param.set(Param::Bytes, 1024).unwrap();
+        assert_eq!(param.get(Param::Bytes), 1024);
+        assert!(param.set(Param::Name, -1).is_err());
+        assert!(param.set(Param::Name, 65536).is_err());
+        param.set(Param::Name, 65535).unwrap();
     }
 }
@@ -0,0 +1,633 @@
+use std::cell::Cell;
+use std::fs::{File, FileTimes, Metadata};
+use std::io::{self, Read};
+use std::os::unix::fs::{FileExt, PermissionsExt};
+use std::path::Path;
+
+use crate::apprentice::MagicList;
+use crate::ascmagic;
+use crate::cdf;
+use crate::compress;
+use crate::encoding::Encoding;
+use crate::fsmagic::{self, FsMagic};
+use crate::is_csv;
+use crate::is_json;
+use crate::is_tar;
+use crate::magic::MagicFlags;
+use crate::magic_param::{MagicParam, Param};
+use crate::names::TokenTable;
+use crate::print;
+use crate::readelf;
+use crate::softmagic::{Match, SoftMagic, TraceLine};
+
+
+bitflags! {
+    /// The flags of a magic set, with the same values as the `MAGIC_*` flags of libmagic.
+    pub struct Flags: u32 {
+        const NONE              = 0x0000000;
+        /// Print the debugging messages, which is accepted but prints nothing, as the
+        /// lines tried are given by `Magic::trace_bytes` instead
+        const DEBUG             = 0x0000001;
+        /// Follow the symlinks
+        const SYMLINK           = 0x0000002;
+        /// Look into the compressed files
+        const COMPRESS          = 0x0000004;
+        /// Look into the contents of the devices
+        const DEVICES           = 0x0000008;
+        /// Return the MIME type instead of the description
+        const MIME_TYPE         = 0x0000010;
+        /// Return all the matches, not just the first
+        const CONTINUE          = 0x0000020;
+        /// Print the warnings of the magic files
+        const CHECK             = 0x0000040;
+        /// Restore the access and the modification times of the files after reading them
+        const PRESERVE_ATIME    = 0x0000080;
+        /// Do not escape the unprintable chars
+        const RAW               = 0x0000100;
+        /// Report the errors of the files, instead of printing them as their types
+        const ERROR             = 0x0000200;
+        /// Return the MIME encoding instead of the description
+        const MIME_ENCODING     = 0x0000400;
+        const MIME              = Self::MIME_TYPE.bits | Self::MIME_ENCODING.bits;
+        /// Return the Apple creator and type codes instead of the description
+        const APPLE             = 0x0000800;
+        /// Return the slash-separated extensions instead of the description
+        const EXTENSION         = 0x1000000;
+        /// Report the contents of the compressed files as if they are not compressed
+        const COMPRESS_TRANSP   = 0x2000000;
+        /// Do not fork for decompressing
+        const NO_COMPRESS_FORK  = 0x4000000;
+        const NODESC            = Self::EXTENSION.bits | Self::MIME.bits | Self::APPLE.bits;
+
+        /// Do not look into the compressed files, even with `Flags::COMPRESS`
+        const NO_CHECK_COMPRESS = 0x0001000;
+        /// Do not check the tar headers
+        const NO_CHECK_TAR      = 0x0002000;
+        /// Do not try the magic rules
+        const NO_CHECK_SOFT     = 0x0004000;
+        /// Do not check the OS/2 application types, which are never checked
+        const NO_CHECK_APPTYPE  = 0x0008000;
+        /// Do not read the ELF headers for their details
+        const NO_CHECK_ELF      = 0x0010000;
+        /// Do not describe the text files, nor try the text rules
+        const NO_CHECK_TEXT     = 0x0020000;
+        /// Do not read the compound documents
+        const NO_CHECK_CDF      = 0x0040000;
+        /// Do not check for comma-separated values
+        const NO_CHECK_CSV      = 0x0080000;
+        /// Do not tell the kinds of text by their tokens
+        const NO_CHECK_TOKENS   = 0x0100000;
+        /// Do not detect the encodings, so that every file is taken as binary by the rules
+        const NO_CHECK_ENCODING = 0x0200000;
+        /// Do not check for JSON data
+        const NO_CHECK_JSON     = 0x0400000;
+        /// Do not check the SIMH tape files, which are never checked
+        const NO_CHECK_SIMH     = 0x0800000;
+        /// Skip every test but the magic rules
+        const NO_CHECK_BUILTIN  = Self::NO_CHECK_COMPRESS.bits | Self::NO_CHECK_TAR.bits |
+            Self::NO_CHECK_APPTYPE.bits | Self::NO_CHECK_ELF.bits | Self::NO_CHECK_TEXT.bits |
+            Self::NO_CHECK_CSV.bits | Self::NO_CHECK_CDF.bits | Self::NO_CHECK_TOKENS.bits |
+            Self::NO_CHECK_ENCODING.bits | Self::NO_CHECK_JSON.bits | Self::NO_CHECK_SIMH.bits;
+    }
+}
+
+impl Flags {
+    /// Return the flag which skips a test, by the name given to `file -e`.
+    pub(crate) fn from_check_name(name: &str) -> Option<Flags> {
+        match name {
+            "apptype" => Some(Flags::NO_CHECK_APPTYPE),
+            // `text' is a synonym of `ascii'
+            "ascii" | "text" => Some(Flags::NO_CHECK_TEXT),
+            "cdf" => Some(Flags::NO_CHECK_CDF),
+            "compress" => Some(Flags::NO_CHECK_COMPRESS),
+            "csv" => Some(Flags::NO_CHECK_CSV),
+            "elf" => Some(Flags::NO_CHECK_ELF),
+            "encoding" => Some(Flags::NO_CHECK_ENCODING),
+            "soft" => Some(Flags::NO_CHECK_SOFT),
+            "tar" => Some(Flags::NO_CHECK_TAR),
+            "json" => Some(Flags::NO_CHECK_JSON),
+            "simh" => Some(Flags::NO_CHECK_SIMH),
+            "tokens" => Some(Flags::NO_CHECK_TOKENS),
+            _ => None,
+        }
+    }
+}
+
+
+/// The loaded magic and the options to classify files with, as `struct magic_set` of
+/// libmagic.
+#[derive(Debug, Default)]
+pub(crate) struct MagicSet {
+    flags: Flags,
+    param: MagicParam,
+    mlist: MagicList,
+    tokens: TokenTable,
+}
+
+impl Default for Flags {
+    fn default() -> Self {
+        Flags::NONE
+    }
+}
+
+impl MagicSet {
+    pub(crate) fn new(flags: Flags) -> Result<MagicSet, String> {
+        let mut ms = MagicSet::default();
+        ms.set_flags(flags)?;
+        Ok(ms)
+    }
+
+    pub(crate) fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    /// Set the flags, which never fails as all the flags are supported, though
+    /// `magic_setflags` of libmagic fails if `PRESERVE_ATIME` is not on the platform.
+    pub(crate) fn set_flags(&mut self, flags: Flags) -> Result<(), String> {
+        self.flags = flags;
+        Ok(())
+    }
+
+    pub(crate) fn param(&self) -> &MagicParam {
+        &self.param
+    }
+
+    pub(crate) fn set_param(&mut self, param: MagicParam) {
+        self.param = param;
+    }
+
+    /// Load the magic files at the paths, replacing the loaded ones, where the warnings of
+    /// the files are printed only with `Flags::CHECK`.
+    pub(crate) fn load<P: AsRef<Path>>(&mut self, paths: &[P]) -> Result<(), String> {
+        self.mlist = MagicList::load(paths)?;
+        if self.flags.contains(Flags::CHECK) {
+            for warning in self.mlist.warnings() {
+                eprintln!("{}", warning);
+            }
+        }
+        Ok(())
+    }
+
+    /// Load a magic file given as a string, for the tests.
+    #[cfg(test)]
+    pub(crate) fn load_str(&mut self, text: &str) {
+        self.mlist = MagicList::load_str(text);
+    }
+
+    /// Extend the tokens which tell the kinds of text by a token file.
+    pub(crate) fn load_tokens(&mut self, path: &Path) -> Result<(), String> {
+        self.tokens.load(path)
+    }
+
+    /// Classify the contents of a buffer.
+    pub(crate) fn buffer(&self, buf: &[u8]) -> Result<String, String> {
+        self.classify(buf, None, false)
+    }
+
+    /// Return all the entries which match a buffer in the order of strength, as
+    /// `Flags::CONTINUE` does, with their descriptions whatever the output flags are.
+    pub(crate) fn buffer_matches(&self, buf: &[u8]) -> Result<Vec<Match>, String> {
+        if self.flags.contains(Flags::NO_CHECK_SOFT) {
+            return Ok(vec![]);
+        }
+        let buf = &buf[..buf.len().min(self.param.get(Param::Bytes) as usize)];
+        let flags = (self.flags | Flags::CONTINUE) - Flags::NODESC;
+        let mut soft = SoftMagic::new(&self.mlist, &self.param, flags, false);
+        if !soft.run(buf, MagicFlags::BINTEST, self.encoding(buf, self.flags).is_text())? &&
+            !self.flags.contains(Flags::NO_CHECK_TEXT) {
+            if let Some((_, chars)) = ascmagic::decode_text(buf, &self.param) {
+                soft = SoftMagic::new(&self.mlist, &self.param, flags, false);
+                soft.run(&ascmagic::to_utf8(&chars), MagicFlags::TEXTTEST, true)?;
+            }
+        }
+        let mut matches = soft.into_matches();
+        if !self.flags.contains(Flags::RAW) {
+            for m in matches.iter_mut() {
+                m.desc = print::octalify(&m.desc);
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Return the magic lines tried on a buffer by the binary tests, and by the text tests
+    /// if none of the binary ones matches, with their values and results.
+    pub(crate) fn buffer_trace(&self, buf: &[u8]) -> Result<Vec<TraceLine>, String> {
+        if self.flags.contains(Flags::NO_CHECK_SOFT) {
+            return Ok(vec![]);
+        }
+        let buf = &buf[..buf.len().min(self.param.get(Param::Bytes) as usize)];
+        let mut soft = SoftMagic::new(&self.mlist, &self.param, self.flags, false);
+        soft.enable_trace();
+        let found = soft.run(buf, MagicFlags::BINTEST, self.encoding(buf, self.flags).is_text())?;
+        let mut trace = soft.into_trace();
+        if !found && !self.flags.contains(Flags::NO_CHECK_TEXT) {
+            if let Some((_, chars)) = ascmagic::decode_text(buf, &self.param) {
+                let mut soft = SoftMagic::new(&self.mlist, &self.param, self.flags, false);
+                soft.enable_trace();
+                soft.run(&ascmagic::to_utf8(&chars), MagicFlags::TEXTTEST, true)?;
+                trace.extend(soft.into_trace());
+            }
+        }
+        Ok(trace)
+    }
+
+    /// Classify a file by its metadata, and then by its contents if it is a regular file.
+    ///
+    /// A file which cannot be opened is described as such, unless `Flags::ERROR` is set.
+    pub(crate) fn file(&self, path: &Path) -> Result<String, String> {
+        let prefix = match fsmagic::fsmagic(path, self.flags)? {
+            FsMagic::Done(desc) if self.flags.contains(Flags::RAW) => return Ok(desc),
+            FsMagic::Done(desc) => return Ok(print::octalify(&desc)),
+            FsMagic::Contents(prefix) => prefix,
+        };
+        self.contents(path).map(|desc| prefix + &desc)
+    }
+
+    /// Classify the contents of a file.
+    fn contents(&self, path: &Path) -> Result<String, String> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if self.flags.contains(Flags::ERROR) => {
+                return Err(format!("cannot open `{}' ({})", path.display(), os_error(&e)));
+            }
+            Err(e) => {
+                return Ok(format!("cannot open `{}' ({})", path.display(), strerror(&e)));
+            }
+        };
+        let meta = file.metadata().ok();
+        let executable = meta.as_ref().is_some_and(|meta| meta.permissions().mode() & 0o111 != 0);
+        let res = self.read(&file)
+            .map_err(|e| format!("cannot read `{}' ({})", path.display(), os_error(&e)))
+            .and_then(|buf| self.classify(&buf, Some(&file), executable));
+        if let Some(meta) = meta.filter(|_| self.flags.contains(Flags::PRESERVE_ATIME)) {
+            restore_times(&file, &meta);
+        }
+        res
+    }
+
+    /// Classify the contents read from a reader, such as the standard input.
+    pub(crate) fn reader<R: Read>(&self, reader: R) -> Result<String, String> {
+        let buf = self.read(reader).map_err(|e| format!("cannot read ({})", os_error(&e)))?;
+        self.classify(&buf, None, false)
+    }
+
+    /// Read up to the bytes limited by `Param::Bytes`.
+    fn read<R: Read>(&self, reader: R) -> io::Result<Vec<u8>> {
+        let mut buf = vec![];
+        reader.take(self.param.get(Param::Bytes) as u64).read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Detect the encoding of a buffer, or take it as binary if `Flags::NO_CHECK_ENCODING`
+    /// is set.
+    fn encoding(&self, buf: &[u8], flags: Flags) -> Encoding {
+        if flags.contains(Flags::NO_CHECK_ENCODING) {
+            return Encoding::Binary;
+        }
+        let max = self.param.get(Param::Encoding) as usize;
+        Encoding::detect(&buf[..buf.len().min(max)])
+    }
+
+    /// Classify a buffer with the flags of the set, where the file of the buffer is read
+    /// further for the ELF headers.
+    fn classify(&self, buf: &[u8], file: Option<&File>, executable: bool) -> Result<String, String> {
+        self.classify_flags(buf, file, executable, self.flags)
+    }
+
+    /// Classify a buffer with the flags as `file_buffer` of libmagic.
+    fn classify_flags(&self, buf: &[u8], file: Option<&File>, executable: bool, flags: Flags)
+        -> Result<String, String> {
+        let buf = &buf[..buf.len().min(self.param.get(Param::Bytes) as usize)];
+        let mut out = String::new();
+        let mut encoding = Encoding::Binary;
+        let default = match buf.len() {
+            0 => "empty",
+            1 => "very short file (no magic)",
+            _ => {
+                if flags.contains(Flags::COMPRESS) && !flags.contains(Flags::NO_CHECK_COMPRESS) {
+                    if let Some(desc) = self.zmagic(buf, executable, flags)? {
+                        return Ok(desc);
+                    }
+                }
+                encoding = self.encoding(buf, flags);
+                let source = file.map_or(Source::Buffer(buf), Source::File);
+                if !flags.contains(Flags::NO_CHECK_TAR) {
+                    out = is_tar::is_tar(buf, flags).unwrap_or_default();
+                }
+                // the extensions and the Apple codes only come from the rules
+                if out.is_empty() && !flags.intersects(Flags::NO_CHECK_CDF | Flags::APPLE | Flags::EXTENSION) &&
+                    buf.starts_with(cdf::MAGIC) {
+                    if let Some(desc) = cdf::trycdf(source, flags) {
+                        out = desc;
+                    }
+                }
+                // the ELF headers are read first, as a PIE is taken as executable by the rules
+                let elf = if flags.contains(Flags::NO_CHECK_ELF) || !buf.starts_with(b"\x7fELF") {
+                    None
+                } else {
+                    readelf::tryelf(source, &self.param)
+                };
+                let executable = elf.as_ref().and_then(|elf| elf.pie).unwrap_or(executable);
+                if out.is_empty() && !flags.contains(Flags::NO_CHECK_SOFT) {
+                    let mut soft = SoftMagic::new(&self.mlist, &self.param, flags, executable);
+                    if soft.run(buf, MagicFlags::BINTEST, encoding.is_text())? {
+                        out = soft.into_output();
+                        if let Some(elf) = elf.filter(|_| !flags.intersects(Flags::NODESC)) {
+                            out.push_str(&elf.desc);
+                        }
+                    }
+                }
+                if out.is_empty() && encoding.is_text() {
+                    if !flags.contains(Flags::NO_CHECK_JSON) {
+                        out = is_json::is_json(buf, flags).unwrap_or_default();
+                    }
+                    if out.is_empty() && !flags.contains(Flags::NO_CHECK_CSV) {
+                        out = is_csv::is_csv(buf, flags).unwrap_or_default();
+                    }
+                }
+                if out.is_empty() && !flags.contains(Flags::NO_CHECK_TEXT) {
+                    let desc = ascmagic::ascmagic(&self.mlist, &self.tokens, &self.param, flags, executable, buf)?;
+                    if let Some(desc) = desc {
+                        out = desc;
+                    }
+                }
+                "data"
+            }
+        };
+
+        // give up, as `file_default` of libmagic
+        if out.is_empty() {
+            if flags.intersects(Flags::MIME) {
+                if flags.contains(Flags::MIME_TYPE) {
+                    out.push_str(if buf.is_empty() { "application/x-empty" } else { "application/octet-stream" });
+                }
+            } else if flags.contains(Flags::APPLE) {
+                out.push_str("UNKNUNKN");
+            } else if flags.contains(Flags::EXTENSION) {
+                out.push_str("???");
+            } else {
+                out.push_str(default);
+            }
+        }
+        if flags.contains(Flags::MIME_ENCODING) {
+            if flags.contains(Flags::MIME_TYPE) {
+                out.push_str("; charset=");
+            }
+            out.push_str(encoding.mime());
+        }
+
+        if flags.contains(Flags::RAW) {
+            Ok(out)
+        } else {
+            Ok(print::octalify(&out))
+        }
+    }
+
+    /// Classify the decompressed contents of a compressed buffer, followed by what the
+    /// compressed buffer is unless `Flags::COMPRESS_TRANSP` is set, as `file_zmagic` of
+    /// libmagic.
+    fn zmagic(&self, buf: &[u8], executable: bool, flags: Flags) -> Result<Option<String>, String> {
+        let data = match compress::decompress(buf, self.param.get(Param::Bytes) as usize) {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        // no looking into a compressed file in another one
+        let flags = flags - Flags::COMPRESS;
+        let mut out = self.classify_flags(&data, None, false, flags)?;
+        let mime = flags & Flags::MIME;
+        if !flags.contains(Flags::COMPRESS_TRANSP) && (mime == Flags::MIME || mime.is_empty()) {
+            out.push_str(if mime.is_empty() { " (" } else { " compressed-encoding=" });
+            out.push_str(&self.classify_flags(buf, None, executable, flags)?);
+            if mime.is_empty() {
+                out.push(')');
+            }
+        }
+        Ok(Some(out))
+    }
+}
+
+
+/// The contents of a file, which may be read at any offset, as the headers of some formats
+/// are past the bytes read for the other tests.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Source<'a> {
+    Buffer(&'a [u8]),
+    File(&'a File),
+}
+
+impl Source<'_> {
+    pub(crate) fn size(&self) -> Option<u64> {
+        match self {
+            Source::Buffer(buf) => Some(buf.len() as u64),
+            Source::File(file) => file.metadata().ok().map(|meta| meta.len()),
+        }
+    }
+
+    /// Read up to `len` bytes at an offset, which are fewer past the end of the file or on
+    /// an error.
+    pub(crate) fn read(&self, off: u64, len: u64) -> Vec<u8> {
+        let size = self.size().unwrap_or(0);
+        let off = off.min(size);
+        let len = len.min(size - off) as usize;
+        match self {
+            Source::Buffer(buf) => buf[off as usize..][..len].to_vec(),
+            Source::File(file) => {
+                let mut buf = vec![0; len];
+                let mut done = 0;
+                while done < len {
+                    match file.read_at(&mut buf[done..], off + done as u64) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => done += n,
+                    }
+                }
+                buf.truncate(done);
+                buf
+            }
+        }
+    }
+}
+
+
+/// Restore the access and the modification times of a file which has been read, as
+/// `close_and_restore` of libmagic, where the failures are ignored.
+fn restore_times(file: &File, meta: &Metadata) {
+    if let (Ok(accessed), Ok(modified)) = (meta.accessed(), meta.modified()) {
+        let _ = file.set_times(FileTimes::new().set_accessed(accessed).set_modified(modified));
+    }
+}
+
+
+thread_local! {
+    /// The error number of the last system call which failed a call, as the `errno` kept
+    /// by `file_error` of libmagic for `magic_errno`
+    static ERRNO: Cell<i32> = const { Cell::new(0) };
+}
+
+/// Keep the error number of an error which fails a call, for `take_errno`.
+pub(crate) fn set_errno(e: &io::Error) {
+    ERRNO.with(|errno| errno.set(e.raw_os_error().unwrap_or(0)));
+}
+
+/// Return and clear the error number of the last system call which failed a call, or 0 if
+/// the failure is not of a system call.
+pub(crate) fn take_errno() -> i32 {
+    ERRNO.with(|errno| errno.replace(0))
+}
+
+/// Describe an error which fails a call as `strerror`, and keep its error number.
+pub(crate) fn os_error(e: &io::Error) -> String {
+    set_errno(e);
+    strerror(e)
+}
+
+/// Describe an error as `strerror(3)`, without the error number which Rust appends.
+pub(crate) fn strerror(e: &io::Error) -> String {
+    let msg = e.to_string();
+    match msg.find(" (os error ") {
+        Some(i) => msg[..i].to_string(),
+        None => msg,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File, FileTimes};
+    use std::io::Write;
+    use std::process;
+    use std::time::{Duration, SystemTime};
+
+    use super::{Flags, MagicSet};
+    use crate::magic_param::{MagicParam, Param};
+
+    #[test]
+    fn test_buffer() {
+        let mut ms = MagicSet::new(Flags::NONE).unwrap();
+        ms.load_str("0\tstring\tAB\tAB\n>2\tbyte\tx\tbyte %c\n");
+
+        assert_eq!(ms.buffer(b"").unwrap(), "empty");
+        assert_eq!(ms.buffer(b"A").unwrap(), "very short file (no magic)");
+        assert_eq!(ms.buffer(b"\x01\x02").unwrap(), "data");
+        assert_eq!(ms.buffer(b"XY").unwrap(), "ASCII text, with no line terminators");
+        assert_eq!(ms.buffer(b"AB\x01").unwrap(), "AB byte \\001");
+        assert_eq!(ms.buffer(b"{\"AB\": [1]}").unwrap(), "JSON data");
+        assert_eq!(ms.buffer(b"x,y\n1,2\n3,4\n").unwrap(), "CSV text");
+
+        let matches = ms.buffer_matches(b"AB\x01").unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].desc, "AB byte \\001");
+
+        ms.set_flags(Flags::RAW).unwrap();
+        assert_eq!(ms.buffer(b"AB\x01").unwrap(), "AB byte \x01");
+        ms.set_flags(Flags::DEBUG).unwrap();
+        assert_eq!(ms.buffer(b"AB\x01").unwrap(), "AB byte \\001");
+        assert!(MagicSet::new(Flags::NONE).unwrap().load(&["/nonexistent"]).is_err());
+    }
+
+    #[test]
+    fn test_exclude() {
+        let mut ms = MagicSet::new(Flags::NONE).unwrap();
+        ms.load_str("0\tstring\tAB\tAB\n");
+        let mut run = |flags: Flags, buf: &[u8]| {
+            ms.set_flags(flags).unwrap();
+            ms.buffer(buf).unwrap()
+        };
+
+        let json = b"{\"a\": [1]}\n";
+        assert_eq!(run(Flags::NO_CHECK_JSON, json), "ASCII text");
+        assert_eq!(run(Flags::NO_CHECK_CSV, b"x,y\n1,2\n3,4\n"), "ASCII text");
+        let c = b"#include <stdio.h>\nint main(void) { printf(\"hi\\n\"); }\n";
+        assert_eq!(run(Flags::NO_CHECK_TOKENS, c), "ASCII text");
+        assert_eq!(run(Flags::NO_CHECK_TEXT, b"XY\n"), "data");
+        assert_eq!(run(Flags::NO_CHECK_SOFT, b"AB\n"), "ASCII text");
+        // only the rules are left
+        assert_eq!(run(Flags::NO_CHECK_BUILTIN, b"AB\n"), "AB");
+        assert_eq!(run(Flags::NO_CHECK_BUILTIN, json), "data");
+
+        assert_eq!(Flags::from_check_name("ascii"), Flags::from_check_name("text"));
+        assert_eq!(Flags::from_check_name("tokens"), Some(Flags::NO_CHECK_TOKENS));
+        assert_eq!(Flags::from_check_name("fs"), None);
+    }
+
+    #[test]
+    fn test_mime() {
+        let mut ms = MagicSet::new(Flags::MIME).unwrap();
+        ms.load_str("0\tstring\t\\<svg\tSVG image\n!:mime\timage/svg+xml\n0\tstring\tAB\tAB\n");
+
+        assert_eq!(ms.buffer(b"<svg/>").unwrap(), "image/svg+xml; charset=us-ascii");
+        assert_eq!(ms.buffer(b"AB\xff\x00\x01").unwrap(), "application/octet-stream; charset=binary");
+        assert_eq!(ms.buffer("caf\u{e9}".as_bytes()).unwrap(), "text/plain; charset=utf-8");
+        assert_eq!(ms.buffer(b"").unwrap(), "application/x-empty; charset=binary");
+        assert_eq!(ms.buffer(b"{\"a\": 1}").unwrap(), "application/json; charset=us-ascii");
+
+        ms.set_flags(Flags::MIME_TYPE).unwrap();
+        assert_eq!(ms.buffer(b"<svg/>").unwrap(), "image/svg+xml");
+        ms.set_flags(Flags::MIME_ENCODING).unwrap();
+        assert_eq!(ms.buffer(b"<svg/>").unwrap(), "us-ascii");
+    }
+
+    #[test]
+    fn test_compress() {
+        let mut ms = MagicSet::new(Flags::COMPRESS).unwrap();
+        ms.load_str("0\tstring\t\\037\\213\tgzip compressed data\n!:mime\tapplication/gzip\n");
+
+        let mut gz = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gz.write_all(b"hello\n").unwrap();
+        let gz = gz.finish().unwrap();
+        assert_eq!(ms.buffer(&gz).unwrap(), "ASCII text (gzip compressed data)");
+        ms.set_flags(Flags::COMPRESS | Flags::MIME).unwrap();
+        assert_eq!(
+            ms.buffer(&gz).unwrap(),
+            "text/plain; charset=us-ascii compressed-encoding=application/gzip; charset=binary"
+        );
+        ms.set_flags(Flags::COMPRESS | Flags::MIME_TYPE).unwrap();
+        assert_eq!(ms.buffer(&gz).unwrap(), "text/plain");
+        ms.set_flags(Flags::COMPRESS | Flags::COMPRESS_TRANSP).unwrap();
+        assert_eq!(ms.buffer(&gz).unwrap(), "ASCII text");
+        ms.set_flags(Flags::NONE).unwrap();
+        assert_eq!(ms.buffer(&gz).unwrap(), "gzip compressed data");
+    }
+
+    #[test]
+    fn test_param() {
+        let mut ms = MagicSet::new(Flags::NONE).unwrap();
+        ms.load_str("\
+0\tstring\tAB\tAB
+>6\tstring\tGH\tGH
+0\tname\tloop
+>0\tuse\tloop
+0\tstring\tLOOP\tloop
+>0\tuse\tloop
+");
+
+        assert_eq!(ms.reader(&b"ABCDEFGH"[..]).unwrap(), "AB GH");
+        assert!(ms.buffer(b"LOOP").unwrap_err().contains("name use count (50) exceeded"));
+
+        let mut param = MagicParam::default();
+        param.set(Param::Bytes, 4).unwrap();
+        param.set(Param::Name, 3).unwrap();
+        ms.set_param(param);
+        assert_eq!(ms.reader(&b"ABCDEFGH"[..]).unwrap(), "AB");
+        assert_eq!(ms.buffer(b"ABCDEFGH").unwrap(), "AB");
+        assert!(ms.buffer(b"LOOP").unwrap_err().contains("name use count (3) exceeded"));
+    }
+
+    #[test]
+    fn test_preserve_atime() {
+        let path = env::temp_dir().join(format!("test_magic_set.{}.atime", process::id()));
+        fs::write(&path, "AB\x01").unwrap();
+        let day = Duration::from_secs(24 * 60 * 60);
+        let (accessed, modified) = (SystemTime::now() - day * 2, SystemTime::now() - day * 3);
+        let times = FileTimes::new().set_accessed(accessed).set_modified(modified);
+        File::options().write(true).open(&path).unwrap().set_times(times).unwrap();
+
+        let mut ms = MagicSet::new(Flags::PRESERVE_ATIME).unwrap();
+        ms.load_str("0\tstring\tAB\tAB\n");
+        let desc = ms.file(&path);
+        let meta = fs::metadata(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(desc.unwrap(), "AB");
+        assert_eq!((meta.accessed().unwrap(), meta.modified().unwrap()), (accessed, modified));
+    }
+}
@@ -0,0 +1,178 @@
+use std::fs;
+use std::path::Path;
+
+
+/// The built-in token table, in the format of the token files, after `names.h` of libmagic.
+///
+/// A line is a description, a MIME type and the tokens of a language separated by spaces,
+/// with the three separated by tabs.
+const BUILTIN: &str = "\
+C source\ttext/x-c\t#include #define #ifdef #ifndef #endif typedef struct union extern unsigned sizeof printf( malloc( NULL
+C++ source\ttext/x-c++\t#include template virtual namespace public: private: protected: std:: nullptr
+Java source\ttext/x-java\timport package public class extends implements @Override throws System.out.
+Python script\ttext/x-script.python\timport from def elif self None True False lambda __name__ print(
+POSIX shell script\ttext/x-shellscript\tfi esac ;; then echo export
+HTML document\ttext/html\t<html <HTML <head <HEAD <body <BODY <div <DIV <p> <P> <a <A <!-- href= HREF=
+makefile script\ttext/x-makefile\tCFLAGS LDFLAGS all: .PHONY: .PRECIOUS clean: $(CC) $(MAKE)
+assembler source\ttext/x-asm\t.ascii .asciz .asciiz .byte .globl .global .text .data .section .long movl
+Pascal source\ttext/x-pascal\t(input, program begin end; end. procedure writeln(
+M4 macro processor script\ttext/x-m4\tdnl define( divert( ifdef(
+GNU gettext message catalogue\ttext/x-po\tmsgid msgstr msgctxt
+BCPL source\ttext/x-bcpl\t\"libhdr\" \"LIBHDR\"
+PL/1 source\ttext/x-pl1\tdcl
+SMTP mail\tmessage/rfc822\tReceived: >From Return-Path: Cc:
+news or mail\tmessage/news\tNewsgroups: Path: Organization:
+";
+
+/// The fewest tokens a text must have of a language to be taken as it
+const MIN_HITS: usize = 2;
+
+
+/// A kind of text which is told by its tokens, as the `types` table of libmagic.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Lang {
+    pub(crate) desc: String,
+    pub(crate) mime: String,
+}
+
+/// The tokens which tell the kinds of text, as the `names` table of libmagic.
+#[derive(Debug, Clone)]
+pub(crate) struct TokenTable {
+    langs: Vec<Lang>,
+    /// The tokens and the indexes of their languages, where a token may be of many
+    tokens: Vec<(String, usize)>,
+}
+
+impl Default for TokenTable {
+    fn default() -> Self {
+        let mut table = TokenTable { langs: vec![], tokens: vec![] };
+        table.parse(BUILTIN, "built-in tokens").unwrap();
+        table
+    }
+}
+
+impl TokenTable {
+    /// Add the tokens of a language, which are added to the ones it has if it is already
+    /// in the table, where its MIME type is kept.
+    fn add(&mut self, desc: &str, mime: &str, tokens: &str) {
+        let lang = match self.langs.iter().position(|lang| lang.desc == desc) {
+            Some(lang) => lang,
+            None => {
+                self.langs.push(Lang { desc: desc.to_string(), mime: mime.to_string() });
+                self.langs.len() - 1
+            }
+        };
+        for token in tokens.split_whitespace() {
+            self.tokens.push((token.to_string(), lang));
+        }
+    }
+
+    /// Add the languages of a token file, where the empty lines and the ones starting with
+    /// `#` are skipped.
+    fn parse(&mut self, text: &str, name: &str) -> Result<(), String> {
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.splitn(3, '\t').map(str::trim).collect();
+            match fields[..] {
+                [desc, mime, tokens] if !desc.is_empty() && !mime.is_empty() && !tokens.is_empty() =>
+                    self.add(desc, mime, tokens),
+                _ => return Err(format!(
+                    "{}:{}: expected a description, a MIME type and tokens separated by tabs",
+                    name, i + 1
+                )),
+            }
+        }
+        Ok(())
+    }
+
+    /// Extend the table by a token file.
+    pub(crate) fn load(&mut self, path: &Path) -> Result<(), String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.parse(&text, &path.display().to_string())
+    }
+
+    /// Return the language which has the most tokens among the words of a text, and at
+    /// least `MIN_HITS` of them, where a tie goes to the one whose first token comes first.
+    ///
+    /// A token matches a word which starts with it, and which does not go on with an
+    /// alphanumeric char if the token ends with one, so `int` matches `int*` but not
+    /// `integer`, and `<html` matches `<html>`.
+    pub(crate) fn classify(&self, text: &[u8]) -> Option<&Lang> {
+        // the number of hits and the index of the first hit of every language
+        let mut hits = vec![(0, usize::MAX); self.langs.len()];
+        let is_space = |c: &u8| matches!(c, b' ' | b'\t' | b'\r' | b'\n' | b'\x0c');
+        let words = text.split(is_space).filter(|word| !word.is_empty());
+        for (i, word) in words.enumerate() {
+            for (token, lang) in &self.tokens {
+                if matches(token.as_bytes(), word) {
+                    let (n, first) = &mut hits[*lang];
+                    *n += 1;
+                    *first = (*first).min(i);
+                }
+            }
+        }
+        let (lang, _) = hits.iter()
+            .enumerate()
+            .filter(|(_, (n, _))| *n >= MIN_HITS)
+            .max_by(|(_, (n1, first1)), (_, (n2, first2))| n1.cmp(n2).then(first2.cmp(first1)))?;
+        Some(&self.langs[lang])
+    }
+}
+
+fn matches(token: &[u8], word: &[u8]) -> bool {
+    let is_word = |c: u8| c.is_ascii_alphanumeric() || c == b'_';
+    word.starts_with(token)
+        && (!token.last().copied().is_some_and(is_word) || !word.get(token.len()).copied().is_some_and(is_word))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{matches, TokenTable};
+
+    #[test]
+    fn test_matches() {
+        assert!(matches(b"int", b"int"));
+        assert!(matches(b"int", b"int*"));
+        assert!(!matches(b"int", b"integer"));
+        assert!(matches(b"<html", b"<html>"));
+        assert!(matches(b"href=", b"href=\"a.html\">"));
+        assert!(!matches(b"def", b"de"));
+    }
+
+    #[test]
+    fn test_classify() {
+        let table = TokenTable::default();
+        let desc = |text: &str| table.classify(text.as_bytes()).map(|lang| lang.desc.as_str());
+
+        assert_eq!(desc("#include <stdio.h>\nint main(void) { printf(\"hi\\n\"); }\n"), Some("C source"));
+        assert_eq!(desc("#include <iostream>\nint main() { std::cout << 1; }\n"), Some("C++ source"));
+        assert_eq!(desc("import os\nimport sys\n\ndef main():\n    pass\n"), Some("Python script"));
+        assert_eq!(desc("import java.util.List;\npublic class A {}\n"), Some("Java source"));
+        assert_eq!(desc("<html>\n<body>hello</body>\n</html>\n"), Some("HTML document"));
+        assert_eq!(desc("if [ -n \"$1\" ]; then\n  echo \"$1\"\nfi\n"), Some("POSIX shell script"));
+        assert_eq!(table.classify(b"msgid \"a\"\nmsgstr \"b\"\n").unwrap().mime, "text/x-po");
+        // a single token is not enough
+        assert_eq!(desc("Then we went home.\n"), None);
+        assert_eq!(desc("hello world\n"), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut table = TokenTable::default();
+        table.parse("# Lua\nLua script\ttext/x-lua\tlocal function end\n\nC source\ttext/plain\tuint8_t\n", "test").unwrap();
+        let lang = table.classify(b"local function f()\nend\n").unwrap();
+        assert_eq!((lang.desc.as_str(), lang.mime.as_str()), ("Lua script", "text/x-lua"));
+        // the tokens are added to the language which is in the table
+        let lang = table.classify(b"uint8_t a;\nuint8_t b;\n").unwrap();
+        assert_eq!((lang.desc.as_str(), lang.mime.as_str()), ("C source", "text/x-c"));
+
+        assert_eq!(
+            table.parse("Lua script text/x-lua local\n", "test").unwrap_err(),
+            "test:1: expected a description, a MIME type and tokens separated by tabs"
+        );
+    }
+}
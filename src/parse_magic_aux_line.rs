@@ -1,9 +1,13 @@
+use std::fmt;
+
 use regex::{Regex};
+use serde::{Deserialize, Serialize};
 
 use crate::str_utils;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
 pub(crate) enum FactorOp {
+    #[default]
     Noop,
     Plus,
     Minus,
@@ -11,12 +15,6 @@ pub(crate) enum FactorOp {
     Divide,
 }
 
-impl Default for FactorOp {
-    fn default() -> Self {
-        FactorOp::Noop
-    }
-}
-
 impl From<&str> for FactorOp {
     fn from(s: &str) -> Self {
         match s {
@@ -29,27 +27,124 @@ impl From<&str> for FactorOp {
     }
 }
 
-#[derive(Debug, Default, PartialEq)]
+impl fmt::Display for FactorOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            FactorOp::Noop => "",
+            FactorOp::Plus => "+",
+            FactorOp::Minus => "-",
+            FactorOp::Multiply => "*",
+            FactorOp::Divide => "/",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
 pub(crate) struct AuxTypes {
     mime: Option<String>,
     apple: Option<String>,
     exts: Vec<String>,
 }
 
-#[derive(Debug, Default, PartialEq)]
+impl AuxTypes {
+    pub(crate) fn mime(&self) -> Option<&str> {
+        self.mime.as_deref()
+    }
+
+    /// The creator and type codes of Apple, as 8 chars.
+    pub(crate) fn apple(&self) -> Option<&str> {
+        self.apple.as_deref()
+    }
+
+    pub(crate) fn exts(&self) -> &[String] {
+        &self.exts
+    }
+
+    /// Merge the types given by another aux line into this one.
+    pub(crate) fn merge(&mut self, other: AuxTypes) {
+        if other.mime.is_some() {
+            self.mime = other.mime;
+        }
+        if other.apple.is_some() {
+            self.apple = other.apple;
+        }
+        self.exts.extend(other.exts);
+    }
+}
+
+impl fmt::Display for AuxTypes {
+    /// Write the aux lines, separated by newlines.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut lines = vec![];
+        if let Some(mime) = &self.mime {
+            lines.push(format!("!:mime\t{}", mime));
+        }
+        if let Some(apple) = &self.apple {
+            lines.push(format!("!:apple\t{}", apple));
+        }
+        if !self.exts.is_empty() {
+            lines.push(format!("!:ext\t{}", self.exts.join("/")));
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
 pub(crate) struct AuxFactor {
     op: FactorOp,
     val: u32,
 }
 
-#[derive(Debug, PartialEq)]
+impl AuxFactor {
+    /// Apply the factor to the strength of an entry.
+    pub(crate) fn apply(&self, strength: i64) -> i64 {
+        let val = self.val as i64;
+        match self.op {
+            FactorOp::Noop => strength,
+            FactorOp::Plus => strength + val,
+            FactorOp::Minus => strength - val,
+            FactorOp::Multiply => strength * val,
+            FactorOp::Divide if val == 0 => strength,
+            FactorOp::Divide => strength / val,
+        }
+    }
+}
+
+impl fmt::Display for AuxFactor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.op, self.val)
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub(crate) enum AuxInfo {
     Types(AuxTypes),
     Strength(AuxFactor),
 }
 
+impl fmt::Display for AuxInfo {
+    /// Write the aux lines, separated by newlines.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuxInfo::Types(types) => write!(f, "{}", types),
+            AuxInfo::Strength(factor) => write!(f, "!:strength\t{}", factor),
+        }
+    }
+}
+
 impl AuxInfo {
-    pub(crate) fn parse_aux_line(s: &str) -> AuxInfo {
+    /// Return the name of the aux line, such as `mime`, for the info parsed from one line.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            AuxInfo::Types(AuxTypes { mime: Some(_), .. }) => "mime",
+            AuxInfo::Types(AuxTypes { apple: Some(_), .. }) => "apple",
+            AuxInfo::Types(_) => "ext",
+            AuxInfo::Strength(_) => "strength",
+        }
+    }
+
+    pub(crate) fn parse_aux_line(s: &str) -> Result<AuxInfo, String> {
         let re = Regex::new(r"(?x)
             (?P<t>mime|ext|apple|strength)
             \s*
@@ -65,37 +160,44 @@ impl AuxInfo {
                 "ext" => { Self::parse_line_ext(aux_val) }
                 "apple" => { Self::parse_line_apple(aux_val) }
                 "strength" => { Self::parse_line_strength(aux_val) }
-                _ => panic!("Unsupport aux type: {}!", aux_type)
+                _ => Err(format!("Unsupport aux type: {}!", aux_type))
             }
         } else {
-            panic!("Failed to parse aux line: {}!", s)
+            Err(format!("Failed to parse aux line: {}!", s))
         }
     }
 
-    fn parse_line_mime(s: &str) -> AuxInfo {
-        AuxInfo::Types(AuxTypes {
-            mime: str_utils::ensure_goodchars(s, "+-/.$?:{}"),
+    fn parse_line_mime(s: &str) -> Result<AuxInfo, String> {
+        Ok(AuxInfo::Types(AuxTypes {
+            mime: Some(
+                str_utils::ensure_goodchars(s, "+-/.$?:{}")
+                    .ok_or_else(|| format!("Invalid mime type: {}!", s))?
+            ),
             ..AuxTypes::default()
-        })
+        }))
     }
 
-    fn parse_line_ext(s: &str) -> AuxInfo {
-        AuxInfo::Types(AuxTypes {
+    fn parse_line_ext(s: &str) -> Result<AuxInfo, String> {
+        Ok(AuxInfo::Types(AuxTypes {
             exts: str_utils::ensure_goodchars(s, ",!+-/@?_$")
-                .unwrap().split('/').collect::<Vec<&str>>()
-                .iter().map(|&e| e.to_string()).collect(),
+                .ok_or_else(|| format!("Invalid extension list: {}!", s))?
+                .split('/')
+                .map(|e| e.to_string()).collect(),
             ..AuxTypes::default()
-        })
+        }))
     }
 
-    fn parse_line_apple(s: &str) -> AuxInfo {
-        AuxInfo::Types(AuxTypes {
-            apple: str_utils::ensure_goodchars(s, "!+-./?"),
+    fn parse_line_apple(s: &str) -> Result<AuxInfo, String> {
+        Ok(AuxInfo::Types(AuxTypes {
+            apple: Some(
+                str_utils::ensure_goodchars(s, "!+-./?")
+                    .ok_or_else(|| format!("Invalid apple type: {}!", s))?
+            ),
             ..AuxTypes::default()
-        })
+        }))
     }
 
-    fn parse_line_strength(s: &str) -> AuxInfo {
+    fn parse_line_strength(s: &str) -> Result<AuxInfo, String> {
         let re = Regex::new(r"(?x)
             (?P<o>[+-/*])
             \s*
@@ -104,12 +206,12 @@ impl AuxInfo {
 
         match re.captures(s) {
             Some(cap) => {
-                AuxInfo::Strength(AuxFactor {
+                Ok(AuxInfo::Strength(AuxFactor {
                     op: cap.name("o").unwrap().as_str().into(),
                     val: cap.name("v").unwrap().as_str().parse::<u32>().unwrap(),
-                })
+                }))
             }
-            None => { panic!("Failed to parse strength line: {}!", s) }
+            None => { Err(format!("Failed to parse strength line: {}!", s)) }
         }
     }
 }
@@ -125,12 +227,12 @@ mod testcase {
         let ext = "txt/doc/pyc";
         let aux = AuxInfo::parse_line_ext(ext);
         assert_eq!(
-            AuxInfo::Types(AuxTypes {
-                exts: vec!["txt", "doc", "pyc", ].iter()
-                    .map(|&s| s)
+            Ok(AuxInfo::Types(AuxTypes {
+                exts: ["txt", "doc", "pyc", ].iter()
+                    .copied()
                     .map(String::from).collect(),
                 ..AuxTypes::default()
-            }), aux
+            })), aux
         );
     }
 
@@ -142,7 +244,7 @@ mod testcase {
         ];
         for (s, expect) in testcases {
             let aux = AuxInfo::parse_line_strength(s);
-            assert_eq!(expect, aux);
+            assert_eq!(Ok(expect), aux);
         }
     }
 
@@ -1,55 +1,252 @@
+use std::fmt;
 use std::io;
-use std::path::Path;
-use std::fs::File;
-use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
 
 use crate::parse_magic_line::MagicLine;
-use crate::parse_magic_aux_line::AuxFactor;
+use crate::parse_magic_aux_line::{AuxFactor, AuxInfo};
+use crate::source_loc::SourceLoc;
 
 
-#[derive(Debug, Default)]
-struct MagicEntry {
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct MagicEntry {
     lines: Vec<MagicLine>,
     factor: Option<AuxFactor>,
+    factor_loc: Option<SourceLoc>,
 }
 
 impl MagicEntry {
-    fn parse() -> io::Result<()> {
+    /// The lines of the entry, where the first one is at level 0.
+    pub(crate) fn lines(&self) -> &[MagicLine] {
+        &self.lines
+    }
+
+    pub(crate) fn factor(&self) -> Option<&AuxFactor> {
+        self.factor.as_ref()
+    }
+}
+
+impl fmt::Display for MagicEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, line) in self.lines.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", line)?;
+            if i == 0 {
+                if let Some(factor) = &self.factor {
+                    write!(f, "\n!:strength\t{}", factor)?;
+                }
+            }
+        }
         Ok(())
     }
+}
+
 
+/// Split the contents of a file into lines with their byte spans, which exclude the line
+/// breaks of both LF and CRLF, where the invalid utf-8 sequences are replaced.
+fn split_lines(buf: &[u8]) -> Vec<(Range<usize>, String)> {
+    let buf = buf.strip_suffix(b"\n").unwrap_or(buf);
+    if buf.is_empty() {
+        return vec![];
+    }
+    let mut start = 0;
+    buf.split(|&b| b == b'\n')
+        .map(|line| {
+            let text_len = line.iter().rposition(|&b| b != b'\r').map_or(0, |i| i + 1);
+            let span = start..start + text_len;
+            start += line.len() + 1;
+            (span, String::from_utf8_lossy(&line[..text_len]).to_string())
+        })
+        .collect()
 }
 
 
-fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
-    where P: AsRef<Path>, {
-    let file = File::open(filename)?;
-    Ok(io::BufReader::new(file).lines())
+/// A line of a magic file, classified by `MagicFile::parse_lines`.
+#[derive(Debug)]
+pub(crate) enum SourceLine {
+    Blank,
+    Comment(String),
+    Magic(MagicLine),
+    Aux(AuxInfo),
+    /// The line fails to parse, which is kept as it is
+    Invalid { text: String, msg: String },
 }
 
+impl SourceLine {
+    fn parse(line: &str) -> SourceLine {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() {
+            return SourceLine::Blank;
+        }
+        if trimmed.starts_with('#') {
+            return SourceLine::Comment(line.trim_end().to_string());
+        }
+
+        let res =
+            if let Some(aux) = trimmed.strip_prefix("!:") {
+                AuxInfo::parse_aux_line(aux).map(SourceLine::Aux)
+            } else {
+                let mut magic_line = MagicLine::default();
+                magic_line.parse_entry_line(trimmed).map(|_| SourceLine::Magic(magic_line))
+            };
+        res.unwrap_or_else(|msg| SourceLine::Invalid { text: line.trim_end().to_string(), msg })
+    }
+
+    /// Return true if the line starts a new entry, even if it is an invalid one.
+    pub(crate) fn is_entry_line(&self) -> bool {
+        match self {
+            SourceLine::Magic(line) => line.cont_lvl() == 0,
+            SourceLine::Invalid { text, .. } => {
+                let text = text.trim_start();
+                !text.starts_with("!:") && MagicLine::is_entry_line(text)
+            }
+            _ => false,
+        }
+    }
+
+    /// Return true if the line is an aux line, even if it is an invalid one.
+    pub(crate) fn is_aux_line(&self) -> bool {
+        match self {
+            SourceLine::Aux(_) => true,
+            SourceLine::Invalid { text, .. } => text.trim_start().starts_with("!:"),
+            _ => false,
+        }
+    }
+}
 
-struct MagicFile {
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct MagicFile {
+    path: PathBuf,
     entries: Vec<MagicEntry>,
+    /// The warnings of the invalid lines, which are skipped
+    #[serde(skip)]
+    warnings: Vec<String>,
+}
+
+impl fmt::Display for MagicFile {
+    /// Write the entries in the canonical magic(5) syntax, separated by blank lines.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
 }
 
 impl MagicFile {
-    fn parse(magic_file: &Path) -> io::Result<()> {
-        let lines = read_lines(magic_file)?;
-        for (line_no, line) in lines.enumerate() {
-            let line = line?;
-            let chars: Vec<char> = line.chars().collect();
-            if !chars.is_empty() {
-                match chars[0] {
-                    '#' => continue,  // comment line, ignore
-                    '!' => {
-                        if chars[1] == ':' {
-                            // parse_aux_line(&line[2..])
-                        }
-                    },
-                    _ => {
-                        println!("line '{}' at {}", line, line_no)
-                    }
+    /// List the magic files at `path`, which is either a file or a directory of files.
+    pub(crate) fn list(path: &Path) -> io::Result<Vec<PathBuf>> {
+        if !path.is_dir() {
+            return Ok(vec![path.to_path_buf()]);
+        }
+
+        let mut files = vec![];
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                files.push(entry.path());
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Parse and classify each line of a magic file, including the comments and the
+    /// invalid lines.
+    pub(crate) fn parse_lines(magic_file: &Path) -> io::Result<Vec<(SourceLoc, SourceLine)>> {
+        Ok(Self::lines_from_bytes(magic_file, &fs::read(magic_file)?))
+    }
+
+    /// Parse and classify each line of the contents of a magic file, which is named by
+    /// `magic_file` in the locations.
+    pub(crate) fn lines_from_bytes(magic_file: &Path, buf: &[u8]) -> Vec<(SourceLoc, SourceLine)> {
+        split_lines(buf).into_iter().enumerate()
+            .map(|(line_no, (span, line))| {
+                let loc = SourceLoc { path: magic_file.to_path_buf(), line_no: line_no + 1, span };
+                let mut line = SourceLine::parse(&line);
+                if let SourceLine::Magic(magic_line) = &mut line {
+                    magic_line.set_loc(loc.clone());
                 }
+                (loc, line)
+            })
+            .collect()
+    }
+
+    /// Parse a magic file, where the invalid lines are skipped as libmagic does, and kept
+    /// in the warnings.
+    pub(crate) fn parse(magic_file: &Path) -> io::Result<MagicFile> {
+        Ok(Self::from_bytes(magic_file, &fs::read(magic_file)?))
+    }
+
+    /// Parse the contents of a magic file, which is named by `magic_file` in the locations.
+    pub(crate) fn from_bytes(magic_file: &Path, buf: &[u8]) -> MagicFile {
+        let mut magic = MagicFile {
+            path: magic_file.to_path_buf(),
+            entries: vec![],
+            warnings: vec![],
+        };
+        // set when the entry line fails, so that its continuations are skipped too
+        let mut skip_entry = false;
+
+        for (loc, line) in Self::lines_from_bytes(magic_file, buf) {
+            if line.is_entry_line() {
+                skip_entry = matches!(line, SourceLine::Invalid { .. });
+            }
+
+            let res = match line {
+                SourceLine::Blank | SourceLine::Comment(_) => continue,
+                SourceLine::Magic(_) | SourceLine::Aux(_) if skip_entry => continue,
+                SourceLine::Magic(magic_line) => magic.add_magic_line(magic_line),
+                SourceLine::Aux(aux) => magic.add_aux_line(aux, loc.clone()),
+                SourceLine::Invalid { msg, .. } => Err(msg),
+            };
+
+            if let Err(msg) = res {
+                magic.warnings.push(format!("{}: Warning: {}", loc, msg));
+            }
+        }
+        magic
+    }
+
+    pub(crate) fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    pub(crate) fn into_entries(self) -> Vec<MagicEntry> {
+        self.entries
+    }
+
+    fn add_magic_line(&mut self, magic_line: MagicLine) -> Result<(), String> {
+        if magic_line.cont_lvl() == 0 {
+            self.entries.push(MagicEntry::default());
+        }
+
+        let entry = self.entries.last_mut()
+            .ok_or_else(|| "No current entry for continuation".to_string())?;
+        entry.lines.push(magic_line);
+        Ok(())
+    }
+
+    fn add_aux_line(&mut self, aux: AuxInfo, loc: SourceLoc) -> Result<(), String> {
+        let entry = self.entries.last_mut()
+            .ok_or_else(|| "No current entry for aux line".to_string())?;
+        match aux {
+            AuxInfo::Strength(factor) => {
+                entry.factor = Some(factor);
+                entry.factor_loc = Some(loc);
+            }
+            AuxInfo::Types(types) => {
+                // aux types are attached to the last line of the entry
+                entry.lines.last_mut().unwrap().add_aux_types(types, loc);
             }
         }
         Ok(())
@@ -59,7 +256,154 @@ impl MagicFile {
 
 #[cfg(test)]
 mod tests {
+    use std::path::Path;
+
+    use super::{split_lines, MagicFile};
+
+    fn clear_locs(magic: &mut MagicFile) {
+        for entry in magic.entries.iter_mut() {
+            entry.factor_loc = None;
+            entry.lines.iter_mut().for_each(|line| line.clear_locs());
+        }
+    }
+
+    /// Assert that parse -> print -> parse gives the same rules, regardless of where they are.
+    fn assert_round_trip(mut magic: MagicFile) {
+        let path = magic.path.clone();
+        let mut reparsed = MagicFile::from_bytes(&path, magic.to_string().as_bytes());
+        clear_locs(&mut magic);
+        clear_locs(&mut reparsed);
+        assert_same_entries(&path, &magic, &reparsed);
+
+        // and in the canonical syntax, as the rules which are not parsed from a source
+        let mut reparsed = MagicFile::from_bytes(&path, magic.to_string().as_bytes());
+        clear_locs(&mut reparsed);
+        assert_same_entries(&path, &magic, &reparsed);
+    }
+
+    fn assert_same_entries(path: &Path, magic: &MagicFile, reparsed: &MagicFile) {
+        assert_eq!(magic.entries.len(), reparsed.entries.len(), "{}", path.display());
+        for (entry, reparsed) in magic.entries.iter().zip(reparsed.entries.iter()) {
+            assert_eq!(entry, reparsed, "{}:\n{}", path.display(), entry);
+        }
+    }
+
     #[test]
     fn test() {
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_split_lines() {
+        assert_eq!(split_lines(b"a\nbc\n"), vec![(0..1, "a".to_string()), (2..4, "bc".to_string())]);
+        assert_eq!(split_lines(b"a\r\nbc\r\n\r\nd"), vec![
+            (0..1, "a".to_string()), (3..5, "bc".to_string()), (7..7, String::new()), (9..10, "d".to_string()),
+        ]);
+        assert_eq!(split_lines(b"\xffa\n"), vec![(0..2, "\u{fffd}a".to_string())]);
+        assert!(split_lines(b"\n").is_empty());
+    }
+
+    #[test]
+    fn test_parse_magic_file() {
+        let path = Path::new("test_parse_magic_file.magic");
+        let magic = MagicFile::from_bytes(path, b"\
+# comment line
+0	string	\\x7fELF	ELF
+!:strength +10
+>4	byte	1	32-bit
+!:mime	application/x-executable
+!:ext	elf/so
+>4	nosuchtype	2	64-bit
+>>5	byte	1	LSB
+
+0	short
+>0	byte	x	skipped too
+!:mime	application/x-short
+
+0	nosuchtype	x	broken
+>0	byte	x	skipped
+!:mime	application/x-broken
+");
+
+        assert_eq!(magic.entries.len(), 1);
+        let entry = &magic.entries[0];
+        assert_eq!(entry.lines.len(), 3);
+        assert!(entry.factor.is_some());
+        assert_eq!(entry.factor_loc.as_ref().unwrap().line_no, 3);
+        assert_eq!(entry.lines[2].cont_lvl(), 2);
+
+        let loc = entry.lines[1].loc();
+        assert_eq!(loc.path, path);
+        assert_eq!(loc.line_no, 4);
+        assert_eq!(loc.span, 51..67);
+        let aux_lines: Vec<usize> = entry.lines[1].aux_locs().iter().map(|loc| loc.line_no).collect();
+        assert_eq!(aux_lines, vec![5, 6]);
+        assert_eq!(entry.lines[2].loc().line_no, 8);
+        // the continuations of an entry line with missing fields are not added to the entry
+        assert!(entry.lines[2].aux_types().is_none());
+    }
+
+    #[test]
+    fn test_print_round_trip() {
+        let magic = MagicFile::from_bytes(Path::new("test_print_round_trip.magic"), b"\
+0	name	elf-le
+>16	leshort	2	executable,
+!:mime	application/x-executable
+>>18	leshort	62	x86-64
+0	string	\\177ELF	ELF
+!:strength * 2
+>5	byte	2	MSB
+>>0	use	\\^elf-le
+0	string	\\037\\213	gzip compressed data
+!:ext	gz/tgz
+!:apple	????GZIP
+>3	byte	&0x08	\\b, was \"%s\"
+>-4	ulelong	x	\\b, original size modulo 2^32 %u
+0	string/b	MZ
+>0x18	uleshort	>	0x3f
+>>(0x3c.l)	string	PE\\0\\0	PE
+>>>&(&0x3c,S-(2))	byte	x	\\b, weird %d|12
+0	search/4096/cW	\\<!doctype\\ html	HTML document text
+0	belong&0xfffffff0	0xcafebab0	masked
+>4	lefloat	>1.5	float %f
+>8	string	x	%s
+>8	string	\\x	literal x
+>8	string	=	empty
+>12	pstring/HJ	!foo\\ bar\\ \\0\\01
+");
+        assert_round_trip(magic);
+    }
+
+    /// Round trip the magic files in a directory, and return how many there are.
+    fn round_trip_dir(dir: &Path) -> usize {
+        let files = MagicFile::list(dir).unwrap();
+        for path in &files {
+            assert_round_trip(MagicFile::parse(path).unwrap());
+        }
+        files.len()
+    }
+
+    /// Round trip the magic files bundled with the tests, which have indirect offsets, the
+    /// flags of strings and regexes, and aux lines.
+    #[test]
+    fn test_print_round_trip_fixtures() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/magic");
+        for path in MagicFile::list(&dir).unwrap() {
+            let magic = MagicFile::parse(&path).unwrap();
+            assert!(magic.warnings.is_empty(), "{:?}", magic.warnings);
+        }
+        assert_eq!(round_trip_dir(&dir), 3);
+    }
+
+    /// Round trip the source magic files of the system, which are not installed by most
+    /// distributions, so run it with `cargo test -- --ignored` where they are.
+    #[test]
+    #[ignore]
+    fn test_print_round_trip_system_db() {
+        let count: usize = ["/usr/share/file/magic", "/usr/share/misc/magic"].iter()
+            .map(Path::new)
+            .filter(|dir| dir.is_dir())
+            .map(round_trip_dir)
+            .sum();
+        assert!(count > 0, "no source magic files found");
+    }
+}
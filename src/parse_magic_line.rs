@@ -1,11 +1,15 @@
-use regex::{Regex, Match};
+use std::fmt;
 
-use crate::magic::{CmpType, MaskOp, StrModifier, RelnOp};
-use crate::parse_magic_aux_line::AuxInfo;
+use serde::{Deserialize, Serialize};
 
+use crate::magic::{CmpType, MaskOp, StrModifier, RelnOp, RelnVal};
+use crate::parse_magic_aux_line::{AuxInfo, AuxTypes};
+use crate::source_loc::SourceLoc;
+use crate::str_utils;
 
-#[derive(Debug, PartialEq)]
-enum Mask {
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Mask {
     Num { op: MaskOp, val: u64 },
     Str { flags: StrModifier, range: u64 },
 }
@@ -16,172 +20,630 @@ impl Default for Mask {
     }
 }
 
+impl fmt::Display for Mask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mask::Num { op: MaskOp::Noop, .. } => Ok(()),
+            Mask::Num { op: op @ (MaskOp::And | MaskOp::Or | MaskOp::Xor), val } => {
+                write!(f, "{}{:#x}", op, val)
+            }
+            Mask::Num { op, val } => write!(f, "{}{}", op, fmt_num(*val as i64)),
+            Mask::Str { flags, range } => {
+                if *range != 0 {
+                    write!(f, "/{}", range)?;
+                }
+                if !flags.is_empty() {
+                    write!(f, "/{}", flags.to_chars())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+
+/// Format a number in decimal if it is small, otherwise in hexadecimal.
+fn fmt_num(num: i64) -> String {
+    let sign = if num < 0 { "-" } else { "" };
+    match num.unsigned_abs() {
+        n if n < 0x100 => format!("{}{}", sign, n),
+        n => format!("{}{:#x}", sign, n),
+    }
+}
+
+
+/// Offset expression such as `&4`, `(0x3c.l)` or `&(&0x10.s*2)`.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Offset {
+    /// `&` before the base: relative to the end of the last match of the upper level
+    pub(crate) relative: bool,
+    /// Negative base is counted from the end of the buffer
+    pub(crate) base: i64,
+    pub(crate) indir: Option<IndirOffset>,
+}
+
+/// The indirect part of an offset expression, that is `(base.t+adj)`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct IndirOffset {
+    /// `&` before `(`: the dereferenced offset is relative to the end of the last match
+    pub(crate) relative: bool,
+    /// `,` instead of `.`: the pointer is read as a signed value
+    pub(crate) signed: bool,
+    pub(crate) typ: CmpType,
+    pub(crate) op: MaskOp,
+    /// `(adj)` instead of `adj`: the adjustment is read from the buffer too
+    pub(crate) adj_indir: bool,
+    pub(crate) adj: i64,
+}
+
+impl Offset {
+    fn parse(s: &str) -> Result<Offset, String> {
+        let (relative, s) = match s.strip_prefix('&') {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+
+        let s = match s.strip_prefix('(') {
+            Some(s) => s,
+            None => {
+                return match str_utils::parse_int_prefix(s) {
+                    Some((base, "")) => Ok(Offset { relative, base, indir: None }),
+                    _ => Err(format!("Invalid offset `{}'", s)),
+                };
+            }
+        };
+
+        let (inner_relative, s) = match s.strip_prefix('&') {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+        let (base, mut s) = str_utils::parse_int_prefix(s)
+            .ok_or_else(|| format!("Invalid indirect offset base `{}'", s))?;
+
+        let mut signed = false;
+        let mut typ = CmpType::Long;
+        if s.starts_with('.') || s.starts_with(',') {
+            signed = s.starts_with(',');
+            let c = s[1..].chars().next().unwrap_or(')');
+            typ = IndirOffset::type_from_char(c)
+                .ok_or_else(|| format!("Invalid indirect offset type `{}'", c))?;
+            s = &s[1 + c.len_utf8()..];
+        }
+
+        let mut op = MaskOp::Noop;
+        let mut adj_indir = false;
+        let mut adj = 0;
+        if s.starts_with(|c: char| "+-*/&|^%".contains(c)) {
+            op = MaskOp::from(&s[..1]);
+            s = &s[1..];
+            if let Some(rest) = s.strip_prefix('(') {
+                adj_indir = true;
+                s = rest;
+            }
+            let (val, rest) = str_utils::parse_int_prefix(s)
+                .ok_or_else(|| format!("Invalid indirect offset adjustment `{}'", s))?;
+            adj = val;
+            s = rest;
+            if adj_indir {
+                s = s.strip_prefix(')')
+                    .ok_or_else(|| "Missing `)' in indirect offset adjustment".to_string())?;
+            }
+        }
+
+        match s {
+            ")" => Ok(Offset {
+                relative: inner_relative,
+                base,
+                indir: Some(IndirOffset { relative, signed, typ, op, adj_indir, adj }),
+            }),
+            _ => Err(format!("Missing `)' in indirect offset, got `{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Offset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rel = if self.relative { "&" } else { "" };
+        match &self.indir {
+            None => write!(f, "{}{}", rel, fmt_num(self.base)),
+            Some(indir) => {
+                if indir.relative {
+                    write!(f, "&")?;
+                }
+                write!(f, "({}{}", rel, fmt_num(self.base))?;
+                if let Some(c) = IndirOffset::type_to_char(&indir.typ) {
+                    write!(f, "{}{}", if indir.signed { ',' } else { '.' }, c)?;
+                }
+                if indir.op != MaskOp::Noop {
+                    if indir.adj_indir {
+                        write!(f, "{}({})", indir.op, fmt_num(indir.adj))?;
+                    } else {
+                        write!(f, "{}{}", indir.op, fmt_num(indir.adj))?;
+                    }
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
 
-#[derive(Debug, Default)]
+impl IndirOffset {
+    fn type_to_char(typ: &CmpType) -> Option<char> {
+        match typ {
+            CmpType::LELong => Some('l'),
+            CmpType::BELong => Some('L'),
+            CmpType::MELong => Some('m'),
+            CmpType::LEShort => Some('s'),
+            CmpType::BEShort => Some('S'),
+            CmpType::Byte => Some('b'),
+            CmpType::LEDouble => Some('e'),
+            CmpType::BEDouble => Some('E'),
+            CmpType::LEID3 => Some('i'),
+            CmpType::BEID3 => Some('I'),
+            CmpType::LEQuad => Some('q'),
+            CmpType::BEQuad => Some('Q'),
+            _ => None,
+        }
+    }
+
+    fn type_from_char(c: char) -> Option<CmpType> {
+        match c {
+            'l' => Some(CmpType::LELong),
+            'L' => Some(CmpType::BELong),
+            'm' => Some(CmpType::MELong),
+            'h' | 's' => Some(CmpType::LEShort),
+            'H' | 'S' => Some(CmpType::BEShort),
+            'c' | 'b' | 'C' | 'B' => Some(CmpType::Byte),
+            'e' | 'f' | 'g' => Some(CmpType::LEDouble),
+            'E' | 'F' | 'G' => Some(CmpType::BEDouble),
+            'i' => Some(CmpType::LEID3),
+            'I' => Some(CmpType::BEID3),
+            'q' => Some(CmpType::LEQuad),
+            'Q' => Some(CmpType::BEQuad),
+            _ => None,
+        }
+    }
+}
+
+
+/// The fields of a magic line which hold numbers, as they are written in the source, so
+/// that they are printed back in the bases and the widths chosen by the author.
+#[derive(Debug, Default, PartialEq)]
+struct Spelling {
+    offset: String,
+    typ: String,
+    /// The relation of a numeric type without the whitespaces, which is `None` for strings
+    reln: Option<String>,
+}
+
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
 pub(crate) struct MagicLine {
     cont_lvl: usize,
+    offset: Offset,
     typ_code: u32,
     cmp_type: CmpType,
     cmp_unsigned: bool,
     mask: Mask,
     reln_op: RelnOp,
-    reln_val: u64,
+    reln_val: RelnVal,
     aux: Option<AuxInfo>,
     desc: String,
+    loc: SourceLoc,
+    /// Where the aux lines attached to this line are
+    aux_locs: Vec<SourceLoc>,
+    /// The numbers as written, which is `None` if the line is not parsed from a source
+    #[serde(skip)]
+    spelling: Option<Box<Spelling>>,
 }
 
 
-impl MagicLine {
-    fn line_regex() -> Regex {
-        // todo!("support parse the string contains escaped characters");
-        Regex::new(r"(?x)
-            (?P<c>>*)         # continue level
-            (?P<o>[^\s]+)     # offset expression
-            \s+
-            (?P<t>\w+)                 # type of comparison
-            (?P<m>[+\-*/&|^%][^\s]+)?  # mask expression
-            \s+
-            (?P<r>[^\s]+)     # relation expression
-            \s+
-            (?P<d>[^|]*)      # description
-            \|?
-            (?P<n>\d*)?     # type code
-        ").unwrap()
+impl fmt::Display for MagicLine {
+    /// Write the line in the canonical magic(5) syntax, followed by its aux lines.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.fields().join("\t"))?;
+        if let Some(aux) = &self.aux {
+            let aux = aux.to_string();
+            if !aux.is_empty() {
+                write!(f, "\n{}", aux)?;
+            }
+        }
+        Ok(())
     }
+}
+
+
+/// The whitespace-separated fields of a magic line.
+struct LineFields<'a> {
+    cont: &'a str,
+    ofst: &'a str,
+    typ: &'a str,
+    reln: &'a str,
+    desc: &'a str,
+}
+
+impl<'a> LineFields<'a> {
+    fn split(line: &'a str) -> Option<LineFields<'a>> {
+        let cont_end = line.find(|c| c != '>').unwrap_or(line.len());
+        let (cont, rest) = line.split_at(cont_end);
+        let (ofst, rest) = Self::next_field(rest)?;
+        let (typ, rest) = Self::next_field(rest)?;
+        let (mut reln, mut rest) = Self::next_field(rest)?;
+
+        // `strtoull' used by libmagic skips the whitespaces between a numeric relation
+        // and its value, as in `>4 lelong > 0'
+        if reln.len() == 1 && "=!<>&^".contains(reln) {
+            if let Some((val, val_rest)) = Self::next_field(rest) {
+                if val.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+                    let start = line.len() - rest.len() - reln.len();
+                    reln = &line[start..line.len() - val_rest.len()];
+                    rest = val_rest;
+                }
+            }
+        }
 
+        Some(LineFields { cont, ofst, typ, reln, desc: rest.trim() })
+    }
+
+    /// Split off the next field, where a whitespace can be escaped by a backslash.
+    fn next_field(s: &str) -> Option<(&str, &str)> {
+        let s = s.trim_start();
+        let mut escaped = false;
+        let end = s.char_indices()
+            .find(|&(_, c)| {
+                let sep = !escaped && c.is_whitespace();
+                escaped = !escaped && c == '\\';
+                sep
+            })
+            .map_or(s.len(), |(i, _)| i);
+        if end == 0 {
+            return None;
+        }
+        Some(s.split_at(end))
+    }
+}
+
+
+impl MagicLine {
+    /// Return true if the line starts a new entry, which is any line not led by `>`, even
+    /// if its fields are missing.
     pub(crate) fn is_entry_line(line: &str) -> bool {
-        let re = Self::line_regex();
-        if let Some(cap) = re.captures(line) {
-            let cont = cap.name("c");
-            return cont.is_some() && cont.unwrap().end() == 0
-        }
-        false
-    }
-
-    pub(crate) fn parse_entry_line(&mut self, s: &str) {
-        let re = Self::line_regex();
-        if let Some(cap) = re.captures(s) {
-            self.parse_cont_part(cap.name("c"));
-            self.parse_ofst_part(cap.name("o"));
-            self.parse_type_part(cap.name("t"));
-            self.parse_mask_part(cap.name("m"));
-            self.parse_reln_part(cap.name("r"));
-            self.parse_desc_part(cap.name("d"));
-            self.parse_code_part(cap.name("n"));
+        !line.trim_start().starts_with('>')
+    }
+
+    pub(crate) fn parse_entry_line(&mut self, s: &str) -> Result<(), String> {
+        let fields = LineFields::split(s)
+            .ok_or_else(|| format!("Incomplete magic line `{}'", s))?;
+        self.parse_cont_part(fields.cont);
+        self.parse_ofst_part(fields.ofst)?;
+        let mask = self.parse_type_part(fields.typ)?;
+        self.parse_mask_part(mask)?;
+        self.parse_reln_part(fields.reln)?;
+        let (desc, code) = Self::split_code_part(fields.desc);
+        self.parse_desc_part(desc);
+        self.parse_code_part(code);
+        self.spelling = Some(Box::new(Spelling {
+            offset: fields.ofst.to_string(),
+            typ: fields.typ.to_string(),
+            reln: Some(fields.reln.split_whitespace().collect())
+                .filter(|_| !self.cmp_type.has_str_value()),
+        }));
+        Ok(())
+    }
+
+    /// Return the fields of the line in the canonical magic(5) syntax, where the
+    /// description is omitted if there is none.
+    ///
+    /// The offset, the type and a numeric value are written as they are in the source, and
+    /// only the strings are escaped in the canonical way.
+    pub(crate) fn fields(&self) -> Vec<String> {
+        let mut fields = match self.spelling.as_deref() {
+            Some(spelling) => vec![
+                format!("{}{}", ">".repeat(self.cont_lvl), spelling.offset),
+                spelling.typ.clone(),
+                spelling.reln.clone().unwrap_or_else(|| self.fmt_reln()),
+            ],
+            None => {
+                let unsigned = if self.cmp_unsigned { "u" } else { "" };
+                vec![
+                    format!("{}{}", ">".repeat(self.cont_lvl), self.offset),
+                    format!("{}{}{}", unsigned, self.cmp_type.to_string().to_lowercase(), self.mask),
+                    self.fmt_reln(),
+                ]
+            }
         };
+
+        let mut desc = match self.desc.strip_prefix(' ') {
+            Some(desc) => desc.to_string(),
+            None if self.desc.is_empty() => String::new(),
+            None => format!("\\b{}", self.desc),
+        };
+        if self.typ_code != 0 {
+            desc.push_str(&format!("|{}", self.typ_code));
+        }
+        if !desc.is_empty() {
+            fields.push(desc);
+        }
+        fields
     }
 
-    fn parse_cont_part(&mut self, s: Option<Match>) {
-        self.cont_lvl = s.unwrap().as_str().len();
+    pub(crate) fn cont_lvl(&self) -> usize {
+        self.cont_lvl
     }
 
-    fn parse_ofst_part(&mut self, s: Option<Match>) {
-        println!("ofst: {}", s.unwrap().as_str());
+    pub(crate) fn offset(&self) -> &Offset {
+        &self.offset
     }
 
-    fn parse_type_part(&mut self, s: Option<Match>) {
-        let s = s.unwrap().as_str();
-        self.cmp_unsigned = s.starts_with("u");
-        self.cmp_type =
-            if self.cmp_unsigned {
-                s[1..].into()
-            } else {
-                s.into()
-            };
-        //    todo: parse as an SUS type if invalid
+    pub(crate) fn cmp_type(&self) -> &CmpType {
+        &self.cmp_type
+    }
+
+    /// Return true if the value is compared as an unsigned number, as with `ubyte`.
+    pub(crate) fn cmp_unsigned(&self) -> bool {
+        self.cmp_unsigned
+    }
+
+    pub(crate) fn mask(&self) -> &Mask {
+        &self.mask
+    }
+
+    /// The modifiers of a string type, which are empty for the numeric types.
+    pub(crate) fn str_flags(&self) -> StrModifier {
+        match &self.mask {
+            Mask::Str { flags, .. } => *flags,
+            Mask::Num { .. } => StrModifier::NONE,
+        }
+    }
+
+    pub(crate) fn reln_op(&self) -> &RelnOp {
+        &self.reln_op
+    }
+
+    pub(crate) fn reln_val(&self) -> &RelnVal {
+        &self.reln_val
+    }
+
+    /// The description, which starts with a space unless it is empty or led by `\b`.
+    pub(crate) fn desc(&self) -> &str {
+        &self.desc
+    }
+
+    pub(crate) fn loc(&self) -> &SourceLoc {
+        &self.loc
+    }
+
+    pub(crate) fn set_loc(&mut self, loc: SourceLoc) {
+        self.loc = loc;
+    }
+
+    /// The types given by the aux lines attached to this line.
+    pub(crate) fn aux_types(&self) -> Option<&AuxTypes> {
+        match &self.aux {
+            Some(AuxInfo::Types(types)) => Some(types),
+            _ => None,
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn aux_locs(&self) -> &[SourceLoc] {
+        &self.aux_locs
+    }
+
+    /// Forget where the line and its aux lines are and how its numbers are written, so that
+    /// the same rules from different sources compare equal.
+    #[cfg(test)]
+    pub(crate) fn clear_locs(&mut self) {
+        self.loc = SourceLoc::default();
+        self.aux_locs.clear();
+        self.spelling = None;
+    }
+
+    /// Attach the info of an `!:mime`, `!:apple` or `!:ext` line at `loc` to this line.
+    pub(crate) fn add_aux_types(&mut self, types: AuxTypes, loc: SourceLoc) {
+        match &mut self.aux {
+            Some(AuxInfo::Types(aux)) => aux.merge(types),
+            _ => self.aux = Some(AuxInfo::Types(types)),
+        }
+        self.aux_locs.push(loc);
+    }
+
+    fn parse_cont_part(&mut self, s: &str) {
+        self.cont_lvl = s.len();
+    }
+
+    fn parse_ofst_part(&mut self, s: &str) -> Result<(), String> {
+        self.offset = Offset::parse(s)?;
+        Ok(())
+    }
+
+    /// Parse the type and return the remained mask expression.
+    fn parse_type_part<'a>(&mut self, s: &'a str) -> Result<&'a str, String> {
+        let end = s.find(|c| "~+-*/&|^%".contains(c)).unwrap_or(s.len());
+        let (name, mask) = s.split_at(end);
+
+        self.cmp_unsigned = false;
+        self.cmp_type = name.into();
+        if self.cmp_type == CmpType::Invalid && name.starts_with('u') {
+            self.cmp_unsigned = true;
+            self.cmp_type = name[1..].into();
+        }
+        if self.cmp_type == CmpType::Invalid {
+            if let Some((typ, unsigned)) = CmpType::from_sus(name) {
+                self.cmp_type = typ;
+                self.cmp_unsigned = unsigned;
+            }
+        }
         //    todo: parse as def|name|use if still invalid
+        match self.cmp_type {
+            CmpType::Invalid | CmpType::NamesSize => Err(format!("Unknown type `{}'", name)),
+            _ => Ok(mask),
+        }
     }
 
-    fn parse_mask_part(&mut self, s: Option<Match>) {
-        if let Some(o) = s {
-            let s = o.as_str();
-            let (op, modifier) = s.split_at(1);
+    fn parse_mask_part(&mut self, s: &str) -> Result<(), String> {
+        self.mask =
+            if self.cmp_type.is_string() {
+                Mask::Str { flags: StrModifier::NONE, range: 0 }
+            } else {
+                Mask::default()
+            };
+        if s.is_empty() {
+            return Ok(());
+        }
 
-            self.mask =
-                if self.cmp_type.is_string() {
-                    self.parse_str_modifier(op, modifier)
-                } else {
-                    self.parse_num_modifier(op, modifier)
+        let (op, modifier) = s.split_at(1);
+        self.mask =
+            if self.cmp_type.is_string() {
+                if op != "/" {
+                    return Err(format!("Invalid string modifier `{}'", s));
                 }
-        }
+                self.parse_str_modifier(op, modifier)?
+            } else {
+                self.parse_num_modifier(op, modifier)?
+            };
+        Ok(())
     }
 
-    fn parse_str_modifier(&self, _op: &str, modifier: &str) -> Mask {
-        let re = Regex::new(r"(?P<r>\d*)(?P<m>.*)").unwrap();
-        if let Some(cap) = re.captures(modifier) {
-            let range = cap.name("r").unwrap().as_str().parse::<u64>().unwrap_or(0);
-            let modifier = cap.name("m").unwrap().as_str();
-            let flags = self.parse_chars_modifier(modifier);
-            return Mask::Str { flags, range };
+    fn parse_str_modifier(&self, _op: &str, modifier: &str) -> Result<Mask, String> {
+        // the range and the chars can be given in any order, such as `search/256/cW'
+        let mut range = 0;
+        let mut chars = String::new();
+        let mut s = modifier;
+        while let Some(c) = s.chars().next() {
+            if c.is_ascii_digit() {
+                let (val, rest) = str_utils::parse_int_prefix(s).unwrap();
+                range = val as u64;
+                s = rest;
+            } else {
+                if c != '/' {
+                    chars.push(c);
+                }
+                s = &s[c.len_utf8()..];
+            }
         }
-        panic!("Failed to parse str modifier, invalid modifier: {}!", modifier)
+        let flags = self.parse_chars_modifier(&chars)?;
+        Ok(Mask::Str { flags, range })
     }
 
-    fn parse_chars_modifier(&self, modifier: &str) -> StrModifier {
-        // remained modifier is a string like `w/c/W/'
+    fn parse_chars_modifier(&self, modifier: &str) -> Result<StrModifier, String> {
+        // remained modifier is a string like `wcW'
         let mut flags = StrModifier::NONE;
-        let chars: Vec<&str> = modifier.split('/').collect();
-        for c in chars {
-            if c.is_empty() { continue; }
+        for c in modifier.chars() {
+            if !StrModifier::all_in_chars().contains(c) {
+                return Err(format!("Invalid string modifier `{}'", c));
+            }
 
             let flag = StrModifier::from(c);
             if flag.is_pstring() {
                 // assert!(self.cmp_type == CmpType::PString ||
                 //     self.cmp_type == CmpType::Regex);
-                match flag {
-                    StrModifier::PSTRING_LENGTH_INCLUDES_ITSELF => {}
-                    _ => {
-                        // only one type pstring can be enabled at the same time
-                        flags.remove(StrModifier::PSTRING_LEN);
-                    }
-                };
+                // only one type pstring can be enabled at the same time
+                flags.remove(StrModifier::PSTRING_LEN);
             };
             flags.insert(flag);
         }
 
-        flags
+        Ok(flags)
     }
 
-    fn parse_num_modifier(&self, op: &str, modifier: &str) -> Mask {
-        // note: the subfix type decorator is ignored
-        let re = Regex::new(r"(\d+)u?.?").unwrap();
-        if let Some(cap) = re.captures(modifier) {
-            let op = MaskOp::from(op);
-            let val = cap.get(0).unwrap().as_str().parse::<u64>().unwrap();
-            return Mask::Num { op, val };
+    fn parse_num_modifier(&self, op: &str, modifier: &str) -> Result<Mask, String> {
+        if op == "~" {
+            return Err("Inverse mask `~' is not supported".to_string());
+        }
+        match str_utils::parse_int_prefix(modifier) {
+            Some((val, rest)) if Self::is_size_suffix(rest) => {
+                Ok(Mask::Num { op: MaskOp::from(op), val: val as u64 })
+            }
+            _ => Err(format!("Failed to parse num modifier, invalid num: {}!", modifier)),
         }
-        panic!("Failed to parse num modifier, invalid num: {}!", modifier)
     }
 
-    fn parse_reln_part(&mut self, s: Option<Match>) {
-        // note: `=' behind &, ^, = is ignored
-        let s = s.unwrap().as_str();
-        // todo!("support parse the string contains escapsed characters");
-        let re = Regex::new(r"(?P<x>x)|(?P<r>[><^&=!])=?(?P<n>\d*)").unwrap();
-        if let Some(cap) = re.captures(s) {
-            if cap.name("x").is_some() {
-                self.reln_op = RelnOp::Eq
+    /// The suffix type decorator of a number, such as `L` in `0xffL`, which is ignored.
+    fn is_size_suffix(s: &str) -> bool {
+        let s = s.strip_prefix(|c| c == 'u' || c == 'U').unwrap_or(s);
+        s.is_empty() || (s.len() == 1 && "lLsShHcCbBqQ".contains(s))
+    }
+
+    fn parse_reln_part(&mut self, s: &str) -> Result<(), String> {
+        self.reln_val = RelnVal::default();
+        if s == "x" {
+            self.reln_op = RelnOp::Any;
+            return Ok(());
+        }
+
+        // note: `=' behind &, ^, = and also behind <, > is ignored
+        let mut val = s;
+        self.reln_op = RelnOp::Eq;
+        if let Some(c) = s.chars().next().filter(|&c| "=!<>&^".contains(c)) {
+            self.reln_op = RelnOp::from(&s[..1]);
+            val = &s[1..];
+            if c != '!' {
+                val = val.strip_prefix('=').unwrap_or(val);
+            }
+        }
+
+        self.reln_val =
+            if self.cmp_type.has_str_value() {
+                RelnVal::Str(str_utils::unescape(val))
+            } else if self.cmp_type.is_float() {
+                RelnVal::Float(
+                    val.trim_start().parse::<f64>()
+                        .map_err(|_| format!("Invalid float value `{}'", val))?
+                )
             } else {
-                let reln_op = cap.name("r").unwrap().as_str();
-                self.reln_op = RelnOp::from(reln_op);
+                match str_utils::parse_int_prefix(val.trim_start()) {
+                    Some((num, rest)) if Self::is_size_suffix(rest) => RelnVal::Num(num as u64),
+                    _ => return Err(format!("Invalid numeric value `{}'", val)),
+                }
+            };
+        Ok(())
+    }
+
+    fn fmt_reln(&self) -> String {
+        let val = match &self.reln_val {
+            _ if self.reln_op == RelnOp::Any => return "x".to_string(),
+            RelnVal::Num(num) => match self.reln_op {
+                RelnOp::And | RelnOp::Xor => format!("{:#x}", num),
+                _ => fmt_num(*num as i64),
+            },
+            RelnVal::Float(num) => format!("{}", num),
+            RelnVal::Str(s) => {
+                let s = str_utils::escape(s);
+                // escape the leading char which would be taken as a relation
+                if s == "x" || s.starts_with(|c| "=!<>&^".contains(c)) {
+                    format!("\\{}", s)
+                } else {
+                    s
+                }
             }
+        };
+        match self.reln_op {
+            RelnOp::Eq if !val.is_empty() => val,
+            _ => format!("{}{}", self.reln_op, val),
         }
     }
 
-    fn parse_desc_part(&mut self, s: Option<Match>) {
-        let s = s.unwrap().as_str();
-        let re = Regex::new(r"(?P<b>\\b)?(?P<d>.*)").unwrap();
-        if let Some(cap) = re.captures(s) {
-            let no_whitespace = cap.name("b").is_some();
-            self.desc = cap.name("d").unwrap().as_str().to_string();
-            if !no_whitespace {
-                self.desc.insert(0, ' ')
+    fn split_code_part(s: &str) -> (&str, &str) {
+        if let Some(i) = s.rfind('|') {
+            let code = &s[i + 1..];
+            if !code.is_empty() && code.chars().all(|c| c.is_ascii_digit()) {
+                return (s[..i].trim_end(), code);
             }
         }
+        (s, "")
     }
 
-    fn parse_code_part(&mut self, s: Option<Match>) {
-        self.typ_code = s.unwrap().as_str().parse::<u32>().unwrap_or(0);
+    fn parse_desc_part(&mut self, s: &str) {
+        self.desc = match s.strip_prefix("\\b") {
+            Some(desc) => desc.to_string(),
+            None if s.is_empty() => String::new(),
+            None => format!(" {}", s),
+        };
+    }
+
+    fn parse_code_part(&mut self, s: &str) {
+        self.typ_code = s.parse::<u32>().unwrap_or(0);
     }
 }
 
@@ -189,8 +651,8 @@ impl MagicLine {
 #[cfg(test)]
 mod tests {
     use regex::Regex;
-    use super::MagicLine;
-    use crate::magic::{CmpType, StrModifier};
+    use super::{MagicLine, Offset, IndirOffset};
+    use crate::magic::{CmpType, MaskOp, RelnOp, RelnVal, StrModifier};
     use crate::parse_magic_line::Mask;
 
     #[test]
@@ -199,6 +661,9 @@ mod tests {
             (">>>>&9	ulelong	x	attributes 0x%x", false),
             ("9	ulelong	x	attributes 0x%x", true),
             ("&9	ulelong	x	attributes 0x%x", true),
+            ("0	broken", true),
+            ("0", true),
+            (">0", false),
         ];
 
         for (s, expect) in testcases {
@@ -220,20 +685,20 @@ mod tests {
     fn test_magic_parse_entry_line() {
         let mut m = MagicLine::default();
 
-        m.parse_entry_line(r">>>>&9	ulelong	x	attributes 0x%x");
+        m.parse_entry_line(r">>>>&9	ulelong	x	attributes 0x%x").unwrap();
         assert_eq!(m.cmp_type, CmpType::LELong);
-        assert_eq!(m.cmp_unsigned, true);
+        assert!(m.cmp_unsigned);
         assert_eq!(m.desc, " attributes 0x%x");
 
-        m.parse_entry_line(r"0	lestring16	x	attributes 0x%x|123");
+        m.parse_entry_line(r"0	lestring16	x	attributes 0x%x|123").unwrap();
         assert_eq!(m.cmp_type, CmpType::LEString16);
-        assert_eq!(m.cmp_unsigned, false);
+        assert!(!m.cmp_unsigned);
         assert_eq!(m.desc, " attributes 0x%x");
         assert_eq!(m.typ_code, 123);
 
-        m.parse_entry_line(r">8	lestring16/c/W	x	\b, attributes 0x%x");
+        m.parse_entry_line(r">8	lestring16/c/W	x	\b, attributes 0x%x").unwrap();
         assert_eq!(m.cmp_type, CmpType::LEString16);
-        assert_eq!(m.cmp_unsigned, false);
+        assert!(!m.cmp_unsigned);
         match m.mask {
             Mask::Num { .. } => {}
             Mask::Str { flags, range } => {
@@ -244,4 +709,69 @@ mod tests {
         }
         assert_eq!(m.desc, ", attributes 0x%x");
     }
+
+    #[test]
+    fn test_parse_offset() {
+        let testcases = vec![
+            ("4", Offset { relative: false, base: 4, indir: None }),
+            ("&-0x10", Offset { relative: true, base: -0x10, indir: None }),
+            ("(0x3c.l)", Offset {
+                relative: false,
+                base: 0x3c,
+                indir: Some(IndirOffset {
+                    relative: false, signed: false, typ: CmpType::LELong,
+                    op: MaskOp::Noop, adj_indir: false, adj: 0,
+                }),
+            }),
+            ("&(&4,S*(-2))", Offset {
+                relative: true,
+                base: 4,
+                indir: Some(IndirOffset {
+                    relative: true, signed: true, typ: CmpType::BEShort,
+                    op: MaskOp::Multiply, adj_indir: true, adj: -2,
+                }),
+            }),
+        ];
+        for (s, expect) in testcases {
+            assert_eq!(Offset::parse(s).unwrap(), expect);
+        }
+        assert!(Offset::parse("(4.l").is_err());
+        assert!(Offset::parse("4x").is_err());
+    }
+
+    #[test]
+    fn test_parse_reln_part() {
+        let mut m = MagicLine::default();
+
+        m.parse_entry_line(r"0	string	\x7fELF\ \=	ELF").unwrap();
+        assert_eq!(m.reln_op, RelnOp::Eq);
+        assert_eq!(m.reln_val, RelnVal::Str(b"\x7fELF =".to_vec()));
+
+        m.parse_entry_line(r">4	ubelong&0xff	>	0x10	big").unwrap();
+        assert_eq!(m.mask, Mask::Num { op: MaskOp::And, val: 0xff });
+        assert_eq!(m.reln_op, RelnOp::Greater);
+        assert_eq!(m.reln_val, RelnVal::Num(0x10));
+
+        m.parse_entry_line(r">>4	search/0x100/cW	!foo").unwrap();
+        assert_eq!(m.mask, Mask::Str {
+            flags: StrModifier::IGNORE_LOWERCASE | StrModifier::COMPACT_WHITESPACE,
+            range: 0x100,
+        });
+        assert_eq!(m.reln_op, RelnOp::Not);
+        assert_eq!(m.desc, "");
+
+        m.parse_entry_line(r"0	use	\^elf-le").unwrap();
+        assert_eq!(m.cmp_type, CmpType::Use);
+        assert_eq!(m.reln_val, RelnVal::Str(b"^elf-le".to_vec()));
+
+        m.parse_entry_line(r"0	uleshort	=-1	minus one").unwrap();
+        assert_eq!(m.reln_val, RelnVal::Num(u64::MAX));
+
+        m.parse_entry_line(r"0	dC	x	byte").unwrap();
+        assert_eq!(m.cmp_type, CmpType::Byte);
+        assert_eq!(m.reln_op, RelnOp::Any);
+
+        assert!(m.parse_entry_line(r"0	nosuchtype	x").is_err());
+        assert!(m.parse_entry_line(r"0	long	abc").is_err());
+    }
 }
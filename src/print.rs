@@ -0,0 +1,459 @@
+use std::fmt::Write;
+
+
+/// The value printed by the format spec of a description.
+pub(crate) enum FmtArg<'a> {
+    /// An integer of `size` bytes, in its unsigned form
+    Num { val: u64, size: usize },
+    Float(f64),
+    Str(&'a str),
+}
+
+
+/// A printf(3) format spec such as `%-8.3lx`.
+#[derive(Debug, Default, PartialEq)]
+struct Spec<'a> {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alt: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    len: &'a str,
+    conv: char,
+}
+
+impl<'a> Spec<'a> {
+    /// Parse the spec after `%`, and return it with the remained string.
+    fn parse(s: &'a str) -> Option<(Spec<'a>, &'a str)> {
+        let mut spec = Spec::default();
+        let mut s = s;
+        while let Some(c) = s.chars().next() {
+            match c {
+                '-' => spec.left = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alt = true,
+                '0' => spec.zero = true,
+                _ => break,
+            }
+            s = &s[1..];
+        }
+
+        let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        spec.width = s[..end].parse().unwrap_or(0);
+        s = &s[end..];
+        if let Some(rest) = s.strip_prefix('.') {
+            let end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+            spec.precision = Some(rest[..end].parse().unwrap_or(0));
+            s = &rest[end..];
+        }
+
+        let end = s.find(|c: char| !"hlLqjzt".contains(c)).unwrap_or(s.len());
+        spec.len = &s[..end];
+        s = &s[end..];
+
+        let conv = s.chars().next().filter(|&c| "diouxXcseEfFgG".contains(c))?;
+        spec.conv = conv;
+        Some((spec, &s[1..]))
+    }
+
+    fn format(&self, arg: &FmtArg, out: &mut String) {
+        let (sign, body) = match (arg, self.conv) {
+            (FmtArg::Str(s), _) => ("", self.truncate(s).to_string()),
+            (FmtArg::Num { val, size }, 's') => self.format_int(*val, *size, 'd'),
+            (FmtArg::Num { val, size }, 'e' | 'E' | 'f' | 'F' | 'g' | 'G') => {
+                self.format_float(self.signed(*val, *size) as f64)
+            }
+            (FmtArg::Num { val, size }, conv) => self.format_int(*val, *size, conv),
+            (FmtArg::Float(val), 'e' | 'E' | 'f' | 'F' | 'g' | 'G') => self.format_float(*val),
+            (FmtArg::Float(val), 's') => Spec { conv: 'g', ..Spec::default() }.format_float(*val),
+            (FmtArg::Float(val), conv) => self.format_int(*val as i64 as u64, 8, conv),
+        };
+
+        let len = sign.chars().count() + body.chars().count();
+        let pad = self.width.saturating_sub(len);
+        let numeric = !matches!(arg, FmtArg::Str(_)) && self.conv != 's' && self.conv != 'c';
+        if self.left {
+            out.push_str(sign);
+            out.push_str(&body);
+            out.push_str(&" ".repeat(pad));
+        } else if self.zero && numeric && (self.precision.is_none() || "eEfFgG".contains(self.conv)) {
+            out.push_str(sign);
+            out.push_str(&"0".repeat(pad));
+            out.push_str(&body);
+        } else {
+            out.push_str(&" ".repeat(pad));
+            out.push_str(sign);
+            out.push_str(&body);
+        }
+    }
+
+    fn truncate<'s>(&self, s: &'s str) -> &'s str {
+        match self.precision.and_then(|p| s.char_indices().nth(p)) {
+            Some((i, _)) => &s[..i],
+            None => s,
+        }
+    }
+
+    /// Interpret the integer as the signed type given by the length modifier, where the
+    /// integers shorter than `int` are promoted.
+    fn signed(&self, val: u64, size: usize) -> i64 {
+        match self.len {
+            "hh" => val as u8 as i8 as i64,
+            "h" => val as u16 as i16 as i64,
+            _ if size <= 4 => val as u32 as i32 as i64,
+            _ => val as i64,
+        }
+    }
+
+    fn unsigned(&self, val: u64, size: usize) -> u64 {
+        match self.len {
+            "hh" => val as u8 as u64,
+            "h" => val as u16 as u64,
+            _ if size <= 4 => val as u32 as u64,
+            _ => val,
+        }
+    }
+
+    /// Return the sign and the digits of an integer.
+    fn format_int(&self, val: u64, size: usize, conv: char) -> (&'static str, String) {
+        let (sign, digits) = match conv {
+            'd' | 'i' => {
+                let val = self.signed(val, size);
+                let sign = if val < 0 {
+                    "-"
+                } else if self.plus {
+                    "+"
+                } else if self.space {
+                    " "
+                } else {
+                    ""
+                };
+                (sign, val.unsigned_abs().to_string())
+            }
+            'c' => return ("", (val as u8 as char).to_string()),
+            _ => {
+                let val = self.unsigned(val, size);
+                let digits = match conv {
+                    'o' => format!("{:o}", val),
+                    'x' => format!("{:x}", val),
+                    'X' => format!("{:X}", val),
+                    _ => val.to_string(),
+                };
+                let prefix = match conv {
+                    'o' if self.alt && !digits.starts_with('0') => "0",
+                    'x' if self.alt && val != 0 => "0x",
+                    'X' if self.alt && val != 0 => "0X",
+                    _ => "",
+                };
+                (prefix, digits)
+            }
+        };
+
+        match self.precision {
+            Some(0) if digits == "0" => (sign, String::new()),
+            Some(p) if p > digits.len() => (sign, format!("{}{}", "0".repeat(p - digits.len()), digits)),
+            _ => (sign, digits),
+        }
+    }
+
+    /// Return the sign and the digits of a floating point number.
+    fn format_float(&self, val: f64) -> (&'static str, String) {
+        let sign = if val.is_sign_negative() && !val.is_nan() {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        };
+        let val = val.abs();
+        let upper = self.conv.is_ascii_uppercase();
+        if !val.is_finite() {
+            let s = if val.is_nan() { "nan" } else { "inf" };
+            return (sign, if upper { s.to_uppercase() } else { s.to_string() });
+        }
+
+        let precision = self.precision.unwrap_or(6);
+        let body = match self.conv {
+            'f' | 'F' => format!("{:.*}", precision, val),
+            'e' | 'E' => fmt_exp(val, precision),
+            _ => {
+                // %g chooses the style by the exponent, and trims the trailing zeros
+                let precision = precision.max(1);
+                let exp = exponent(val, precision - 1);
+                let body = if exp < -4 || exp >= precision as i32 {
+                    fmt_exp(val, precision - 1)
+                } else {
+                    format!("{:.*}", (precision as i32 - 1 - exp) as usize, val)
+                };
+                if self.alt { body } else { trim_zeros(&body) }
+            }
+        };
+        (sign, if upper { body.to_uppercase() } else { body })
+    }
+}
+
+
+/// Format a non-negative number in the `%e` style, such as `1.500000e+00`.
+fn fmt_exp(val: f64, precision: usize) -> String {
+    let s = format!("{:.*e}", precision, val);
+    let (mantissa, exp) = s.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    format!("{}e{}{:02}", mantissa, if exp < 0 { '-' } else { '+' }, exp.abs())
+}
+
+/// Return the decimal exponent of a number rounded to `precision` digits after the point.
+fn exponent(val: f64, precision: usize) -> i32 {
+    if val == 0.0 {
+        return 0;
+    }
+    let s = format!("{:.*e}", precision, val);
+    s.split_once('e').unwrap().1.parse().unwrap()
+}
+
+/// Trim the trailing zeros of the fraction, and the point if nothing remains after it.
+fn trim_zeros(s: &str) -> String {
+    let (num, exp) = match s.find('e') {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let num = if num.contains('.') {
+        num.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        num
+    };
+    format!("{}{}", num, exp)
+}
+
+
+/// Format a description by printf(3) with a single argument, as libmagic does.
+///
+/// The invalid format specs are kept as they are.
+pub(crate) fn sprintf(fmt: &str, arg: &FmtArg) -> String {
+    let mut out = String::new();
+    let mut rest = fmt;
+    while let Some(i) = rest.find('%') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        if let Some(r) = rest.strip_prefix('%') {
+            out.push('%');
+            rest = r;
+            continue;
+        }
+        match Spec::parse(rest) {
+            Some((spec, r)) => {
+                spec.format(arg, &mut out);
+                rest = r;
+            }
+            None => out.push('%'),
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+
+/// Expand the `${x?yes:no}` variables of a description or a MIME type, where `x` tells if the
+/// file is executable.
+///
+/// The string is kept as it is if any variable is malformed.
+pub(crate) fn expand_vars(s: &str, executable: bool) -> String {
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find("${") {
+        out.push_str(&rest[..i]);
+        let var = &rest[i + 2..];
+        let (yes, no, end) = match var.strip_prefix("x?").and_then(|v| {
+            let colon = v.find(':')?;
+            let close = colon + v[colon..].find('}')?;
+            Some((&v[..colon], &v[colon + 1..close], close + 1))
+        }) {
+            Some(parts) => parts,
+            None => return s.to_string(),
+        };
+        out.push_str(if executable { yes } else { no });
+        rest = &var[2 + end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+
+/// Escape the unprintable bytes of a value in octal, such as `\033`, unless `raw` is set.
+///
+/// The value ends at the first NUL, as a C string does.
+pub(crate) fn printable(s: &[u8], raw: bool) -> String {
+    let s = &s[..s.iter().position(|&b| b == 0).unwrap_or(s.len())];
+    if raw {
+        return String::from_utf8_lossy(s).into_owned();
+    }
+
+    let mut out = String::new();
+    for &b in s {
+        if (0x20..0x7f).contains(&b) {
+            out.push(b as char);
+        } else {
+            write!(out, "\\{:03o}", b).unwrap();
+        }
+    }
+    out
+}
+
+/// Escape the control chars of the result in octal, such as the newline in the `\012- `
+/// separator of `file -k`.
+pub(crate) fn octalify(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        if c.is_control() {
+            let mut buf = [0; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                write!(out, "\\{:03o}", b).unwrap();
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+
+const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+/// The largest time which asctime(3) can print, that is in the year 9999
+const MAX_CTIME: i64 = 0x3afff487cf;
+/// The seconds from 1601-01-01, the epoch of windows, to 1970-01-01
+const WINDOWS_EPOCH_OFFSET: i64 = 11644473600;
+
+/// The kind of a time value of a magic type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum TimeKind {
+    /// Seconds since the unix epoch, printed in UTC
+    Utc,
+    /// Seconds since the unix epoch, printed in the local time zone
+    Local,
+    /// 100-nanoseconds since 1601-01-01, printed in UTC
+    Windows,
+}
+
+/// Format a time like asctime(3), such as `Thu Jan  1 00:00:00 1970`.
+pub(crate) fn fmt_time(val: u64, kind: TimeKind) -> String {
+    let secs = match kind {
+        TimeKind::Windows => (val / 10_000_000) as i64 - WINDOWS_EPOCH_OFFSET,
+        _ => val as i64,
+    };
+    if secs > MAX_CTIME {
+        return "*Invalid datetime*".to_string();
+    }
+
+    let secs = secs as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let res = unsafe {
+        if kind == TimeKind::Local {
+            libc::localtime_r(&secs, &mut tm)
+        } else {
+            libc::gmtime_r(&secs, &mut tm)
+        }
+    };
+    if res.is_null() {
+        return "*Invalid datetime*".to_string();
+    }
+
+    format!(
+        "{} {}{:3} {:02}:{:02}:{:02} {}",
+        DAYS.get(tm.tm_wday as usize).unwrap_or(&"???"),
+        MONTHS.get(tm.tm_mon as usize).unwrap_or(&"???"),
+        tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec,
+        tm.tm_year as i64 + 1900,
+    )
+}
+
+/// Format a MS-DOS date, such as `Sun, Jan 01 1980`.
+///
+/// As libmagic, the weekday is left unset, which is always printed as `Sun`.
+pub(crate) fn fmt_msdos_date(val: u16) -> String {
+    let month = ((val >> 5) & 0xf) as usize;
+    format!(
+        "{}, {} {:02} {}",
+        DAYS[0],
+        month.checked_sub(1).and_then(|m| MONTHS.get(m)).unwrap_or(&"?"),
+        val & 0x1f,
+        (val >> 9) as u32 + 1980,
+    )
+}
+
+/// Format a MS-DOS time, such as `12:30:58`.
+pub(crate) fn fmt_msdos_time(val: u16) -> String {
+    format!("{:02}:{:02}:{:02}", val >> 11, (val >> 5) & 0x3f, (val & 0x1f) * 2)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{expand_vars, fmt_msdos_date, fmt_msdos_time, fmt_time, octalify, printable, sprintf};
+    use super::{FmtArg, TimeKind};
+
+    #[test]
+    fn test_sprintf() {
+        let num = |val, size| FmtArg::Num { val, size };
+        let testcases = vec![
+            ("version %d.%d", num(3, 1), "version 3.3"),
+            ("%d", num(0xff, 1), "255"),
+            ("%d", num(0xffffffff, 4), "-1"),
+            ("%u", num(0xffffffff, 4), "4294967295"),
+            ("%lld", num(u64::MAX, 8), "-1"),
+            ("%#x", num(0x1f, 2), "0x1f"),
+            ("%#X", num(0, 2), "0"),
+            ("%04x", num(0xab, 2), "00ab"),
+            ("[%-5d]", num(42, 4), "[42   ]"),
+            ("[%5.3d]", num(7, 4), "[  007]"),
+            ("%+d", num(7, 4), "+7"),
+            ("%o", num(8, 4), "10"),
+            ("%c", num(0x41, 1), "A"),
+            ("%s bytes", num(1024, 4), "1024 bytes"),
+            ("%hd", num(0xffff, 2), "-1"),
+            ("%s", FmtArg::Str("abc"), "abc"),
+            ("[%.2s]", FmtArg::Str("abc"), "[ab]"),
+            ("[%5s]", FmtArg::Str("abc"), "[  abc]"),
+            ("%f", FmtArg::Float(1.5), "1.500000"),
+            ("%.1f", FmtArg::Float(-0.25), "-0.2"),
+            ("%e", FmtArg::Float(1234.5), "1.234500e+03"),
+            ("%g", FmtArg::Float(1.5), "1.5"),
+            ("%g", FmtArg::Float(0.0001), "0.0001"),
+            ("%g", FmtArg::Float(1e-5), "1e-05"),
+            ("%g", FmtArg::Float(1234567.0), "1.23457e+06"),
+            ("%G", FmtArg::Float(f64::INFINITY), "INF"),
+            ("%s", FmtArg::Float(0.5), "0.5"),
+            ("100%%", num(0, 1), "100%"),
+            ("%y", num(0, 1), "%y"),
+        ];
+        for (fmt, arg, expect) in testcases {
+            assert_eq!(sprintf(fmt, &arg), expect, "{}", fmt);
+        }
+    }
+
+    #[test]
+    fn test_printable() {
+        assert_eq!(printable(b"ab\x1bc\xff\0d", false), "ab\\033c\\377");
+        assert_eq!(printable(b"ab\tc", true), "ab\tc");
+        assert_eq!(expand_vars("${x?pie executable:shared object},", true), "pie executable,");
+        assert_eq!(expand_vars("a ${x?b:c} ${x?d:e}", false), "a c e");
+        assert_eq!(expand_vars("${y?b:c}", false), "${y?b:c}");
+        assert_eq!(octalify("ELF\n- data\u{e9}"), "ELF\\012- data\u{e9}");
+    }
+
+    #[test]
+    fn test_fmt_time() {
+        assert_eq!(fmt_time(0, TimeKind::Utc), "Thu Jan  1 00:00:00 1970");
+        assert_eq!(fmt_time(1700000000, TimeKind::Utc), "Tue Nov 14 22:13:20 2023");
+        assert_eq!(fmt_time(116444736000000000, TimeKind::Windows), "Thu Jan  1 00:00:00 1970");
+        assert_eq!(fmt_time(u64::MAX >> 1, TimeKind::Utc), "*Invalid datetime*");
+        assert_eq!(fmt_msdos_date(0x5821), "Sun, Jan 01 2024");
+        assert_eq!(fmt_msdos_time(0x63dd), "12:30:58");
+    }
+}
@@ -0,0 +1,588 @@
+use std::convert::{TryFrom, TryInto};
+use std::fmt::Display;
+
+use crate::magic_param::{MagicParam, Param};
+use crate::magic_set::Source;
+
+
+const ET_REL: u16 = 1;
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+const ET_CORE: u16 = 4;
+
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+const PT_NOTE: u32 = 4;
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOTE: u32 = 7;
+
+const DT_NEEDED: u64 = 1;
+const DT_FLAGS_1: u64 = 0x6fff_fffb;
+const DF_1_PIE: u64 = 0x0800_0000;
+
+const NT_GNU_VERSION: u32 = 1;
+const NT_GNU_BUILD_ID: u32 = 3;
+const NT_GO_BUILD_ID: u32 = 4;
+
+/// The most of a segment which is read, as the `BUFSIZ` buffer of libmagic
+const SEGMENT_MAX: u64 = 8192;
+
+/// The most of a section name which is read
+const NAME_MAX: u64 = 50;
+
+
+/// What the headers of an ELF file tell beyond the magic rules, as `file_tryelf` of
+/// libmagic.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct ElfInfo {
+    /// Appended to the description, such as `, dynamically linked`
+    pub(crate) desc: String,
+    /// If the file is a PIE by its dynamic flags, for the `${x?yes:no}` variables
+    pub(crate) pie: Option<bool>,
+}
+
+
+/// Read the headers of an ELF file, or return `None` if it is not one.
+///
+/// The program headers, the section headers and the notes are limited by
+/// `Param::ElfPhnum`, `Param::ElfShnum` and `Param::ElfNotes`.
+pub(crate) fn tryelf(source: Source, param: &MagicParam) -> Option<ElfInfo> {
+    let size = source.size()?;
+    let mut elf = ReadElf {
+        source,
+        size,
+        class64: false,
+        big_endian: false,
+        notes_left: param.get(Param::ElfNotes) as u32,
+        too_many_notes: false,
+        did_os_note: false,
+        did_build_id: false,
+        pie: None,
+        out: String::new(),
+    };
+    let ident = elf.read(0, 64);
+    if !ident.starts_with(b"\x7fELF") || ident.len() < 6 {
+        return None;
+    }
+    elf.class64 = match ident[4] {
+        1 => false,
+        2 => true,
+        class => return Some(ElfInfo { desc: format!(", unknown class {}", class), pie: None }),
+    };
+    elf.big_endian = ident[5] == 2;
+    if ident.len() < elf.sized(52, 64) {
+        return None;
+    }
+
+    // a file which cannot be read as far as its headers say is not described at all
+    let desc = match elf.headers(&ident, param) {
+        Some(()) => elf.out,
+        None => String::new(),
+    };
+    Some(ElfInfo { desc, pie: elf.pie })
+}
+
+
+/// The bytes of an ELF file in its class and byte order.
+#[derive(Clone, Copy)]
+struct Data<'a> {
+    buf: &'a [u8],
+    class64: bool,
+    big_endian: bool,
+}
+
+impl Data<'_> {
+    fn bytes<const N: usize>(&self, off: usize) -> Option<[u8; N]> {
+        self.buf.get(off..off.checked_add(N)?)?.try_into().ok()
+    }
+
+    fn u16(&self, off: usize) -> Option<u16> {
+        let bytes = self.bytes(off)?;
+        Some(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32(&self, off: usize) -> Option<u32> {
+        let bytes = self.bytes(off)?;
+        Some(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    fn u64(&self, off: usize) -> Option<u64> {
+        let bytes = self.bytes(off)?;
+        Some(if self.big_endian { u64::from_be_bytes(bytes) } else { u64::from_le_bytes(bytes) })
+    }
+
+    /// An address, an offset or a size, at the offset of the 32-bit or the 64-bit class.
+    fn word(&self, off32: usize, off64: usize) -> Option<u64> {
+        if self.class64 {
+            self.u64(off64)
+        } else {
+            self.u32(off32).map(u64::from)
+        }
+    }
+}
+
+
+/// The state of reading the headers, as the `flags` and `notecount` of libmagic.
+struct ReadElf<'a> {
+    source: Source<'a>,
+    size: u64,
+    class64: bool,
+    big_endian: bool,
+    notes_left: u32,
+    too_many_notes: bool,
+    did_os_note: bool,
+    did_build_id: bool,
+    pie: Option<bool>,
+    out: String,
+}
+
+impl ReadElf<'_> {
+    fn read(&self, off: u64, len: u64) -> Vec<u8> {
+        self.source.read(off, len)
+    }
+
+    fn data<'b>(&self, buf: &'b [u8]) -> Data<'b> {
+        Data { buf, class64: self.class64, big_endian: self.big_endian }
+    }
+
+    /// The size of a header of the 32-bit or the 64-bit class.
+    fn sized(&self, size32: usize, size64: usize) -> usize {
+        if self.class64 { size64 } else { size32 }
+    }
+
+    fn too_many(&mut self, what: &str, num: impl Display) {
+        self.out.push_str(&format!(", too many {} ({})", what, num));
+    }
+
+    /// Read the program headers and the section headers by the ELF header, or return
+    /// `None` if they cannot be read.
+    fn headers(&mut self, ident: &[u8], param: &MagicParam) -> Option<()> {
+        let ehdr = self.data(ident);
+        let typ = ehdr.u16(16)?;
+        let phoff = ehdr.word(28, 32)?;
+        let shoff = ehdr.word(32, 40)?;
+        let phentsize = ehdr.u16(self.sized(42, 54))?;
+        let phnum = ehdr.u16(self.sized(44, 56))?;
+        let shentsize = ehdr.u16(self.sized(46, 58))?;
+        let shnum = ehdr.u16(self.sized(48, 60))?;
+        let shstrndx = ehdr.u16(self.sized(50, 62))?;
+
+        let max_phnum = param.get(Param::ElfPhnum);
+        let max_shnum = param.get(Param::ElfShnum);
+        match typ {
+            ET_CORE if i32::from(phnum) > max_phnum => {
+                self.too_many("program headers", phnum);
+                return Some(());
+            }
+            ET_EXEC | ET_DYN | ET_REL => {
+                if i32::from(phnum) > max_phnum && typ != ET_REL {
+                    self.too_many("program headers", phnum);
+                    return Some(());
+                }
+                if i32::from(shnum) > max_shnum {
+                    self.too_many("section headers", shnum);
+                    return Some(());
+                }
+                if typ != ET_REL {
+                    self.program_headers(phoff, phnum, phentsize, shnum)?;
+                }
+                self.section_headers(shoff, shnum, shentsize, shstrndx)?;
+            }
+            _ => {}
+        }
+        if self.too_many_notes {
+            self.too_many("notes", param.get(Param::ElfNotes));
+        }
+        Some(())
+    }
+
+    /// Describe the linking and the interpreter of an executable or a shared object, as
+    /// `dophn_exec` of libmagic, where the notes are only read from the segments if there
+    /// are no sections.
+    fn program_headers(&mut self, off: u64, num: u16, entsize: u16, shnum: u16) -> Option<()> {
+        if num == 0 {
+            self.out.push_str(", no program header");
+            return Some(());
+        }
+        if usize::from(entsize) != self.sized(32, 56) {
+            self.out.push_str(", corrupted program header size");
+            return Some(());
+        }
+        let mut dynamic = false;
+        let mut pie = false;
+        // the number of the shared objects and interpreters needed
+        let mut need = 0;
+        let mut interp = None;
+        for i in 0..u64::from(num) {
+            let buf = self.read(off.saturating_add(i * u64::from(entsize)), u64::from(entsize));
+            let phdr = self.data(&buf);
+            let typ = phdr.u32(0)?;
+            let offset = phdr.word(4, 8)?;
+            let filesz = phdr.word(16, 32)?.min(SEGMENT_MAX);
+            let align = phdr.word(28, 48)?;
+            match typ {
+                PT_DYNAMIC => {
+                    dynamic = true;
+                    let buf = self.read(offset, filesz);
+                    for entry in buf.chunks_exact(self.sized(8, 16)) {
+                        let entry = self.data(entry);
+                        match entry.word(0, 0)? {
+                            DT_NEEDED => need += 1,
+                            DT_FLAGS_1 => {
+                                pie = entry.word(4, 8)? & DF_1_PIE != 0;
+                                self.pie = Some(pie);
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                PT_INTERP => {
+                    need += 1;
+                    let buf = self.read(offset, filesz);
+                    let path = buf.split(|&c| c == 0).next().unwrap_or_default();
+                    interp = Some(if path.is_empty() {
+                        "*empty*".to_string()
+                    } else {
+                        String::from_utf8_lossy(path).into_owned()
+                    });
+                }
+                PT_NOTE if shnum == 0 => {
+                    let align = if (4..0x8000_0000).contains(&align) { align } else { 4 };
+                    let buf = self.read(offset, filesz);
+                    self.notes(&buf, align);
+                }
+                _ => {}
+            }
+        }
+
+        let linking = if !dynamic {
+            "statically"
+        } else if pie && need == 0 {
+            "static-pie"
+        } else {
+            "dynamically"
+        };
+        self.out.push_str(&format!(", {} linked", linking));
+        if let Some(interp) = interp {
+            self.out.push_str(&format!(", interpreter {}", interp));
+        }
+        Some(())
+    }
+
+    /// Describe the debugging info, the symbols and the notes by the sections, as `doshn`
+    /// of libmagic.
+    fn section_headers(&mut self, off: u64, num: u16, entsize: u16, strndx: u16) -> Option<()> {
+        if num == 0 {
+            self.out.push_str(", no section header");
+            return Some(());
+        }
+        if usize::from(entsize) != self.sized(40, 64) {
+            self.out.push_str(", corrupted section header size");
+            return Some(());
+        }
+        let entsize = u64::from(entsize);
+        let strtab = off.saturating_add(entsize * u64::from(strndx));
+        let buf = self.read(strtab, entsize);
+        let names = match self.data(&buf).word(16, 24) {
+            Some(names) => names,
+            None => {
+                self.out.push_str(&format!(", missing section headers at {}", strtab));
+                return Some(());
+            }
+        };
+        if names > self.size {
+            self.out.push_str(&format!(", too large section header offset {}", names));
+            return Some(());
+        }
+
+        let mut stripped = true;
+        let mut debug_info = false;
+        for i in 0..u64::from(num) {
+            let buf = self.read(off.saturating_add(i * entsize), entsize);
+            let shdr = self.data(&buf);
+            let name = self.read(names.saturating_add(u64::from(shdr.u32(0)?)), NAME_MAX);
+            if name.split(|&c| c == 0).next() == Some(b".debug_info") {
+                debug_info = true;
+                stripped = false;
+            }
+            let offset = shdr.word(16, 24)?;
+            let size = shdr.word(20, 32)?;
+            match shdr.u32(4)? {
+                SHT_SYMTAB => stripped = false,
+                SHT_NOTE => {
+                    if offset.saturating_add(size) > self.size {
+                        self.out.push_str(&format!(
+                            ", note offset/size {:#x}+{:#x} exceeds file size {:#x}", offset, size, self.size
+                        ));
+                        return Some(());
+                    }
+                    let buf = self.read(offset, size);
+                    self.notes(&buf, 4);
+                }
+                _ => {}
+            }
+        }
+
+        if debug_info {
+            self.out.push_str(", with debug_info");
+        }
+        self.out.push_str(if stripped { ", stripped" } else { ", not stripped" });
+        Some(())
+    }
+
+    /// Describe the notes of a segment or a section.
+    fn notes(&mut self, buf: &[u8], align: u64) {
+        let mut off = 0;
+        while off < buf.len() {
+            match self.note(buf, off, align) {
+                Some(next) => off = next,
+                None => break,
+            }
+        }
+    }
+
+    /// Describe a note, and return the offset of the next one, as `donote` of libmagic.
+    fn note(&mut self, buf: &[u8], off: usize, align: u64) -> Option<usize> {
+        if self.notes_left == 0 {
+            self.too_many_notes = true;
+            return None;
+        }
+        self.notes_left -= 1;
+        let nhdr = self.data(buf);
+        let namesz = nhdr.u32(off)?;
+        let descsz = nhdr.u32(off + 4)?;
+        let typ = nhdr.u32(off + 8)?;
+        if namesz == 0 && descsz == 0 {
+            return None;
+        }
+        if namesz & 0x8000_0000 != 0 {
+            self.out.push_str(&format!(", bad note name size {:#x}", namesz));
+            return None;
+        }
+        if descsz & 0x8000_0000 != 0 {
+            self.out.push_str(&format!(", bad note description size {:#x}", descsz));
+            return None;
+        }
+
+        let align_up = |n: u64| n.div_ceil(align) * align;
+        let name_off = off as u64 + 12;
+        let desc_off = align_up(name_off + u64::from(namesz));
+        let next = align_up(desc_off + u64::from(descsz));
+        let name = buf.get(name_off as usize..(name_off + u64::from(namesz)) as usize)?;
+        let desc = buf.get(desc_off as usize..(desc_off + u64::from(descsz)) as usize)?;
+        if !self.did_os_note && self.os_note(name, typ, desc) {
+            return usize::try_from(next).ok();
+        }
+        if !self.did_build_id {
+            self.build_id_note(name, typ, desc);
+        }
+        usize::try_from(next).ok()
+    }
+
+    /// Describe the OS which the file is for, as `do_os_note` of libmagic.
+    fn os_note(&mut self, name: &[u8], typ: u32, desc: &[u8]) -> bool {
+        if name == b"SuSE\0" && typ == NT_GNU_VERSION && desc.len() == 2 {
+            self.out.push_str(&format!(", for SuSE {}.{}", desc[0], desc[1]));
+            return true;
+        }
+        if name == b"GNU\0" && typ == NT_GNU_VERSION && desc.len() == 16 {
+            let data = self.data(desc);
+            let word = |i: usize| data.u32(i * 4).unwrap_or_default();
+            let os = match word(0) {
+                0 => "Linux",
+                1 => "Hurd",
+                2 => "Solaris",
+                3 => "kFreeBSD",
+                4 => "kNetBSD",
+                _ => "<unknown>",
+            };
+            self.out.push_str(&format!(", for GNU/{} {}.{}.{}", os, word(1), word(2), word(3)));
+            self.did_os_note = true;
+            return true;
+        }
+        false
+    }
+
+    /// Describe the build ID of the GNU or the Go linker, as `do_bid_note` of libmagic.
+    fn build_id_note(&mut self, name: &[u8], typ: u32, desc: &[u8]) -> bool {
+        if name == b"GNU\0" && typ == NT_GNU_BUILD_ID && (4..=20).contains(&desc.len()) {
+            let kind = match desc.len() {
+                8 => "xxHash",
+                16 => "md5/uuid",
+                20 => "sha1",
+                _ => "unknown",
+            };
+            let hex: String = desc.iter().map(|c| format!("{:02x}", c)).collect();
+            self.out.push_str(&format!(", BuildID[{}]={}", kind, hex));
+            self.did_build_id = true;
+            return true;
+        }
+        if name == b"Go\0\0" && typ == NT_GO_BUILD_ID && desc.len() < 128 {
+            let id = desc.split(|&c| c == 0).next().unwrap_or_default();
+            self.out.push_str(&format!(", Go BuildID={}", String::from_utf8_lossy(id)));
+            self.did_build_id = true;
+            return true;
+        }
+        false
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::process;
+
+    use super::{tryelf, ElfInfo};
+    use crate::magic_param::{MagicParam, Param};
+    use crate::magic_set::{Flags, MagicSet, Source};
+
+    const INTERP: &[u8] = b"/lib64/ld-linux-x86-64.so.2\0";
+
+    /// Build a little-endian 64-bit PIE with an interpreter, a build ID and an ABI tag,
+    /// with the sections unless `sections` is false.
+    fn build_elf(sections: bool, symtab: bool) -> Vec<u8> {
+        fn half(buf: &mut Vec<u8>, n: u16) {
+            buf.extend_from_slice(&n.to_le_bytes());
+        }
+        fn word(buf: &mut Vec<u8>, n: u32) {
+            buf.extend_from_slice(&n.to_le_bytes());
+        }
+        fn xword(buf: &mut Vec<u8>, n: u64) {
+            buf.extend_from_slice(&n.to_le_bytes());
+        }
+        fn phdr(buf: &mut Vec<u8>, typ: u32, offset: u64, size: u64) {
+            word(buf, typ);
+            word(buf, 4);
+            xword(buf, offset);
+            xword(buf, offset);
+            xword(buf, offset);
+            xword(buf, size);
+            xword(buf, size);
+            xword(buf, 4);
+        }
+        fn shdr(buf: &mut Vec<u8>, name: u32, typ: u32, offset: u64, size: u64) {
+            word(buf, name);
+            word(buf, typ);
+            xword(buf, 0);
+            xword(buf, 0);
+            xword(buf, offset);
+            xword(buf, size);
+            word(buf, 0);
+            word(buf, 0);
+            xword(buf, 1);
+            xword(buf, 0);
+        }
+
+        let mut notes = vec![];
+        for (typ, desc) in [(3, vec![0xab; 20]), (1, vec![0, 0, 0, 0, 3, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0])] {
+            word(&mut notes, 4);
+            word(&mut notes, desc.len() as u32);
+            word(&mut notes, typ);
+            notes.extend_from_slice(b"GNU\0");
+            notes.extend_from_slice(&desc);
+        }
+        let mut dynamic = vec![];
+        for (tag, val) in [(1, 1), (0x6fff_fffb, 0x0800_0000), (0, 0)] {
+            xword(&mut dynamic, tag);
+            xword(&mut dynamic, val);
+        }
+        let strtab = b"\0.shstrtab\0.note\0.symtab\0";
+
+        let interp_off = 64 + 3 * 56;
+        let dynamic_off = interp_off + INTERP.len() as u64;
+        let notes_off = dynamic_off + dynamic.len() as u64;
+        let strtab_off = notes_off + notes.len() as u64;
+        let shoff = strtab_off + strtab.len() as u64;
+
+        let mut buf = b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0".to_vec();
+        half(&mut buf, 3);
+        half(&mut buf, 62);
+        word(&mut buf, 1);
+        xword(&mut buf, 0x1000);
+        xword(&mut buf, 64);
+        xword(&mut buf, if sections { shoff } else { 0 });
+        word(&mut buf, 0);
+        half(&mut buf, 64);
+        half(&mut buf, 56);
+        half(&mut buf, 3);
+        half(&mut buf, 64);
+        half(&mut buf, if sections { 4 } else { 0 });
+        half(&mut buf, if sections { 1 } else { 0 });
+        phdr(&mut buf, 3, interp_off, INTERP.len() as u64);
+        phdr(&mut buf, 2, dynamic_off, dynamic.len() as u64);
+        phdr(&mut buf, 4, notes_off, notes.len() as u64);
+        buf.extend_from_slice(INTERP);
+        buf.extend_from_slice(&dynamic);
+        buf.extend_from_slice(&notes);
+        buf.extend_from_slice(strtab);
+        if sections {
+            shdr(&mut buf, 0, 0, 0, 0);
+            shdr(&mut buf, 1, 3, strtab_off, strtab.len() as u64);
+            shdr(&mut buf, 11, 7, notes_off, notes.len() as u64);
+            shdr(&mut buf, 17, if symtab { 2 } else { 0 }, 0, 0);
+        }
+        buf
+    }
+
+    #[test]
+    fn test_tryelf() {
+        let param = MagicParam::default();
+        let build_id = format!("BuildID[sha1]={}", "ab".repeat(20));
+        let interp = "interpreter /lib64/ld-linux-x86-64.so.2";
+
+        let elf = build_elf(true, true);
+        assert_eq!(tryelf(Source::Buffer(&elf), &param).unwrap(), ElfInfo {
+            desc: format!(", dynamically linked, {}, {}, for GNU/Linux 3.2.0, not stripped", interp, build_id),
+            pie: Some(true),
+        });
+        assert_eq!(
+            tryelf(Source::Buffer(&build_elf(true, false)), &param).unwrap().desc,
+            format!(", dynamically linked, {}, {}, for GNU/Linux 3.2.0, stripped", interp, build_id)
+        );
+        // the notes are read from the segments without the sections
+        assert_eq!(
+            tryelf(Source::Buffer(&build_elf(false, false)), &param).unwrap().desc,
+            format!(", {}, for GNU/Linux 3.2.0, dynamically linked, {}, no section header", build_id, interp)
+        );
+
+        let mut limited = MagicParam::default();
+        limited.set(Param::ElfPhnum, 2).unwrap();
+        assert_eq!(tryelf(Source::Buffer(&elf), &limited).unwrap().desc, ", too many program headers (3)");
+        let mut limited = MagicParam::default();
+        limited.set(Param::ElfShnum, 3).unwrap();
+        assert_eq!(tryelf(Source::Buffer(&elf), &limited).unwrap().desc, ", too many section headers (4)");
+        let mut limited = MagicParam::default();
+        limited.set(Param::ElfNotes, 1).unwrap();
+        assert_eq!(
+            tryelf(Source::Buffer(&elf), &limited).unwrap().desc,
+            format!(", dynamically linked, {}, {}, not stripped, too many notes (1)", interp, build_id)
+        );
+
+        // the section headers are past the end
+        let desc = tryelf(Source::Buffer(&elf[..elf.len() - 64]), &param).unwrap().desc;
+        assert_eq!(desc, "");
+        assert_eq!(tryelf(Source::Buffer(b"\x7fELF\x03\x01"), &param).unwrap().desc, ", unknown class 3");
+        assert_eq!(tryelf(Source::Buffer(b"MZ\x90\0"), &param), None);
+
+        // a file is read past the bytes of the buffer
+        let path = env::temp_dir().join(format!("test_tryelf.{}", process::id()));
+        fs::write(&path, &elf).unwrap();
+        let file = File::open(&path).unwrap();
+        assert_eq!(tryelf(Source::File(&file), &param), tryelf(Source::Buffer(&elf), &param));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_elf_magic() {
+        let mut ms = MagicSet::new(Flags::NONE).unwrap();
+        ms.load_str("0\tstring\t\\177ELF\tELF\n>16\tleshort\t3\t${x?pie executable:shared object}\n");
+
+        let elf = build_elf(true, true);
+        assert!(ms.buffer(&elf).unwrap().starts_with("ELF pie executable, dynamically linked, interpreter "));
+        ms.set_flags(Flags::NO_CHECK_ELF).unwrap();
+        assert_eq!(ms.buffer(&elf).unwrap(), "ELF shared object");
+    }
+}
@@ -0,0 +1,1602 @@
+use std::convert::{TryFrom, TryInto};
+use std::mem;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::apprentice::MagicList;
+use crate::magic::{CmpType, MagicFlags, MaskOp, RelnOp, RelnVal, StrModifier};
+use crate::magic_param::{MagicParam, Param};
+use crate::magic_set::Flags;
+use crate::parse_magic_line::{Mask, MagicLine};
+use crate::print::{self, FmtArg, TimeKind};
+
+
+/// The maximum bytes of a string value read from the buffer
+const MAX_STRING: usize = 128;
+
+
+/// The state of a continuation level.
+#[derive(Debug, Default, Clone)]
+struct Level {
+    /// The end of the last match at this level, which is the base of the relative offsets
+    /// of the next level
+    off: usize,
+    /// If any line matched at this level, so that a `default` line is skipped
+    got_match: bool,
+}
+
+
+/// The value read by a line, as the `union VALUETYPE` of libmagic.
+#[derive(Debug)]
+enum Value<'b> {
+    /// The types without a value, such as `default` and `use`
+    None,
+    /// An integer, masked and truncated to the size of its type
+    Num(u64),
+    Float(f64),
+    /// The bytes of a string type, converted for `pstring` and `string16`
+    Str(Vec<u8>),
+    /// The region of a `search` or a `regex`, where `start` and `len` are updated to the
+    /// match by the check
+    Search { region: &'b [u8], start: usize, len: usize },
+    Guid([u8; 16]),
+}
+
+/// The result of reading the value of a line.
+struct Got<'b> {
+    /// Where the value is read
+    offset: usize,
+    val: Value<'b>,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Endian {
+    Native,
+    Big,
+    Little,
+    /// The PDP-11 order, such as `melong`
+    Middle,
+}
+
+fn endian(typ: CmpType) -> Endian {
+    match typ {
+        CmpType::BEShort |
+        CmpType::BELong |
+        CmpType::BEDate |
+        CmpType::BELDate |
+        CmpType::BEQuad |
+        CmpType::BEQDate |
+        CmpType::BEQLDate |
+        CmpType::BEQWDate |
+        CmpType::BEFloat |
+        CmpType::BEDouble |
+        CmpType::BEID3 |
+        CmpType::BEMSDOSDate |
+        CmpType::BEMSDOSTime => Endian::Big,
+        CmpType::LEShort |
+        CmpType::LELong |
+        CmpType::LEDate |
+        CmpType::LELDate |
+        CmpType::LEQuad |
+        CmpType::LEQDate |
+        CmpType::LEQLDate |
+        CmpType::LEQWDate |
+        CmpType::LEFloat |
+        CmpType::LEDouble |
+        CmpType::LEID3 |
+        CmpType::LEMSDOSDate |
+        CmpType::LEMSDOSTime => Endian::Little,
+        CmpType::MELong | CmpType::MEDate | CmpType::MELDate => Endian::Middle,
+        _ => Endian::Native,
+    }
+}
+
+/// Read an unsigned integer of `size` bytes, or `None` if it is out of the buffer.
+fn read_uint(buf: &[u8], offset: i64, size: usize, endian: Endian) -> Option<u64> {
+    let start = usize::try_from(offset).ok()?;
+    let bytes = buf.get(start..start.checked_add(size)?)?;
+    let big = match endian {
+        Endian::Native => cfg!(target_endian = "big"),
+        Endian::Big => true,
+        Endian::Little => false,
+        Endian::Middle => {
+            return Some(u64::from(u32::from_be_bytes([bytes[1], bytes[0], bytes[3], bytes[2]])));
+        }
+    };
+    let fold = |val: u64, &b: &u8| val << 8 | b as u64;
+    Some(if big {
+        bytes.iter().fold(0, fold)
+    } else {
+        bytes.iter().rev().fold(0, fold)
+    })
+}
+
+/// Sign extend an integer of `size` bytes.
+fn sign_extend(val: u64, size: usize) -> u64 {
+    match size {
+        1 => val as i8 as u64,
+        2 => val as i16 as u64,
+        4 => val as i32 as u64,
+        _ => val,
+    }
+}
+
+/// Truncate an integer to `size` bytes.
+fn truncate(val: u64, size: usize) -> u64 {
+    if size >= 8 {
+        val
+    } else {
+        val & ((1 << (size * 8)) - 1)
+    }
+}
+
+/// Decode the 7-bit bytes of an ID3 size.
+fn cvt_id3(val: u64) -> u64 {
+    (val & 0x7f) | ((val >> 8) & 0x7f) << 7 | ((val >> 16) & 0x7f) << 14 | ((val >> 24) & 0x7f) << 21
+}
+
+/// Apply the operator of an indirect offset, or return `None` if it is out of range.
+fn do_ops(op: &MaskOp, lhs: i64, adj: i64) -> Option<usize> {
+    let limit = |v: i64| v < u32::MAX as i64 && v > i32::MIN as i64;
+    if !limit(lhs) || !limit(adj) {
+        return None;
+    }
+    let offset = if adj == 0 {
+        lhs
+    } else {
+        match op {
+            MaskOp::Noop => lhs,
+            MaskOp::And => lhs & adj,
+            MaskOp::Or => lhs | adj,
+            MaskOp::Xor => lhs ^ adj,
+            MaskOp::Add => lhs + adj,
+            MaskOp::Minus => lhs - adj,
+            MaskOp::Multiply => lhs * adj,
+            MaskOp::Divide => lhs / adj,
+            MaskOp::Modulo => lhs % adj,
+        }
+    };
+    if offset < 0 || offset >= u32::MAX as i64 {
+        return None;
+    }
+    Some(offset as usize)
+}
+
+/// Apply the mask of a numeric type in its width, or return `None` for dividing by 0.
+fn apply_mask(val: u64, mask: &Mask, size: usize) -> Option<u64> {
+    let (op, m) = match mask {
+        Mask::Num { op, val } if *val != 0 => (op, truncate(*val, size)),
+        _ => return Some(val),
+    };
+    let val = match op {
+        MaskOp::Noop => val,
+        MaskOp::And => val & m,
+        MaskOp::Or => val | m,
+        MaskOp::Xor => val ^ m,
+        MaskOp::Add => val.wrapping_add(m),
+        MaskOp::Minus => val.wrapping_sub(m),
+        MaskOp::Multiply => val.wrapping_mul(m),
+        MaskOp::Divide => val.checked_div(m)?,
+        MaskOp::Modulo => val.checked_rem(m)?,
+    };
+    Some(truncate(val, size))
+}
+
+
+/// Compare a pattern with the data as `file_strncmp` of libmagic, and return the difference
+/// of the first different bytes, that is data minus pattern.
+///
+/// `maxlen` is the bytes of the data which the compacted whitespaces can take.
+fn strncmp(pattern: &[u8], data: &[u8], maxlen: usize, flags: StrModifier) -> i64 {
+    let compact = flags.intersects(
+        StrModifier::COMPACT_WHITESPACE | StrModifier::COMPACT_OPTIONAL_WHITESPACE
+    );
+    let end = if compact { maxlen } else { pattern.len() }.min(data.len());
+    let at = |s: &[u8], i: usize| s.get(i).copied().unwrap_or(0);
+    let (mut a, mut b) = (0, 0);
+    let mut v: i64 = 0;
+    let mut len = pattern.len();
+    while len > 0 {
+        len -= 1;
+        if b >= end && compact {
+            v = 1;
+            break;
+        }
+        let ca = at(pattern, a);
+        let cb = at(data, b);
+        if flags.contains(StrModifier::IGNORE_LOWERCASE) && ca.is_ascii_lowercase() {
+            v = cb.to_ascii_lowercase() as i64 - ca as i64;
+            a += 1;
+            b += 1;
+        } else if flags.contains(StrModifier::IGNORE_UPPERCASE) && ca.is_ascii_uppercase() {
+            v = cb.to_ascii_uppercase() as i64 - ca as i64;
+            a += 1;
+            b += 1;
+        } else if flags.contains(StrModifier::COMPACT_WHITESPACE) && is_space(ca) {
+            a += 1;
+            if is_space(cb) {
+                b += 1;
+                if !is_space(at(pattern, a)) {
+                    while b < end && is_space(at(data, b)) {
+                        b += 1;
+                    }
+                }
+            } else {
+                v = 1;
+                break;
+            }
+        } else if flags.contains(StrModifier::COMPACT_OPTIONAL_WHITESPACE) && is_space(ca) {
+            a += 1;
+            while b < end && is_space(at(data, b)) {
+                b += 1;
+            }
+        } else {
+            v = cb as i64 - ca as i64;
+            a += 1;
+            b += 1;
+        }
+        if v != 0 {
+            break;
+        }
+    }
+    if len == 0 && v == 0 && flags.contains(StrModifier::FULL_WORD) {
+        let next = at(data, b);
+        if next != 0 && !is_space(next) {
+            v = 1;
+        }
+    }
+    v
+}
+
+/// `isspace(3)` in the C locale
+fn is_space(c: u8) -> bool {
+    matches!(c, b' ' | b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r')
+}
+
+
+/// An entry which matches a buffer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    /// The description printed by the entry
+    pub desc: String,
+    /// The first MIME type of the matched lines
+    pub mime: Option<String>,
+    /// The extensions of all the matched lines
+    pub exts: Vec<String>,
+    /// The strength of the entry, which decides the order of the matches
+    pub strength: i64,
+}
+
+/// A line tried on a buffer, which tells where the matching goes and why, as the debugging
+/// output of `file -d`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TraceLine {
+    /// The magic file of the line
+    pub path: PathBuf,
+    /// The 1-based line number in the magic file
+    pub line_no: usize,
+    /// The line in the magic(5) syntax
+    pub rule: String,
+    /// How deep the line is, counting the continuation levels and the entries tried by
+    /// `use` and `indirect`
+    pub depth: usize,
+    /// The offset which the value is read at after any indirection, or `None` if it is out
+    /// of the buffer, which is the one of `indirect` for its entries
+    pub offset: Option<usize>,
+    /// The bytes which the value is read from, or which a `search` or a `regex` matches
+    pub raw: Vec<u8>,
+    /// The value after the mask, or `None` if there is none to compare
+    pub value: Option<String>,
+    /// If the relation holds
+    pub matched: bool,
+}
+
+
+/// Matching a buffer against the magic entries, as `softmagic.c` of libmagic.
+pub(crate) struct SoftMagic<'a> {
+    mlist: &'a MagicList,
+    param: &'a MagicParam,
+    flags: Flags,
+    /// If the file is executable, for the `${x?yes:no}` variables
+    executable: bool,
+    /// `MagicFlags::BINTEST` or `MagicFlags::TEXTTEST`, the kind of the entries tried
+    mode: MagicFlags,
+    /// If the buffer looks like text
+    text: bool,
+    out: String,
+    levels: Vec<Level>,
+    /// The offset of the last negative offset, which the absolute offsets of its
+    /// continuations are relative to
+    eoffset: usize,
+    printed_something: bool,
+    need_separator: bool,
+    firstline: bool,
+    /// Set when an annotation such as the MIME type is printed, which ends the matching
+    annotated: bool,
+    /// The MIME type and the extensions of the lines matched so far in the current entry
+    mime: Option<String>,
+    exts: Vec<String>,
+    /// The entries which match, in the order of strength
+    matches: Vec<Match>,
+    indir_count: usize,
+    name_count: usize,
+    /// The lines tried so far, if they are traced
+    trace: Option<Vec<TraceLine>>,
+    /// How deep the entries of `use` and `indirect` are, for the trace
+    depth: usize,
+}
+
+impl<'a> SoftMagic<'a> {
+    pub(crate) fn new(mlist: &'a MagicList, param: &'a MagicParam, flags: Flags, executable: bool) -> Self {
+        SoftMagic {
+            mlist,
+            param,
+            flags,
+            executable,
+            mode: MagicFlags::BINTEST,
+            text: false,
+            out: String::new(),
+            levels: vec![],
+            eoffset: 0,
+            printed_something: false,
+            need_separator: false,
+            firstline: true,
+            annotated: false,
+            mime: None,
+            exts: vec![],
+            matches: vec![],
+            indir_count: 0,
+            name_count: 0,
+            trace: None,
+            depth: 0,
+        }
+    }
+
+    /// Record the lines which are tried.
+    pub(crate) fn enable_trace(&mut self) {
+        self.trace = Some(vec![]);
+    }
+
+    /// Match the buffer against the binary or the text tests by `mode`, and return true if
+    /// anything is printed.
+    pub(crate) fn run(&mut self, buf: &[u8], mode: MagicFlags, text: bool) -> Result<bool, String> {
+        self.mode = mode;
+        self.text = text;
+        let mlist = self.mlist;
+        let mut returnval = false;
+        let mut found_match = false;
+        self.match_entries(mlist.top(), buf, 0, false, &mut returnval, &mut found_match)
+    }
+
+    pub(crate) fn into_output(self) -> String {
+        self.out
+    }
+
+    pub(crate) fn into_matches(self) -> Vec<Match> {
+        self.matches
+    }
+
+    pub(crate) fn into_trace(self) -> Vec<TraceLine> {
+        self.trace.unwrap_or_default()
+    }
+
+    /// Record a line before it is tried, so that it comes before the lines tried for it by
+    /// `use` and `indirect`, and return where it is in the trace.
+    fn trace_start(&mut self, line: &MagicLine) -> Option<usize> {
+        let trace = self.trace.as_mut()?;
+        let loc = line.loc();
+        trace.push(TraceLine {
+            path: loc.path.clone(),
+            line_no: loc.line_no,
+            rule: line.fields().join(" "),
+            depth: self.depth + line.cont_lvl(),
+            offset: None,
+            raw: vec![],
+            value: None,
+            matched: false,
+        });
+        Some(trace.len() - 1)
+    }
+
+    /// Record the value of a line and the result of its relation.
+    fn trace_end(&mut self, slot: Option<usize>, line: &MagicLine, buf: &[u8], got: Option<&Got>, matched: bool) {
+        let traced = match (slot, self.trace.as_mut()) {
+            (Some(slot), Some(trace)) => &mut trace[slot],
+            _ => return,
+        };
+        traced.matched = matched;
+        if let Some(got) = got {
+            traced.offset = Some(got.offset);
+            traced.raw = raw_bytes(line, buf, got);
+            traced.value = match &got.val {
+                Value::None => None,
+                Value::Num(val) => Some(format!("{:#x}", val)),
+                Value::Float(val) => Some(val.to_string()),
+                Value::Str(_) => Some(format!("\"{}\"", print::printable(&traced.raw, false))),
+                Value::Search { len: 0, .. } => None,
+                Value::Search { .. } => Some(format!("\"{}\"", print::printable(&traced.raw, false))),
+                Value::Guid(guid) => Some(fmt_guid(guid)),
+            };
+        }
+    }
+
+    fn print_sep(&mut self) {
+        if !self.firstline {
+            self.out.push_str("\n- ");
+        }
+    }
+
+    /// Print the annotation of a matched line which is asked by the flags instead of the
+    /// descriptions, and return true if any, as `handle_annotation` of libmagic.
+    fn handle_annotation(&mut self, line: &MagicLine) -> bool {
+        let types = match line.aux_types() {
+            Some(types) => types,
+            None => return false,
+        };
+        if self.mime.is_none() {
+            self.mime = types.mime().map(|mime| print::expand_vars(mime, self.executable));
+        }
+        for ext in types.exts() {
+            if !self.exts.contains(ext) {
+                self.exts.push(ext.clone());
+            }
+        }
+
+        if self.flags.contains(Flags::APPLE) {
+            if let Some(apple) = types.apple() {
+                self.print_sep();
+                // libmagic keeps only 8 chars of it
+                self.out.extend(apple.chars().take(8));
+                self.annotated = true;
+                return true;
+            }
+        }
+        if self.flags.contains(Flags::EXTENSION) {
+            // the extensions of all the matched lines are printed when the entry ends
+            return false;
+        }
+        if self.flags.contains(Flags::MIME_TYPE) {
+            if let Some(mime) = types.mime() {
+                let mime = print::expand_vars(mime, self.executable);
+                self.print_sep();
+                self.out.push_str(&mime);
+                self.annotated = true;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Reset the state of a level when entering it.
+    fn enter_level(&mut self, lvl: usize) {
+        if self.levels.len() <= lvl {
+            self.levels.resize(lvl + 1, Level::default());
+        }
+        self.levels[lvl].got_match = false;
+    }
+
+    /// Match the entries until one matches, or all of them with `Flags::CONTINUE`, as
+    /// `match()` of libmagic.
+    ///
+    /// `o` is the offset which the absolute offsets are relative to, and `flip` swaps the
+    /// endianness of the types, for `use`.
+    fn match_entries(
+        &mut self,
+        entries: &[usize],
+        buf: &[u8],
+        o: usize,
+        flip: bool,
+        returnval: &mut bool,
+        found_match: &mut bool,
+    ) -> Result<bool, String> {
+        // the entries matched for `use` and `indirect` are part of the current entry
+        let nested = self.name_count > 0 || self.indir_count > 0;
+        for &idx in entries {
+            if !self.is_tried(idx) {
+                continue;
+            }
+            let start = self.out.len();
+            if !nested {
+                self.mime = None;
+                self.exts.clear();
+            }
+            let mut entry_found = false;
+            self.match_entry(idx, buf, o, flip, returnval, &mut entry_found)?;
+            *found_match |= entry_found;
+            if !nested && entry_found {
+                let desc = &self.out[start..];
+                self.matches.push(Match {
+                    desc: desc.strip_prefix("\n- ").unwrap_or(desc).to_string(),
+                    mime: self.mime.clone(),
+                    exts: self.exts.clone(),
+                    strength: self.mlist.strength(idx),
+                });
+            }
+            if !nested && self.flags.contains(Flags::EXTENSION) && !self.exts.is_empty() {
+                self.print_sep();
+                self.out.push_str(&self.exts.join("/"));
+                self.annotated = true;
+                *returnval = true;
+            }
+            if self.annotated {
+                return Ok(true);
+            }
+
+            if self.printed_something {
+                self.firstline = false;
+            }
+            if *found_match {
+                if !self.flags.contains(Flags::CONTINUE) {
+                    return Ok(*returnval);
+                }
+                // so that a separator is printed
+                self.printed_something = false;
+                self.firstline = false;
+            }
+        }
+        Ok(*returnval)
+    }
+
+    /// Return true if an entry is of the kind of tests being tried, where the string tests
+    /// with a `b` or a `t` modifier are tried only on the binary or the text buffers.
+    fn is_tried(&self, idx: usize) -> bool {
+        let line = &self.mlist.entries()[idx].lines()[0];
+        if *line.cmp_type() == CmpType::Name {
+            return true;
+        }
+        let test = line.str_flags() & (StrModifier::BINTEST | StrModifier::TEXTTEST);
+        if line.cmp_type().is_string() &&
+            ((self.text && test == StrModifier::BINTEST) || (!self.text && test == StrModifier::TEXTTEST)) {
+            return false;
+        }
+        self.mlist.test_type(idx).contains(self.mode)
+    }
+
+    fn match_entry(
+        &mut self,
+        idx: usize,
+        buf: &[u8],
+        o: usize,
+        flip: bool,
+        returnval: &mut bool,
+        found_match: &mut bool,
+    ) -> Result<(), String> {
+        let lines = self.mlist.entries()[idx].lines();
+        let print = !self.flags.intersects(Flags::NODESC);
+
+        self.enter_level(0);
+        let line = &lines[0];
+        let slot = self.trace_start(line);
+        let offset = match self.set_offset(line, buf, o, 0)? {
+            Some(offset) => offset,
+            None => {
+                self.trace_end(slot, line, buf, None, false);
+                return Ok(());
+            }
+        };
+        let mut got = self.mget(line, buf, offset, o, 0, flip, returnval, found_match)?;
+        let matched = match &mut got {
+            None => *line.reln_op() == RelnOp::Not,
+            Some(got) => {
+                if *line.cmp_type() == CmpType::Indirect {
+                    *found_match = true;
+                    *returnval = true;
+                }
+                self.check(idx, 0, line, got)
+            }
+        };
+        self.trace_end(slot, line, buf, got.as_ref(), matched);
+        if !matched || self.annotated {
+            return Ok(());
+        }
+
+        if !line.desc().is_empty() {
+            *found_match = true;
+        }
+        if self.handle_annotation(line) {
+            *found_match = true;
+            *returnval = true;
+            return Ok(());
+        }
+        if print && !line.desc().is_empty() {
+            *returnval = true;
+            self.need_separator = true;
+            self.printed_something = true;
+            self.print_sep();
+            self.mprint(line, got.as_ref());
+        }
+
+        match self.moffset(line, got.as_ref(), buf.len()) {
+            Some(off) => self.levels[0].off = off,
+            None => return Ok(()),
+        }
+
+        let mut cont_lvl = 1;
+        self.enter_level(cont_lvl);
+        for (j, line) in lines.iter().enumerate().skip(1) {
+            if cont_lvl < line.cont_lvl() {
+                continue;
+            }
+            // the end of the continuations at the deeper levels
+            cont_lvl = line.cont_lvl();
+
+            let slot = self.trace_start(line);
+            let offset = match self.set_offset(line, buf, o, cont_lvl)? {
+                Some(offset) => offset,
+                None => {
+                    self.trace_end(slot, line, buf, None, false);
+                    return Ok(());
+                }
+            };
+            let mut got = self.mget(line, buf, offset, o, cont_lvl, flip, returnval, found_match)?;
+            let matched = match &mut got {
+                None => *line.reln_op() == RelnOp::Not,
+                Some(got) => {
+                    if *line.cmp_type() == CmpType::Indirect {
+                        *found_match = true;
+                        *returnval = true;
+                    }
+                    self.check(idx, j, line, got)
+                }
+            };
+            self.trace_end(slot, line, buf, got.as_ref(), matched);
+            if got.is_none() && !matched {
+                continue;
+            }
+            if self.annotated {
+                return Ok(());
+            }
+            if !matched {
+                continue;
+            }
+
+            let level = &mut self.levels[cont_lvl];
+            match line.cmp_type() {
+                CmpType::Clear => level.got_match = false,
+                // a `default` line matches only if nothing else at the level matches
+                CmpType::Default if level.got_match => continue,
+                _ => level.got_match = true,
+            }
+
+            if !line.desc().is_empty() {
+                *found_match = true;
+            }
+            if self.handle_annotation(line) {
+                *found_match = true;
+                *returnval = true;
+                return Ok(());
+            }
+            if print && !line.desc().is_empty() {
+                *returnval = true;
+                if !self.printed_something {
+                    self.printed_something = true;
+                    self.print_sep();
+                }
+                // a space if something is printed before, unless the desc is led by `\b`
+                if self.need_separator && line.desc().starts_with(' ') {
+                    self.out.push(' ');
+                }
+                self.mprint(line, got.as_ref());
+                self.need_separator = true;
+            }
+
+            // the deeper continuations are skipped if the end is beyond the buffer
+            if let Some(off) = self.moffset(line, got.as_ref(), buf.len()) {
+                self.levels[cont_lvl].off = off;
+                cont_lvl += 1;
+                self.enter_level(cont_lvl);
+            }
+        }
+        Ok(())
+    }
+
+    /// Compute the offset of a line before any indirection, as `msetoffset` of libmagic.
+    ///
+    /// Return `None` if a negative offset is out of the buffer, which skips the rest of
+    /// the entry.
+    fn set_offset(&mut self, line: &MagicLine, buf: &[u8], o: usize, cont_lvl: usize)
+        -> Result<Option<i64>, String> {
+        let off = line.offset();
+        let relative = cont_lvl > 0 && off.relative;
+        let indir_relative = cont_lvl > 0 && off.indir.as_ref().is_some_and(|indir| indir.relative);
+
+        if off.base < 0 && !relative && !indir_relative {
+            if o != 0 {
+                return Err(format!("non zero offset {} at level {}", o, cont_lvl));
+            }
+            let back = off.base.unsigned_abs() as usize;
+            if back > buf.len() {
+                return Ok(None);
+            }
+            self.eoffset = buf.len() - back;
+            return Ok(Some(self.eoffset as i64));
+        }
+
+        if relative {
+            return Ok(Some(self.levels[cont_lvl - 1].off as i64 + off.base));
+        }
+        if cont_lvl == 0 || indir_relative {
+            self.eoffset = 0;
+            return Ok(Some(off.base + o as i64));
+        }
+        Ok(Some(self.eoffset as i64 + off.base + o as i64))
+    }
+
+    /// Resolve the indirect offset of a line, or return `None` if it is out of the buffer.
+    fn indirect_offset(&self, line: &MagicLine, buf: &[u8], offset: i64, cont_lvl: usize, flip: bool)
+        -> Option<i64> {
+        let indir = match &line.offset().indir {
+            Some(indir) => indir,
+            None => return Some(offset),
+        };
+        let typ = if flip { indir.typ.flip() } else { indir.typ };
+        if typ.is_float() {
+            return None;
+        }
+        let size = typ.size()?;
+        let read = |at: i64| {
+            let val = read_uint(buf, at, size, endian(typ))?;
+            let val = match typ {
+                CmpType::BEID3 | CmpType::LEID3 => cvt_id3(val),
+                _ => val,
+            };
+            Some(if indir.signed { sign_extend(val, size) as i64 } else { val as i64 })
+        };
+
+        let adj = if indir.adj_indir { read(offset + indir.adj)? } else { indir.adj };
+        let mut res = do_ops(&indir.op, read(offset)?, adj)? as i64;
+        if indir.relative {
+            if cont_lvl == 0 {
+                return None;
+            }
+            res += self.levels[cont_lvl - 1].off as i64;
+        }
+        Some(res)
+    }
+
+    /// Read the value of a line, or return `None` if it cannot match, as `mget` of
+    /// libmagic.
+    #[allow(clippy::too_many_arguments)]
+    fn mget<'b>(
+        &mut self,
+        line: &MagicLine,
+        buf: &'b [u8],
+        offset: i64,
+        o: usize,
+        cont_lvl: usize,
+        flip: bool,
+        returnval: &mut bool,
+        found_match: &mut bool,
+    ) -> Result<Option<Got<'b>>, String> {
+        if self.indir_count >= self.param.get(Param::Indir) as usize {
+            return Err(format!("indirect count ({}) exceeded", self.indir_count));
+        }
+        if self.name_count >= self.param.get(Param::Name) as usize {
+            return Err(format!("name use count ({}) exceeded", self.name_count));
+        }
+
+        let offset = match self.indirect_offset(line, buf, offset, cont_lvl, flip) {
+            Some(offset) if offset >= 0 => offset as usize,
+            _ => return Ok(None),
+        };
+        let typ = if flip { line.cmp_type().flip() } else { *line.cmp_type() };
+        let got = |val| Ok(Some(Got { offset, val }));
+
+        if let Some(size) = typ.size() {
+            if typ == CmpType::Offset {
+                return got(Value::Num(offset as u64));
+            }
+            let raw = match read_uint(buf, offset as i64, size, endian(typ)) {
+                Some(raw) => raw,
+                None => return Ok(None),
+            };
+            if typ.is_float() {
+                let val = if size == 4 { f32::from_bits(raw as u32) as f64 } else { f64::from_bits(raw) };
+                return got(Value::Float(val));
+            }
+            let raw = match typ {
+                CmpType::BEID3 | CmpType::LEID3 => cvt_id3(raw),
+                _ => raw,
+            };
+            return match apply_mask(raw, line.mask(), size) {
+                Some(val) => got(Value::Num(val)),
+                None => Ok(None),
+            };
+        }
+
+        let vallen = match line.reln_val() {
+            RelnVal::Str(s) => s.len(),
+            _ => 0,
+        };
+        match typ {
+            CmpType::String | CmpType::PString | CmpType::Octal => {
+                if offset.checked_add(vallen).is_none_or(|end| end > buf.len()) {
+                    return Ok(None);
+                }
+                let s = Self::read_str(buf, offset);
+                if typ == CmpType::PString {
+                    return Ok(Self::read_pstring(line, &s).map(|s| Got { offset, val: Value::Str(s) }));
+                }
+                if typ == CmpType::Octal {
+                    return got(Value::Num(Self::parse_octal(&s)));
+                }
+                got(Value::Str(s))
+            }
+            CmpType::BEString16 | CmpType::LEString16 => {
+                if offset >= buf.len() {
+                    return Ok(None);
+                }
+                got(Value::Str(Self::read_str16(buf, offset, typ == CmpType::BEString16)))
+            }
+            CmpType::Search => {
+                if offset.checked_add(vallen).is_none_or(|end| end > buf.len()) {
+                    return Ok(None);
+                }
+                got(Value::Search { region: &buf[offset..], start: offset, len: 0 })
+            }
+            CmpType::Regex => {
+                if offset > buf.len() {
+                    return Ok(None);
+                }
+                got(Value::Search { region: self.regex_region(line, buf, offset), start: offset, len: 0 })
+            }
+            CmpType::Guid => match buf.get(offset..offset + 16) {
+                Some(bytes) => got(Value::Guid(bytes.try_into().unwrap())),
+                None => Ok(None),
+            },
+            CmpType::Indirect => {
+                let mut offset = offset;
+                if let Mask::Str { flags, .. } = line.mask() {
+                    if flags.contains(StrModifier::INDIRECT_RRELATIVE) {
+                        offset += o;
+                    }
+                }
+                if offset == 0 || offset > buf.len() {
+                    return Ok(None);
+                }
+                if self.indirect(line, buf, offset)? {
+                    got(Value::None)
+                } else {
+                    Ok(None)
+                }
+            }
+            CmpType::Use => {
+                if offset > buf.len() {
+                    return Ok(None);
+                }
+                let nfound = self.use_name(line, buf, offset, flip, returnval)?;
+                *found_match |= nfound;
+                if *returnval || *found_match {
+                    got(Value::None)
+                } else {
+                    Ok(None)
+                }
+            }
+            CmpType::Name => {
+                if !self.flags.intersects(Flags::NODESC) {
+                    let desc = line.desc().to_string();
+                    self.out.push_str(desc.strip_prefix(' ').unwrap_or(&desc));
+                }
+                got(Value::None)
+            }
+            CmpType::Default | CmpType::Clear => got(Value::None),
+            // der and varint are not supported
+            _ => Ok(None),
+        }
+    }
+
+    /// Read a string value, which is zero padded to `MAX_STRING` bytes.
+    fn read_str(buf: &[u8], offset: usize) -> Vec<u8> {
+        let mut s = buf[offset.min(buf.len())..].iter().take(MAX_STRING - 1).copied().collect::<Vec<u8>>();
+        s.resize(MAX_STRING, 0);
+        s
+    }
+
+    /// Read a `pstring` value from its length prefix, or return `None` if the length is
+    /// smaller than the prefix with `J`.
+    fn read_pstring(line: &MagicLine, s: &[u8]) -> Option<Vec<u8>> {
+        let flags = match line.mask() {
+            Mask::Str { flags, .. } => *flags,
+            Mask::Num { .. } => StrModifier::NONE,
+        };
+        let (size, endian) = Self::pstring_len(flags);
+        let mut len = read_uint(s, 0, size, endian)? as usize;
+        if flags.contains(StrModifier::PSTRING_LENGTH_INCLUDES_ITSELF) {
+            len = len.checked_sub(size)?;
+        }
+        let len = len.min(MAX_STRING - size);
+        let mut val = s[size..size + len].to_vec();
+        val.resize(MAX_STRING, 0);
+        Some(val)
+    }
+
+    /// The size and the endianness of the length prefix of a `pstring`.
+    fn pstring_len(flags: StrModifier) -> (usize, Endian) {
+        if flags.contains(StrModifier::PSTRING_2_BE) {
+            (2, Endian::Big)
+        } else if flags.contains(StrModifier::PSTRING_2_LE) {
+            (2, Endian::Little)
+        } else if flags.contains(StrModifier::PSTRING_4_BE) {
+            (4, Endian::Big)
+        } else if flags.contains(StrModifier::PSTRING_4_LE) {
+            (4, Endian::Little)
+        } else {
+            (1, Endian::Little)
+        }
+    }
+
+    /// Read a UCS-2 string by its low bytes, where a char other than NUL with a zero low
+    /// byte is taken as a space.
+    fn read_str16(buf: &[u8], offset: usize, big: bool) -> Vec<u8> {
+        let data = &buf[offset..];
+        let mut s = vec![];
+        for pair in data.chunks(2) {
+            if s.len() >= MAX_STRING - 1 {
+                break;
+            }
+            let (lo, hi) = if big {
+                (pair.get(1).copied(), pair[0])
+            } else {
+                (Some(pair[0]), pair.get(1).copied().unwrap_or(0))
+            };
+            let lo = match lo {
+                Some(lo) => lo,
+                None => break,
+            };
+            s.push(if lo == 0 && hi != 0 { b' ' } else { lo });
+        }
+        s.resize(MAX_STRING, 0);
+        s
+    }
+
+    fn parse_octal(s: &[u8]) -> u64 {
+        s.iter()
+            .skip_while(|&&c| is_space(c))
+            .take_while(|c| (b'0'..=b'7').contains(c))
+            .fold(0, |val, &c| val.wrapping_mul(8).wrapping_add((c - b'0') as u64))
+    }
+
+    /// Return the region searched by a `regex`, limited by its range in bytes or in lines.
+    fn regex_region<'b>(&self, line: &MagicLine, buf: &'b [u8], offset: usize) -> &'b [u8] {
+        let (flags, range) = match line.mask() {
+            Mask::Str { flags, range } => (*flags, *range as usize),
+            Mask::Num { .. } => (StrModifier::NONE, 0),
+        };
+        // `l' counts the range in lines
+        let (lines, bytes) = if flags.contains(StrModifier::PSTRING_4_LE) {
+            (range, range.saturating_mul(80))
+        } else {
+            (0, range)
+        };
+
+        let avail = buf.len() - offset;
+        let bytes = if bytes == 0 || bytes > avail { avail } else { bytes }
+            .min(self.param.get(Param::Regex) as usize);
+        let region = &buf[offset..offset + bytes];
+        if lines == 0 {
+            return region;
+        }
+
+        let mut end = 0;
+        let mut pos = 0;
+        for _ in 0..lines {
+            let brk = match region[pos..].iter().position(|&c| c == b'\n')
+                .or_else(|| region[pos..].iter().position(|&c| c == b'\r')) {
+                Some(i) => pos + i,
+                None => return region,
+            };
+            let mut b = brk;
+            if b + 1 < region.len() && region[b] == b'\r' && region[b + 1] == b'\n' {
+                b += 1;
+            }
+            end = b + 1;
+            pos = end;
+            if pos >= region.len() {
+                break;
+            }
+        }
+        &region[..end]
+    }
+
+    /// Match the buffer from `offset` against all the entries, and print the description of
+    /// the line with the result.
+    fn indirect(&mut self, line: &MagicLine, buf: &[u8], offset: usize) -> Result<bool, String> {
+        let saved_out = mem::take(&mut self.out);
+        let saved_levels = mem::take(&mut self.levels);
+        let saved_eoffset = self.eoffset;
+        // the output of the buffer is printed as a whole by this line
+        let saved_state = (self.printed_something, self.need_separator, self.firstline);
+        self.printed_something = false;
+        self.need_separator = false;
+        self.firstline = true;
+        // the buffer is tried with the binary tests only, as libmagic does
+        let saved_mode = mem::replace(&mut self.mode, MagicFlags::BINTEST);
+        self.indir_count += 1;
+        self.depth += line.cont_lvl() + 1;
+
+        let mlist = self.mlist;
+        let mut returnval = false;
+        let mut found_match = false;
+        let res = self.match_entries(mlist.top(), &buf[offset..], 0, false, &mut returnval, &mut found_match);
+
+        self.indir_count -= 1;
+        self.depth -= line.cont_lvl() + 1;
+        self.mode = saved_mode;
+        self.levels = saved_levels;
+        self.eoffset = saved_eoffset;
+        (self.printed_something, self.need_separator, self.firstline) = saved_state;
+        let inner = mem::replace(&mut self.out, saved_out);
+        if !res? {
+            return Ok(false);
+        }
+
+        if !self.flags.intersects(Flags::NODESC) {
+            let desc = self.format_desc(line);
+            self.out.push_str(&print::sprintf(&desc, &FmtArg::Num { val: offset as u64, size: 4 }));
+        }
+        self.out.push_str(&inner);
+        Ok(true)
+    }
+
+    /// Match the buffer against the entry named by a `use` line, and return true if it
+    /// matches.
+    fn use_name(&mut self, line: &MagicLine, buf: &[u8], offset: usize, flip: bool, returnval: &mut bool)
+        -> Result<bool, String> {
+        let name = match line.reln_val() {
+            RelnVal::Str(name) => name.as_slice(),
+            _ => return Ok(false),
+        };
+        let (name, flip) = match name.strip_prefix(b"^") {
+            Some(name) => (name, !flip),
+            None => (name, flip),
+        };
+        let idx = self.mlist.find_name(name)
+            .ok_or_else(|| format!("cannot find entry `{}'", String::from_utf8_lossy(name)))?;
+
+        let saved_levels = self.levels.clone();
+        let saved_eoffset = self.eoffset;
+        let saved_separator = self.need_separator;
+        // a `use` line led by `\b` joins the output of the named entry to the previous one
+        if !line.desc().is_empty() && !line.desc().starts_with(' ') {
+            self.need_separator = false;
+        }
+
+        let mut nfound = false;
+        self.name_count += 1;
+        self.depth += line.cont_lvl() + 1;
+        let res = self.match_entries(&[idx], buf, offset, flip, returnval, &mut nfound);
+        self.depth -= line.cont_lvl() + 1;
+        self.name_count -= 1;
+
+        self.levels = saved_levels;
+        self.eoffset = saved_eoffset;
+        if !res? {
+            self.need_separator = saved_separator;
+        }
+        Ok(nfound)
+    }
+
+    /// Check the value against the relation of a line, as `magiccheck` of libmagic.
+    fn check(&self, idx: usize, line_idx: usize, line: &MagicLine, got: &mut Got) -> bool {
+        let op = line.reln_op();
+        if *op == RelnOp::Any {
+            return true;
+        }
+        let unsigned = line.cmp_unsigned();
+        let flags = match line.mask() {
+            Mask::Str { flags, .. } => *flags,
+            Mask::Num { .. } => StrModifier::NONE,
+        };
+        let pattern: &[u8] = match line.reln_val() {
+            RelnVal::Str(s) => s,
+            _ => &[],
+        };
+
+        let (v, l) = match &mut got.val {
+            Value::None => (0, 0),
+            Value::Float(f) => {
+                let l = match line.reln_val() {
+                    RelnVal::Float(l) => *l,
+                    _ => return false,
+                };
+                return match op {
+                    RelnOp::Not => f.is_nan() || l.is_nan() || *f != l,
+                    RelnOp::Eq => *f == l,
+                    RelnOp::Greater => *f > l,
+                    RelnOp::Less => *f < l,
+                    _ => false,
+                };
+            }
+            Value::Num(val) => {
+                let size = line.cmp_type().size().unwrap_or(8);
+                let l = match line.reln_val() {
+                    RelnVal::Num(l) => *l,
+                    _ => return false,
+                };
+                if unsigned {
+                    (*val, l)
+                } else {
+                    (sign_extend(*val, size), sign_extend(truncate(l, size), size))
+                }
+            }
+            Value::Str(s) => {
+                let maxlen = s.len();
+                (strncmp(pattern, s, maxlen, flags) as u64, 0)
+            }
+            Value::Guid(guid) => {
+                let v = match parse_guid(pattern) {
+                    Some(l) if l == *guid => 0,
+                    _ => 1,
+                };
+                (v, 0)
+            }
+            Value::Search { region, start, len } if *line.cmp_type() == CmpType::Search => {
+                let range = match line.mask() {
+                    Mask::Str { range, .. } => *range as usize,
+                    Mask::Num { .. } => 0,
+                };
+                let mut v = 1;
+                let mut idx = 0;
+                while range == 0 || idx < range {
+                    if pattern.len() + idx > region.len() {
+                        return false;
+                    }
+                    v = strncmp(pattern, &region[idx..], region.len() - idx, flags);
+                    if v == 0 {
+                        *start += idx;
+                        *len = region.len() - idx;
+                        break;
+                    }
+                    idx += 1;
+                }
+                (v as u64, 0)
+            }
+            Value::Search { region, start, len } => {
+                let re = match self.mlist.regex(idx, line_idx) {
+                    Some(re) => re,
+                    None => return false,
+                };
+                // libmagic drops the last byte, and the regex ends at a NUL as a C string
+                let text = &region[..region.len().saturating_sub(1)];
+                let text = &text[..text.iter().position(|&c| c == 0).unwrap_or(text.len())];
+                match re.find(text) {
+                    Some(m) => {
+                        *start += m.start();
+                        *len = m.end() - m.start();
+                        (0, 0)
+                    }
+                    None => (1, 0),
+                }
+            }
+        };
+
+        match op {
+            RelnOp::Not => v != l,
+            RelnOp::Eq => v == l,
+            RelnOp::Greater if unsigned => v > l,
+            RelnOp::Greater => (v as i64) > (l as i64),
+            RelnOp::Less if unsigned => v < l,
+            RelnOp::Less => (v as i64) < (l as i64),
+            RelnOp::And => v & l == l,
+            RelnOp::Xor => v & l != l,
+            RelnOp::Any => true,
+            RelnOp::Noop => false,
+        }
+    }
+
+    /// The description to print by `sprintf`, without the leading space and with the
+    /// variables expanded.
+    fn format_desc(&self, line: &MagicLine) -> String {
+        let desc = line.desc();
+        print::expand_vars(desc.strip_prefix(' ').unwrap_or(desc), self.executable)
+    }
+
+    /// Print the description of a matched line with its value, as `mprint` of libmagic.
+    fn mprint(&mut self, line: &MagicLine, got: Option<&Got>) {
+        let raw = self.flags.contains(Flags::RAW);
+        let typ = line.cmp_type();
+        let desc = self.format_desc(line);
+        let val = match got {
+            Some(got) => &got.val,
+            None => &Value::None,
+        };
+
+        let text;
+        let arg = match (typ, val) {
+            (CmpType::Default | CmpType::Clear, _) => {
+                self.out.push_str(&desc);
+                return;
+            }
+            (CmpType::Name | CmpType::Use | CmpType::Indirect, _) => return,
+            (_, Value::Num(val)) if typ.is_date() => {
+                let size = typ.size().unwrap_or(4);
+                text = match typ {
+                    CmpType::MSDOSDate | CmpType::LEMSDOSDate | CmpType::BEMSDOSDate => {
+                        print::fmt_msdos_date(*val as u16)
+                    }
+                    CmpType::MSDOSTime | CmpType::LEMSDOSTime | CmpType::BEMSDOSTime => {
+                        print::fmt_msdos_time(*val as u16)
+                    }
+                    CmpType::LDate | CmpType::BELDate | CmpType::LELDate | CmpType::MELDate |
+                    CmpType::QLDate | CmpType::LEQLDate | CmpType::BEQLDate => {
+                        print::fmt_time(sign_extend(*val, size), TimeKind::Local)
+                    }
+                    CmpType::QWDate | CmpType::LEQWDate | CmpType::BEQWDate => {
+                        print::fmt_time(*val, TimeKind::Windows)
+                    }
+                    _ => print::fmt_time(*val, TimeKind::Utc),
+                };
+                FmtArg::Str(&text)
+            }
+            (CmpType::Octal, Value::Num(val)) => {
+                text = val.to_string();
+                FmtArg::Str(&text)
+            }
+            (_, Value::Num(val)) => FmtArg::Num { val: *val, size: typ.size().unwrap_or(8) },
+            (_, Value::Float(val)) => FmtArg::Float(*val),
+            (_, Value::Str(s)) => {
+                text = match (line.reln_op(), line.reln_val()) {
+                    (RelnOp::Eq | RelnOp::Not, RelnVal::Str(pattern)) => print::printable(pattern, raw),
+                    (_, reln_val) => {
+                        let mut s = Self::cstr(s);
+                        if matches!(reln_val, RelnVal::Str(p) if p.first().is_none_or(|&c| c == 0)) {
+                            s = &s[..s.iter().position(|&c| c == b'\r' || c == b'\n').unwrap_or(s.len())];
+                        }
+                        if let Mask::Str { flags, .. } = line.mask() {
+                            if flags.contains(StrModifier::TRIM) {
+                                s = s.trim_ascii();
+                            }
+                        }
+                        print::printable(s, raw)
+                    }
+                };
+                FmtArg::Str(&text)
+            }
+            (CmpType::Search, Value::Search { .. }) => {
+                text = match line.reln_val() {
+                    RelnVal::Str(pattern) => print::printable(pattern, raw),
+                    _ => String::new(),
+                };
+                FmtArg::Str(&text)
+            }
+            (_, Value::Search { region, start, len }) => {
+                let begin = start - (got.map_or(0, |got| got.offset));
+                text = print::printable(&region[begin..begin + len], raw);
+                FmtArg::Str(&text)
+            }
+            (_, Value::Guid(guid)) => {
+                text = fmt_guid(guid);
+                FmtArg::Str(&text)
+            }
+            (_, Value::None) => FmtArg::Str(""),
+        };
+        self.out.push_str(&print::sprintf(&desc, &arg));
+    }
+
+    /// The bytes of a string value before the first NUL.
+    fn cstr(s: &[u8]) -> &[u8] {
+        &s[..s.iter().position(|&c| c == 0).unwrap_or(s.len())]
+    }
+
+    /// Compute the end of the matched value, which is the base of the relative offsets of
+    /// the next level, as `moffset` of libmagic.
+    ///
+    /// Return `None` if it is beyond the buffer.
+    fn moffset(&self, line: &MagicLine, got: Option<&Got>, len: usize) -> Option<usize> {
+        let got = got?;
+        let typ = line.cmp_type();
+        let flags = match line.mask() {
+            Mask::Str { flags, .. } => *flags,
+            Mask::Num { .. } => StrModifier::NONE,
+        };
+        let pattern: &[u8] = match line.reln_val() {
+            RelnVal::Str(s) => s,
+            _ => &[],
+        };
+
+        let end = match (&got.val, typ) {
+            (_, CmpType::Offset) => got.offset,
+            (_, t) if t.size().is_some() => got.offset + t.size().unwrap(),
+            (Value::Str(s), t) => {
+                if matches!(line.reln_op(), RelnOp::Eq | RelnOp::Not) {
+                    got.offset + pattern.len()
+                } else {
+                    let mut s = Self::cstr(s);
+                    if pattern.first().is_none_or(|&c| c == 0) {
+                        s = &s[..s.iter().position(|&c| c == b'\r' || c == b'\n').unwrap_or(s.len())];
+                    }
+                    let mut end = got.offset + s.len();
+                    if *t == CmpType::PString {
+                        end += Self::pstring_len(flags).0;
+                    }
+                    end
+                }
+            }
+            (Value::Search { start, len, .. }, t) => {
+                if flags.contains(StrModifier::REGEX_OFFSET_START) {
+                    *start
+                } else if *t == CmpType::Search {
+                    start + pattern.len()
+                } else {
+                    start + len
+                }
+            }
+            (Value::Guid(_), _) => got.offset + 16,
+            _ => got.offset,
+        };
+        if end > len {
+            None
+        } else {
+            Some(end)
+        }
+    }
+}
+
+
+/// Parse a GUID such as `8BE4DF61-93CA-11D2-AA0D-00E098032B8C` into its bytes in the order
+/// of the file, where the first 3 fields are little endian.
+fn parse_guid(s: &[u8]) -> Option<[u8; 16]> {
+    let s = std::str::from_utf8(s).ok()?;
+    let parts: Vec<&str> = s.split('-').collect();
+    if parts.len() != 5 || parts.iter().map(|p| p.len()).ne([8, 4, 4, 4, 12]) {
+        return None;
+    }
+    let mut guid = [0; 16];
+    let data1 = u32::from_str_radix(parts[0], 16).ok()?;
+    let data2 = u16::from_str_radix(parts[1], 16).ok()?;
+    let data3 = u16::from_str_radix(parts[2], 16).ok()?;
+    guid[..4].copy_from_slice(&data1.to_le_bytes());
+    guid[4..6].copy_from_slice(&data2.to_le_bytes());
+    guid[6..8].copy_from_slice(&data3.to_le_bytes());
+    let data4 = format!("{}{}", parts[3], parts[4]);
+    for i in 0..8 {
+        guid[8 + i] = u8::from_str_radix(&data4[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(guid)
+}
+
+/// The bytes of the buffer which a value is read from, for the trace.
+fn raw_bytes(line: &MagicLine, buf: &[u8], got: &Got) -> Vec<u8> {
+    let len = match &got.val {
+        Value::None => 0,
+        Value::Num(_) | Value::Float(_) => line.cmp_type().size().unwrap_or(0),
+        Value::Str(s) => match line.reln_val() {
+            RelnVal::Str(pattern) if !pattern.is_empty() => pattern.len(),
+            _ => s.iter().position(|&c| c == 0).unwrap_or(s.len()),
+        },
+        // the length of a `search` match is the rest of the buffer, as `rm_len` of libmagic
+        Value::Search { start, len, .. } => {
+            let len = match (line.cmp_type(), line.reln_val()) {
+                (CmpType::Search, RelnVal::Str(pattern)) if *len > 0 => pattern.len(),
+                _ => *len,
+            };
+            return buf.get(*start..start + len).unwrap_or_default().to_vec();
+        }
+        Value::Guid(_) => 16,
+    };
+    buf[got.offset.min(buf.len())..].iter().take(len).copied().collect()
+}
+
+fn fmt_guid(guid: &[u8; 16]) -> String {
+    let data1 = u32::from_le_bytes(guid[..4].try_into().unwrap());
+    let data2 = u16::from_le_bytes([guid[4], guid[5]]);
+    let data3 = u16::from_le_bytes([guid[6], guid[7]]);
+    let data4: String = guid[8..].iter().map(|b| format!("{:02X}", b)).collect();
+    format!("{:08X}-{:04X}-{:04X}-{}-{}", data1, data2, data3, &data4[..4], &data4[4..])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{strncmp, Match, SoftMagic};
+    use crate::apprentice::MagicList;
+    use crate::encoding::Encoding;
+    use crate::magic::{MagicFlags, StrModifier};
+    use crate::magic_param::MagicParam;
+    use crate::magic_set::Flags;
+
+    const MAGIC: &str = "\
+0	name	elf-le
+>16	leshort	2	executable,
+!:ext	bin
+>16	leshort	3	${x?pie executable:shared object},
+!:mime	application/x-${x?pie-executable:sharedlib}
+>18	leshort	62	x86-64
+0	string	\\177ELF	ELF
+!:ext	elf
+>4	byte	2	64-bit
+>5	byte	1	LSB
+>>0	use	elf-le
+>5	byte	2	MSB
+>>0	use	\\^elf-le
+0	string	\\037\\213	gzip compressed data
+!:mime	application/gzip
+!:apple	????GZIP
+>3	byte	&0x08	\\b, was \"%s\"
+>-4	ulelong	x	\\b, original size modulo 2^32 %u
+0	string/b	MZ
+>0x18	uleshort	<0x40	MS-DOS executable
+>0x18	uleshort	>0x3f
+>>(0x3c.l)	string	PE\\0\\0	PE
+>>>(0x3c.l+24)	leshort	0x020b	\\b32+ executable
+0	search/64/c	\\<html	HTML document text
+0	regex	\\^#!.*python	Python script text executable
+0	belong	0x12345678	tagged
+>4	byte	1	one
+>4	default	x	other
+0	string	AB	AB
+0	string	ABC	ABC %s
+";
+
+    fn run(flags: Flags, executable: bool, buf: &[u8]) -> Option<String> {
+        let mlist = MagicList::load_str(MAGIC);
+        let param = MagicParam::default();
+        let text = Encoding::detect(buf).is_text();
+        let mut soft = SoftMagic::new(&mlist, &param, flags, executable);
+        if soft.run(buf, MagicFlags::BINTEST, text).unwrap() {
+            return Some(soft.into_output());
+        }
+        let mut soft = SoftMagic::new(&mlist, &param, flags, executable);
+        match text && soft.run(buf, MagicFlags::TEXTTEST, text).unwrap() {
+            true => Some(soft.into_output()),
+            false => None,
+        }
+    }
+
+    fn elf(big: bool) -> Vec<u8> {
+        let mut buf = b"\x7fELF\x02\x01\x01\x00".to_vec();
+        buf.resize(24, 0);
+        let (typ, machine) = if big { ([0, 3], [0, 62]) } else { ([3, 0], [62, 0]) };
+        buf[5] = if big { 2 } else { 1 };
+        buf[16..18].copy_from_slice(&typ);
+        buf[18..20].copy_from_slice(&machine);
+        buf
+    }
+
+    #[test]
+    fn test_use() {
+        assert_eq!(run(Flags::NONE, false, &elf(false)).unwrap(), "ELF 64-bit LSB shared object, x86-64");
+        assert_eq!(run(Flags::NONE, true, &elf(false)).unwrap(), "ELF 64-bit LSB pie executable, x86-64");
+        assert_eq!(run(Flags::NONE, false, &elf(true)).unwrap(), "ELF 64-bit MSB shared object, x86-64");
+    }
+
+    #[test]
+    fn test_match() {
+        let gzip = b"\x1f\x8b\x08\x08\x00\x00\x00\x00\x00\x03abc\x00\x05\x00\x00\x00";
+        assert_eq!(
+            run(Flags::NONE, false, gzip).unwrap(),
+            "gzip compressed data, was \"8\", original size modulo 2^32 5"
+        );
+
+        let mut pe = b"MZ".to_vec();
+        pe.resize(0x80, 0);
+        pe[0x18] = 0x40;
+        pe[0x3c] = 0x40;
+        pe[0x40..0x44].copy_from_slice(b"PE\0\0");
+        pe[0x58..0x5a].copy_from_slice(&[0x0b, 0x02]);
+        assert_eq!(run(Flags::NONE, false, &pe).unwrap(), "PE32+ executable");
+
+        assert_eq!(run(Flags::NONE, false, b"\n\n<HTML><body>").unwrap(), "HTML document text");
+        assert_eq!(
+            run(Flags::NONE, false, b"#!/usr/bin/env python3\n").unwrap(),
+            "Python script text executable"
+        );
+        assert_eq!(run(Flags::NONE, false, b"\x12\x34\x56\x78\x01").unwrap(), "tagged one");
+        assert_eq!(run(Flags::NONE, false, b"\x12\x34\x56\x78\x02").unwrap(), "tagged other");
+        assert_eq!(run(Flags::NONE, false, b"nothing"), None);
+        // the text tests are not tried on binary data
+        assert_eq!(run(Flags::NONE, false, b"\0\0<html>"), None);
+    }
+
+    #[test]
+    fn test_mime() {
+        assert_eq!(run(Flags::MIME_TYPE, false, &elf(false)).unwrap(), "application/x-sharedlib");
+        assert_eq!(run(Flags::MIME_TYPE, true, &elf(true)).unwrap(), "application/x-pie-executable");
+        assert_eq!(run(Flags::MIME_TYPE, false, b"\x1f\x8b\x08\x00").unwrap(), "application/gzip");
+        // an entry without a MIME type ends the matching without any output
+        assert_eq!(run(Flags::MIME_TYPE, false, b"ABCD"), None);
+    }
+
+    #[test]
+    fn test_extension() {
+        let mut exe = elf(false);
+        exe[16] = 2;
+        // aggregated from all the matched lines, including those of the named entry
+        assert_eq!(run(Flags::EXTENSION, false, &exe).unwrap(), "elf/bin");
+        assert_eq!(run(Flags::EXTENSION, false, &elf(false)).unwrap(), "elf");
+        assert_eq!(run(Flags::EXTENSION, false, b"ABCD"), None);
+    }
+
+    #[test]
+    fn test_apple() {
+        assert_eq!(run(Flags::APPLE, false, b"\x1f\x8b\x08\x00").unwrap(), "????GZIP");
+        assert_eq!(run(Flags::APPLE, false, &elf(false)), None);
+    }
+
+    #[test]
+    fn test_keep_going() {
+        assert_eq!(run(Flags::NONE, false, b"ABCD").unwrap(), "ABC ABC");
+        assert_eq!(run(Flags::CONTINUE, false, b"ABCD").unwrap(), "ABC ABC\n- AB");
+
+        let mlist = MagicList::load_str(MAGIC);
+        let param = MagicParam::default();
+        let mut soft = SoftMagic::new(&mlist, &param, Flags::CONTINUE, false);
+        soft.run(b"\x1f\x8b\x08\x00AB", MagicFlags::BINTEST, false).unwrap();
+        assert_eq!(soft.into_matches(), vec![
+            Match {
+                desc: "gzip compressed data, original size modulo 2^32 1111556104".to_string(),
+                mime: Some("application/gzip".to_string()),
+                exts: vec![],
+                strength: 50,
+            },
+        ]);
+
+        let mut soft = SoftMagic::new(&mlist, &param, Flags::CONTINUE, false);
+        soft.run(b"ABCD", MagicFlags::BINTEST, true).unwrap();
+        let matches: Vec<(String, i64)> = soft.into_matches().into_iter()
+            .map(|m| (m.desc, m.strength))
+            .collect();
+        assert_eq!(matches, vec![("ABC ABC".to_string(), 60), ("AB".to_string(), 50)]);
+    }
+
+    #[test]
+    fn test_trace() {
+        let mlist = MagicList::load_str(MAGIC);
+        let param = MagicParam::default();
+        let mut soft = SoftMagic::new(&mlist, &param, Flags::NONE, false);
+        soft.enable_trace();
+        assert!(soft.run(&elf(false), MagicFlags::BINTEST, false).unwrap());
+        let trace = soft.into_trace();
+        let start = trace.iter().position(|line| line.rule.starts_with("0 string \\x7fELF")).unwrap();
+        let lines: Vec<(&str, usize, bool)> = trace[start..].iter()
+            .map(|line| (line.rule.as_str(), line.depth, line.matched))
+            .collect();
+        assert_eq!(lines, [
+            ("0 string \\x7fELF ELF", 0, true),
+            (">4 byte 2 64-bit", 1, true),
+            (">5 byte 1 LSB", 1, true),
+            (">>0 use elf-le", 2, true),
+            ("0 name elf-le", 3, true),
+            (">16 leshort 2 executable,", 4, false),
+            (">16 leshort 3 ${x?pie executable:shared object},", 4, true),
+            (">18 leshort 62 x86-64", 4, true),
+            (">5 byte 2 MSB", 1, false),
+        ]);
+        let elf = &trace[start];
+        assert_eq!((elf.offset, elf.raw.as_slice(), elf.value.as_deref()), (Some(0), &b"\x7fELF"[..], Some("\"\\177ELF\"")));
+        assert_eq!(trace[start + 7].value.as_deref(), Some("0x3e"));
+        assert_eq!(trace[start + 7].raw, [62, 0]);
+        assert!(elf.path.ends_with("test.magic"));
+        assert_eq!(elf.line_no, 7);
+
+        // a negative offset out of the buffer
+        let mut soft = SoftMagic::new(&mlist, &param, Flags::NONE, false);
+        soft.enable_trace();
+        soft.run(b"\x1f\x8b\x08", MagicFlags::BINTEST, false).unwrap();
+        let trace = soft.into_trace();
+        let size = trace.iter().find(|line| line.rule.starts_with(">-4")).unwrap();
+        assert_eq!((size.offset, size.matched), (None, false));
+
+        // only the bytes of a `search` match, not the rest of the buffer
+        let mut soft = SoftMagic::new(&mlist, &param, Flags::NONE, false);
+        soft.enable_trace();
+        assert!(soft.run(b"xx<HTML><body>text</body>", MagicFlags::TEXTTEST, true).unwrap());
+        let trace = soft.into_trace();
+        let html = trace.iter().find(|line| line.rule.starts_with("0 search/64")).unwrap();
+        assert_eq!((html.offset, html.raw.as_slice(), html.matched), (Some(0), &b"<HTML"[..], true));
+        assert_eq!(html.value.as_deref(), Some("\"<HTML\""));
+    }
+
+    #[test]
+    fn test_strncmp() {
+        assert_eq!(strncmp(b"abc", b"abcd", 4, StrModifier::empty()), 0);
+        assert!(strncmp(b"abd", b"abcd", 4, StrModifier::empty()) < 0);
+        assert_eq!(strncmp(b"abc", b"ABC", 3, StrModifier::IGNORE_LOWERCASE), 0);
+        assert_eq!(strncmp(b"a b", b"a   b", 5, StrModifier::COMPACT_WHITESPACE), 0);
+        assert_eq!(strncmp(b"a b", b"ab", 2, StrModifier::COMPACT_OPTIONAL_WHITESPACE), 0);
+        assert_ne!(strncmp(b"abc", b"abcd", 4, StrModifier::FULL_WORD), 0);
+        assert_eq!(strncmp(b"abc", b"abc d", 5, StrModifier::FULL_WORD), 0);
+    }
+}
@@ -0,0 +1,24 @@
+use std::fmt;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+
+/// Where a line is in the magic sources, for pointing out the rule responsible for a result
+/// or an error.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SourceLoc {
+    pub(crate) path: PathBuf,
+    /// 1-based line number
+    pub(crate) line_no: usize,
+    /// The bytes of the line in the file, excluding the line break
+    pub(crate) span: Range<usize>,
+}
+
+impl fmt::Display for SourceLoc {
+    /// Write as `path:line`, which most editors and terminals can jump to.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.path.display(), self.line_no)
+    }
+}
@@ -6,7 +6,7 @@ use regex::Regex;
 pub(crate) fn ensure_goodchars(s: &str, goodchars: &str) -> Option<String> {
     Some(
         Regex::new(
-            format!(r"^[0-9A-Za-z{}]*$", goodchars).as_str()
+            format!(r"^[0-9A-Za-z{}]*$", regex::escape(goodchars)).as_str()
         ).unwrap()
             .captures(s)?
             .get(0)?
@@ -15,6 +15,145 @@ pub(crate) fn ensure_goodchars(s: &str, goodchars: &str) -> Option<String> {
     )
 }
 
+/// Parse an integer at the beginning of `s` in the way of `strtoll(s, &end, 0)`.
+///
+/// The integer can be decimal, hexadecimal with a `0x` prefix or octal with a `0` prefix,
+/// optionally signed. Return the integer in two's complement together with the rest of `s`,
+/// or `None` if `s` does not start with an integer.
+pub(crate) fn parse_int_prefix(s: &str) -> Option<(i64, &str)> {
+    let (neg, s) = match s.as_bytes().first()? {
+        b'-' => (true, &s[1..]),
+        b'+' => (false, &s[1..]),
+        _ => (false, s),
+    };
+    let (radix, digits) =
+        if (s.starts_with("0x") || s.starts_with("0X")) &&
+            s[2..].starts_with(|c: char| c.is_ascii_hexdigit()) {
+            (16, &s[2..])
+        } else if s.starts_with('0') {
+            (8, s)
+        } else {
+            (10, s)
+        };
+    let end = digits.find(|c: char| !c.is_digit(radix)).unwrap_or(digits.len());
+    if end == 0 {
+        return None;
+    }
+    // wrap around like `strtoull' does for values which do not fit in 64 bits
+    let val = digits[..end].chars()
+        .fold(0u64, |acc, c| {
+            acc.wrapping_mul(radix as u64).wrapping_add(c.to_digit(radix).unwrap() as u64)
+        }) as i64;
+    Some((if neg { val.wrapping_neg() } else { val }, &digits[end..]))
+}
+
+/// Unescape a string written in magic(5) in the way of libmagic's `getstr`.
+///
+/// Besides the C escapes (`\n`, `\x7f`, `\177`, ...), a backslash can escape a whitespace or
+/// any other char, which is then kept as it is.
+pub(crate) fn unescape(s: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(s.len());
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        i += 1;
+        if c != b'\\' || i == bytes.len() {
+            out.push(c);
+            continue;
+        }
+
+        let c = bytes[i];
+        i += 1;
+        match c {
+            b'a' => out.push(0x07),
+            b'b' => out.push(0x08),
+            b'f' => out.push(0x0c),
+            b'n' => out.push(b'\n'),
+            b'r' => out.push(b'\r'),
+            b't' => out.push(b'\t'),
+            b'v' => out.push(0x0b),
+            b'0'..=b'7' => {
+                let mut val = (c - b'0') as u32;
+                let mut n = 1;
+                while n < 3 && i < bytes.len() && (b'0'..=b'7').contains(&bytes[i]) {
+                    val = val * 8 + (bytes[i] - b'0') as u32;
+                    i += 1;
+                    n += 1;
+                }
+                out.push(val as u8);
+            }
+            b'x' => {
+                let mut val = 0u32;
+                let mut n = 0;
+                while n < 2 && i < bytes.len() && bytes[i].is_ascii_hexdigit() {
+                    val = val * 16 + (bytes[i] as char).to_digit(16).unwrap();
+                    i += 1;
+                    n += 1;
+                }
+                if n == 0 {
+                    out.push(b'x');
+                } else {
+                    out.push(val as u8);
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Escape a string to be written in magic(5), so that `unescape` gives it back.
+pub(crate) fn escape(s: &[u8]) -> String {
+    let mut out = String::with_capacity(s.len());
+    for (i, &c) in s.iter().enumerate() {
+        match c {
+            b'\\' => out.push_str("\\\\"),
+            b' ' => out.push_str("\\ "),
+            b'\t' => out.push_str("\\t"),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            0 => {
+                // a following octal digit would be taken as a part of the escape
+                match s.get(i + 1) {
+                    Some(b'0'..=b'7') => out.push_str("\\000"),
+                    _ => out.push_str("\\0"),
+                }
+            }
+            0x21..=0x7e => out.push(c as char),
+            _ => out.push_str(&format!("\\x{:02x}", c)),
+        }
+    }
+    out
+}
+
+/// Return the printf format specs in `s`, such as `%d` or `%-08.3lx`, where `%%` is skipped.
+///
+/// An incomplete spec at the end of `s` is returned as it is.
+pub(crate) fn format_specs(s: &str) -> Vec<&str> {
+    let mut specs = vec![];
+    let mut iter = s.char_indices().peekable();
+    while let Some((start, c)) = iter.next() {
+        if c != '%' {
+            continue;
+        }
+        if let Some((_, '%')) = iter.peek() {
+            iter.next();
+            continue;
+        }
+        let mut end = s.len();
+        for (i, c) in iter.by_ref() {
+            if c.is_ascii_alphabetic() && !"hlLqjzt".contains(c) {
+                end = i + 1;
+                break;
+            }
+        }
+        specs.push(&s[start..end]);
+    }
+    specs
+}
+
+
 #[test]
 fn test_parse_extra() {
     let raw = "hello+-0";
@@ -25,3 +164,52 @@ fn test_parse_extra() {
         raw
     )
 }
+
+#[test]
+fn test_parse_int_prefix() {
+    let testcases = vec![
+        ("0x1F.l", Some((0x1f, ".l"))),
+        ("017", Some((0o17, ""))),
+        ("0", Some((0, ""))),
+        ("-12)", Some((-12, ")"))),
+        ("0xffffffffffffffff", Some((-1, ""))),
+        ("x", None),
+    ];
+    for (s, expect) in testcases {
+        assert_eq!(parse_int_prefix(s), expect);
+    }
+}
+
+#[test]
+fn test_unescape() {
+    let testcases: Vec<(&str, &[u8])> = vec![
+        (r"\x7fELF", b"\x7fELF"),
+        (r"MZ\0\0", b"MZ\0\0"),
+        (r"\177\1a", b"\x7f\x01a"),
+        (r"hello\ world\\", b"hello world\\"),
+        (r"\^#!\t", b"^#!\t"),
+    ];
+    for (s, expect) in testcases {
+        assert_eq!(unescape(s), expect);
+    }
+}
+
+#[test]
+fn test_escape() {
+    let testcases: Vec<&[u8]> = vec![
+        b"\x7fELF\0\x01",
+        b"MZ\0\0",
+        b"\0\x31 \\\t",
+        b"\xff\xfe<html>",
+    ];
+    for s in testcases {
+        assert_eq!(unescape(&escape(s)), s);
+    }
+    assert_eq!(escape(b"\x7fELF\0\x01 a"), r"\x7fELF\0\x01\ a");
+}
+
+#[test]
+fn test_format_specs() {
+    assert_eq!(format_specs("version %d.%-02lld, 100%% %s"), vec!["%d", "%-02lld", "%s"]);
+    assert_eq!(format_specs("${x?pie:so}, size %"), vec!["%"]);
+}